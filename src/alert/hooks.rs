@@ -0,0 +1,314 @@
+
+use {
+  std::{
+    sync::mpsc::{channel, Sender},
+    thread,
+    time::Duration,
+  },
+  crate::{
+    alert::{fmt_value, Transition},
+    config::{Config, Section},
+    http::{self, Url},
+    util::HashMap,
+    widget::command::call_cmd_env,
+  },
+};
+
+/// Why a delivery failed, and whether trying again could help
+#[derive(Debug, Clone, PartialEq)]
+pub enum PostError {
+  /// Couldn't connect, timed out, or the server had a 5xx moment
+  Retry(String),
+  /// The server turned the request down, sending it again won't change that
+  Rejected(String),
+}
+
+/// POSTs `body` as JSON, returning the status code on a 2xx answer
+pub fn post_json(url: &Url, body: &str, timeout: Duration) -> Result<u16, PostError> {
+  let resp = http::request("POST", url, Some(("application/json", body)), timeout)
+    .map_err(|e| PostError::Retry(e.to_string()))?;
+  match resp.status {
+    200..=299 => Ok(resp.status),
+    500..=599 | 408 | 429 => Err(PostError::Retry(resp.status_line)),
+    _ => Err(PostError::Rejected(resp.status_line)),
+  }
+}
+
+/// The details of a transition as `ALERT_*` variables for a hook command
+pub fn env_vars(t: &Transition) -> Vec<(String, String)> {
+  vec![
+    ("ALERT_NAME".to_owned(), t.name.clone()),
+    ("ALERT_STATE".to_owned(), state_name(t).to_owned()),
+    ("ALERT_SEVERITY".to_owned(), t.severity.name().to_lowercase()),
+    ("ALERT_VALUE".to_owned(), fmt_value(t.value)),
+    ("ALERT_THRESHOLD".to_owned(), fmt_value(t.threshold)),
+    ("ALERT_SUMMARY".to_owned(), t.summary.clone()),
+    ("ALERT_TIME".to_owned(), format!("{}", t.ts as u64)),
+  ]
+}
+
+/// The JSON body sent to a webhook
+pub fn payload(t: &Transition, suppressed: usize) -> String {
+  let value = if t.value.is_finite() { serde_json::json!(t.value) } else { serde_json::Value::Null };
+  serde_json::json!({
+    "alert": t.name,
+    "state": state_name(t),
+    "severity": t.severity.name().to_lowercase(),
+    "value": value,
+    "threshold": t.threshold,
+    "summary": t.summary,
+    "time": t.ts as u64,
+    "suppressed": suppressed,
+  }).to_string()
+}
+
+fn state_name(t: &Transition) -> &'static str {
+  if t.firing { "firing" } else { "resolved" }
+}
+
+/// What to do when one alert starts or stops firing. Set in the alert's own
+/// section, next to the rule.
+///
+///   [alert mem_high]
+///   command = notify-send "$ALERT_NAME $ALERT_STATE" "$ALERT_SUMMARY"
+///   webhook = http://127.0.0.1:9000/alerts
+///   min_interval = 300
+///   retries = 3
+pub struct Hook {
+  pub command: Option<String>,
+  pub webhook: Option<Url>,
+  /// Seconds after a notification before the alert can notify again
+  pub min_interval: f64,
+  pub retries: u32,
+  /// Seconds before the first retry, doubled each time after
+  pub retry_delay: f64,
+  pub timeout: Duration,
+  last_sent: Option<f64>,
+  /// Set when a firing notification was held back, so its resolve is too
+  held_back: bool,
+  suppressed: usize,
+  tx: Sender<Notification>,
+}
+impl Hook {
+  pub fn from_section(section: &Section) -> Option<Self> {
+    let command = section.get("command").map(|c| c.to_owned());
+    let webhook = match section.get("webhook").map(Url::parse) {
+      Some(Ok(url)) => Some(url),
+      Some(Err(e)) => { println!("Config: [alert {}] webhook: {}", section.name, e); None }
+      None => None,
+    };
+    if command.is_none() && webhook.is_none() { return None }
+    let retries = section.get_or("retries", 3);
+    let retry_delay = section.get_or("retry_delay", 5.0);
+    let timeout = Duration::from_secs_f64(section.get_or("timeout", 10.0_f64).max(0.1));
+    let (tx, rx) = channel();
+    let (cmd, url) = (command.clone(), webhook.clone());
+    thread::spawn(move || {
+      for n in rx { deliver(&n, &cmd, &url, retries, retry_delay, timeout) }
+    });
+    Some(Self {
+      command,
+      webhook,
+      min_interval: section.get_or("min_interval", 60.0),
+      retries,
+      retry_delay,
+      timeout,
+      last_sent: None,
+      held_back: false,
+      suppressed: 0,
+      tx,
+    })
+  }
+  /// Rate limiting: a firing alert notifies at most once per `min_interval`
+  /// and its resolve always follows it, while one that was held back stays
+  /// quiet when it resolves. Returns how many were held back before this one.
+  fn should_send(&mut self, t: &Transition) -> Option<usize> {
+    if !t.firing {
+      if self.held_back { self.held_back = false; return None }
+      return Some(0)
+    }
+    if let Some(last) = self.last_sent {
+      if t.ts - last < self.min_interval {
+        self.held_back = true;
+        self.suppressed += 1;
+        return None
+      }
+    }
+    self.last_sent = Some(t.ts);
+    self.held_back = false;
+    Some(std::mem::replace(&mut self.suppressed, 0))
+  }
+  /// Hands the notification to the hook's thread, so a slow endpoint never
+  /// holds up drawing and a resolve can't overtake the firing it follows.
+  fn send(&self, t: &Transition, suppressed: usize) {
+    let mut env = env_vars(t);
+    env.push(("ALERT_SUPPRESSED".to_owned(), suppressed.to_string()));
+    let _ = self.tx.send(Notification { name: t.name.clone(), env, body: payload(t, suppressed) });
+  }
+}
+
+struct Notification {
+  name: String,
+  env: Vec<(String, String)>,
+  body: String,
+}
+
+fn deliver(n: &Notification, command: &Option<String>, webhook: &Option<Url>,
+           retries: u32, retry_delay: f64, timeout: Duration) {
+  if let Some(ref cmd) = command {
+    match call_cmd_env(cmd, &n.env, timeout) {
+      Ok(out) if out.failed() => println!("Alert {}: hook command failed: {}", n.name, out.stderr.trim()),
+      Ok(_) => (),
+      Err(e) => println!("Alert {}: can't run hook command: {}", n.name, e),
+    }
+  }
+  if let Some(ref url) = webhook {
+    let mut delay = retry_delay;
+    for attempt in 0..=retries {
+      match post_json(url, &n.body, timeout) {
+        Ok(_) => return,
+        Err(PostError::Rejected(e)) => {
+          println!("Alert {}: webhook rejected: {}", n.name, e);
+          return
+        }
+        Err(PostError::Retry(e)) if attempt < retries => {
+          println!("Alert {}: webhook failed ({}), retrying in {}s", n.name, e, delay);
+          thread::sleep(Duration::from_secs_f64(delay));
+          delay *= 2.0;
+        }
+        Err(PostError::Retry(e)) => println!("Alert {}: webhook failed, giving up: {}", n.name, e),
+      }
+    }
+  }
+}
+
+/// The hooks of every alert that has one
+pub struct Hooks {
+  pub hooks: HashMap<String, Hook>,
+  /// Off for made up or replayed metrics, so they can't page anyone
+  pub enabled: bool,
+}
+impl Default for Hooks {
+  fn default() -> Self {
+    Self { hooks: HashMap::new(), enabled: true }
+  }
+}
+impl Hooks {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn from_config(config: &Config) -> Self {
+    let mut out = Self::new();
+    for section in config.sections("alert") {
+      if let Some(hook) = Hook::from_section(section) {
+        out.hooks.insert(section.name.clone(), hook);
+      }
+    }
+    out
+  }
+  pub fn notify(&mut self, transitions: &[Transition]) {
+    if !self.enabled { return }
+    for t in transitions {
+      if let Some(hook) = self.hooks.get_mut(&t.name) {
+        if let Some(suppressed) = hook.should_send(t) { hook.send(t, suppressed) }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{alert::Severity, http::tests::{answer, serve}},
+  };
+
+  fn transition(firing: bool, ts: f64) -> Transition {
+    Transition {
+      name: "mem_high".to_owned(),
+      severity: Severity::Critical,
+      firing,
+      value: 93.5,
+      threshold: 90.0,
+      summary: "mem 93.5 > 90".to_owned(),
+      ts,
+    }
+  }
+
+  fn notification() -> Notification {
+    let t = transition(true, 1_700_000_000.0);
+    Notification { name: t.name.clone(), env: env_vars(&t), body: payload(&t, 2) }
+  }
+
+  #[test]
+  fn posts_the_payload() {
+    let (mut url, requests) = serve(vec![answer("200 OK", "")]);
+    url.path = "/alerts".to_owned();
+    deliver(&notification(), &None, &Some(url), 3, 0.01, Duration::from_secs(5));
+    let req = requests.recv().unwrap();
+    assert!(req.starts_with("POST /alerts HTTP/1.0\r\n"));
+    assert!(req.contains("\r\nContent-Type: application/json\r\n"));
+    let body: serde_json::Value = serde_json::from_str(&req[req.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+    assert_eq!(body, serde_json::json!({
+      "alert": "mem_high",
+      "state": "firing",
+      "severity": "crit",
+      "value": 93.5,
+      "threshold": 90.0,
+      "summary": "mem 93.5 > 90",
+      "time": 1_700_000_000u64,
+      "suppressed": 2,
+    }));
+    assert!(requests.try_recv().is_err());
+  }
+
+  #[test]
+  fn retries_server_errors() {
+    let (url, requests) = serve(vec![
+      answer("503 Service Unavailable", ""),
+      answer("500 Internal Server Error", ""),
+      answer("202 Accepted", ""),
+      answer("200 OK", ""),
+    ]);
+    deliver(&notification(), &None, &Some(url), 3, 0.01, Duration::from_secs(5));
+    // Two failures then done, the fourth answer is never asked for
+    assert_eq!(requests.try_iter().count(), 3);
+  }
+
+  #[test]
+  fn gives_up_after_retries() {
+    let (url, requests) = serve(vec![answer("503 Service Unavailable", ""); 5]);
+    deliver(&notification(), &None, &Some(url), 2, 0.01, Duration::from_secs(5));
+    assert_eq!(requests.try_iter().count(), 3);
+  }
+
+  #[test]
+  fn rejections_are_not_retried() {
+    let (url, requests) = serve(vec![answer("400 Bad Request", ""), answer("200 OK", "")]);
+    assert_eq!(post_json(&url, "{}", Duration::from_secs(5)), Err(PostError::Rejected("HTTP/1.1 400 Bad Request".to_owned())));
+    assert_eq!(requests.try_iter().count(), 1);
+    let (url, _requests) = serve(vec![answer("429 Too Many Requests", "")]);
+    assert_eq!(post_json(&url, "{}", Duration::from_secs(5)), Err(PostError::Retry("HTTP/1.1 429 Too Many Requests".to_owned())));
+  }
+
+  #[test]
+  fn unreachable_is_retried() {
+    let (url, requests) = serve(Vec::new());
+    let _ = requests.recv();
+    assert!(matches!(post_json(&url, "{}", Duration::from_secs(1)), Err(PostError::Retry(_))));
+  }
+
+  #[test]
+  fn resolve_follows_only_sent_firings() {
+    let section = {
+      let config = Config::parse("[alert mem_high]\ncommand = true\nmin_interval = 60\n");
+      config.sections("alert")[0].clone()
+    };
+    let mut hook = Hook::from_section(&section).unwrap();
+    assert_eq!(hook.should_send(&transition(true, 100.0)), Some(0));
+    assert_eq!(hook.should_send(&transition(false, 110.0)), Some(0));
+    assert_eq!(hook.should_send(&transition(true, 120.0)), None);
+    assert_eq!(hook.should_send(&transition(false, 130.0)), None);
+    assert_eq!(hook.should_send(&transition(true, 170.0)), Some(1));
+  }
+}
@@ -0,0 +1,412 @@
+pub mod hooks;
+
+pub use {
+  crate::{
+    alert::{
+      hooks::Hooks,
+    },
+  },
+};
+
+use {
+  std::collections::BTreeMap,
+  crate::{
+    config::{Config, Section},
+    gamemgr::GameMgr,
+    metrics::{expr::{self, Expr}, Store},
+    text::TextMgr,
+    util::HashMap,
+    widget::{COLOUR_ERROR, COLOUR_NORMAL, COLOUR_WARN},
+  },
+};
+
+pub const ALERT_LABEL: &str = "Alerts";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+  Info,
+  Warning,
+  Critical,
+}
+impl Severity {
+  pub fn parse(s: &str) -> Option<Self> {
+    match s.to_lowercase().as_str() {
+      "info" => Some(Severity::Info),
+      "warn" | "warning" => Some(Severity::Warning),
+      "crit" | "critical" => Some(Severity::Critical),
+      _ => None,
+    }
+  }
+  pub fn name(&self) -> &'static str {
+    match self {
+      Severity::Info => "INFO",
+      Severity::Warning => "WARN",
+      Severity::Critical => "CRIT",
+    }
+  }
+  pub fn colour(&self) -> (f32, f32, f32) {
+    match self {
+      Severity::Info => (0.1, 0.3, 0.8),
+      Severity::Warning => COLOUR_WARN,
+      Severity::Critical => COLOUR_ERROR,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cmp {
+  Gt,
+  Ge,
+  Lt,
+  Le,
+  Eq,
+  Ne,
+}
+impl Cmp {
+  pub fn parse(s: &str) -> Option<Self> {
+    match s {
+      ">" => Some(Cmp::Gt),
+      ">=" => Some(Cmp::Ge),
+      "<" => Some(Cmp::Lt),
+      "<=" => Some(Cmp::Le),
+      "==" | "=" => Some(Cmp::Eq),
+      "!=" => Some(Cmp::Ne),
+      _ => None,
+    }
+  }
+  pub fn symbol(&self) -> &'static str {
+    match self {
+      Cmp::Gt => ">",
+      Cmp::Ge => ">=",
+      Cmp::Lt => "<",
+      Cmp::Le => "<=",
+      Cmp::Eq => "==",
+      Cmp::Ne => "!=",
+    }
+  }
+  pub fn test(&self, value: f64, threshold: f64) -> bool {
+    match self {
+      Cmp::Gt => value > threshold,
+      Cmp::Ge => value >= threshold,
+      Cmp::Lt => value < threshold,
+      Cmp::Le => value <= threshold,
+      Cmp::Eq => value == threshold,
+      Cmp::Ne => value != threshold,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+  Inactive,
+  /// Over the threshold since the given time, but not for long enough yet
+  Pending(f64),
+  /// Firing, over the threshold since the given time
+  Firing(f64),
+}
+
+/// A rule changing state, handed to anything that wants to react to it
+#[derive(Debug, Clone)]
+pub struct Transition {
+  pub name: String,
+  pub severity: Severity,
+  pub firing: bool,
+  pub value: f64,
+  pub threshold: f64,
+  pub summary: String,
+  pub ts: f64,
+}
+
+/// One threshold to watch. `metric` can be any expression the derived
+/// metrics understand.
+///
+///   [alert mem_high]
+///   metric = mem.used / mem.total * 100
+///   op = >
+///   threshold = 90
+///   for = 30
+///   severity = critical
+///   label = CPU RAM HDD
+pub struct Rule {
+  pub name: String,
+  pub source: String,
+  pub metric: Expr,
+  pub cmp: Cmp,
+  pub threshold: f64,
+  /// Seconds the condition has to hold before the alert fires
+  pub duration: f64,
+  pub severity: Severity,
+  /// Labels to colour while the alert is firing
+  pub labels: Vec<String>,
+  pub state: State,
+  pub value: Option<f64>,
+}
+impl Rule {
+  pub fn from_section(section: &Section) -> Result<Self, String> {
+    let source = section.get("metric").ok_or("no metric")?.to_owned();
+    let metric = expr::parse(&source)?;
+    let op = section.get("op").unwrap_or(">");
+    let cmp = Cmp::parse(op).ok_or_else(|| format!("bad op {}", op))?;
+    let threshold = section.get("threshold").ok_or("no threshold")?
+      .parse().map_err(|_| "bad threshold".to_owned())?;
+    let severity = section.get("severity").unwrap_or("warning");
+    let severity = Severity::parse(severity).ok_or_else(|| format!("bad severity {}", severity))?;
+    let labels = section.get("label").map(|l| {
+      l.split(',').map(|l| l.trim().to_owned()).filter(|l| !l.is_empty()).collect()
+    }).unwrap_or_default();
+    Ok(Self {
+      name: section.name.clone(),
+      source,
+      metric,
+      cmp,
+      threshold,
+      duration: section.get_or("for", 0.0),
+      severity,
+      labels,
+      state: State::Inactive,
+      value: None,
+    })
+  }
+  pub fn summary(&self) -> String {
+    let value = match self.value { Some(v) => fmt_value(v), None => "?".to_owned() };
+    format!("{} {} {} (now {})", self.source, self.cmp.symbol(), fmt_value(self.threshold), value)
+  }
+  fn transition(&self, firing: bool, ts: f64) -> Transition {
+    Transition {
+      name: self.name.clone(),
+      severity: self.severity,
+      firing,
+      value: self.value.unwrap_or(f64::NAN),
+      threshold: self.threshold,
+      summary: self.summary(),
+      ts,
+    }
+  }
+  /// Moves the rule along with the latest value, returning a transition if
+  /// it started or stopped firing.
+  pub fn evaluate(&mut self, store: &Store, now: f64) -> Option<Transition> {
+    // A metric that's gone missing counts as the condition not holding
+    self.value = self.metric.eval(store).ok();
+    let holds = match self.value {
+      Some(value) => self.cmp.test(value, self.threshold),
+      None => false,
+    };
+    let (state, out) = match (self.state, holds) {
+      (State::Inactive, true) if self.duration <= 0.0 => (State::Firing(now), Some(true)),
+      (State::Inactive, true) => (State::Pending(now), None),
+      (State::Pending(since), true) if now - since >= self.duration => (State::Firing(since), Some(true)),
+      (State::Firing(_), false) => (State::Inactive, Some(false)),
+      (_, false) => (State::Inactive, None),
+      (state, true) => (state, None),
+    };
+    self.state = state;
+    out.map(|firing| self.transition(firing, now))
+  }
+}
+
+/// Every alert rule in the config, and what they've done to the screen.
+/// Alerts have the final say on a label's colour while they fire; widgets
+/// ask `colour` before colouring their rows.
+#[derive(Default)]
+pub struct Alerts {
+  pub rules: Vec<Rule>,
+  /// Labels currently coloured by an alert, by the worst severity on them
+  touched: HashMap<String, Severity>,
+  hidden: bool,
+}
+impl Alerts {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn from_config(config: &Config) -> Self {
+    let mut out = Self::new();
+    for section in config.sections("alert") {
+      match Rule::from_section(section) {
+        Ok(rule) => out.rules.push(rule),
+        Err(e) => println!("Config: [alert {}] {}", section.name, e),
+      }
+    }
+    out
+  }
+  pub fn evaluate(&mut self, store: &Store, now: f64) -> Vec<Transition> {
+    let mut out = Vec::new();
+    for rule in &mut self.rules {
+      if let Some(t) = rule.evaluate(store, now) {
+        println!("Alert {} {}: {}", rule.name, if t.firing { "firing" } else { "resolved" }, t.summary);
+        out.push(t);
+      }
+    }
+    out
+  }
+  /// Firing alerts by name, with the name of their severity
+  pub fn firing(&self) -> BTreeMap<String, String> {
+    self.rules.iter()
+      .filter(|r| matches!(r.state, State::Firing(_)))
+      .map(|r| (r.name.clone(), r.severity.name().to_owned()))
+      .collect()
+  }
+  /// The colour a firing alert has given `label`, if any
+  pub fn colour(&self, label: &str) -> Option<(f32, f32, f32)> {
+    self.touched.get(label).map(|sev| sev.colour())
+  }
+  /// Hides the alert list while another page is up
+  pub fn set_hidden(&mut self, hidden: bool, textmgr: &mut TextMgr, mgr: GameMgr, now: f64) {
+    self.hidden = hidden;
+    if hidden { textmgr.disable_label(ALERT_LABEL) } else { self.show_list(textmgr, mgr, now) }
+  }
+  /// Labels with a firing alert on them, by the worst severity of those
+  fn worst(&self) -> HashMap<String, Severity> {
+    let mut worst: HashMap<String, Severity> = HashMap::new();
+    for rule in &self.rules {
+      if let State::Firing(_) = rule.state {
+        for label in &rule.labels {
+          let sev = worst.entry(label.clone()).or_insert(rule.severity);
+          if rule.severity > *sev { *sev = rule.severity; }
+        }
+      }
+    }
+    worst
+  }
+  /// The on screen alert list, worst first. Empty if nothing's going on.
+  pub fn text(&self, now: f64) -> String {
+    let mut active: Vec<&Rule> = self.rules.iter().filter(|r| r.state != State::Inactive).collect();
+    active.sort_by_key(|r| (std::cmp::Reverse(matches!(r.state, State::Firing(_))), std::cmp::Reverse(r.severity)));
+    if active.is_empty() { return String::new() }
+    let mut rows = vec!["Alerts".to_owned()];
+    for rule in active {
+      let (what, since) = match rule.state {
+        State::Firing(since) => (rule.severity.name(), since),
+        State::Pending(since) => ("pending", since),
+        State::Inactive => continue,
+      };
+      rows.push(format!("[{}] {}: {} for {}", what, rule.name, rule.summary(), fmt_secs(now - since)));
+    }
+    rows.join("\n")
+  }
+  /// Colours every label with a firing alert by its worst severity, pulsing
+  /// the border on critical ones, and puts labels back once they're clear.
+  /// Returns the labels that cleared, for widgets to put their own colour
+  /// back on. Also refreshes the alert list.
+  pub fn show(&mut self, textmgr: &mut TextMgr, mgr: GameMgr, now: f64) -> Vec<String> {
+    let worst = self.worst();
+    let cleared: Vec<String> = self.touched.keys().filter(|l| !worst.contains_key(*l)).cloned().collect();
+    for label in &cleared {
+      if let Some(gtext) = textmgr.texts.get_mut(label) {
+        let (r, g, b) = COLOUR_NORMAL;
+        gtext.set_colour(r, g, b);
+        gtext.effect.reset_anim();
+      }
+    }
+    for (label, sev) in &worst {
+      if let Some(gtext) = textmgr.texts.get_mut(label) {
+        let (r, g, b) = sev.colour();
+        gtext.set_colour(r, g, b);
+        if *sev == Severity::Critical { gtext.effect.pulse(1.0) } else { gtext.effect.reset_anim() }
+      }
+    }
+    self.touched = worst;
+    self.show_list(textmgr, mgr, now);
+    cleared
+  }
+  fn show_list(&mut self, textmgr: &mut TextMgr, mgr: GameMgr, now: f64) {
+    let text = self.text(now);
+    if text.is_empty() {
+      textmgr.disable_label(ALERT_LABEL);
+    } else {
+      textmgr.update_text(mgr.clone(), ALERT_LABEL, &text);
+      if !self.hidden { textmgr.enable_label(mgr, ALERT_LABEL); }
+    }
+  }
+}
+
+pub fn fmt_value(v: f64) -> String {
+  if v.abs() >= 1e6 || (v != 0.0 && v.abs() < 0.01) { format!("{:.3e}", v) }
+  else if v.fract() == 0.0 { format!("{}", v) }
+  else { format!("{:.2}", v) }
+}
+
+pub fn fmt_secs(secs: f64) -> String {
+  let secs = secs.max(0.0) as u64;
+  if secs < 60 { format!("{}s", secs) }
+  else if secs < 3600 { format!("{}m{:02}s", secs / 60, secs % 60) }
+  else { format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60) }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RULES: &str = "
+[alert load_high]
+metric = load.1
+threshold = 4
+for = 2
+label = CPU RAM HDD, Sessions
+
+[alert load_very_high]
+metric = load.1
+threshold = 8
+severity = critical
+label = Sessions
+";
+
+  /// Feeds `load.1` one value a second, returning the transitions as
+  /// `(name, firing, ts)`
+  fn run(alerts: &mut Alerts, store: &mut Store, values: &[(f64, f64)]) -> Vec<(String, bool, f64)> {
+    let mut out = Vec::new();
+    for (ts, value) in values {
+      store.push("load.1", *ts, *value);
+      out.extend(alerts.evaluate(store, *ts).into_iter().map(|t| (t.name, t.firing, t.ts)));
+    }
+    out
+  }
+
+  #[test]
+  fn pending_then_firing_then_resolved() {
+    let mut alerts = Alerts::from_config(&Config::parse(RULES));
+    let mut store = Store::new();
+    assert!(run(&mut alerts, &mut store, &[(0.0, 1.0), (1.0, 5.0)]).is_empty());
+    assert_eq!(alerts.rules[0].state, State::Pending(1.0));
+    assert_eq!(alerts.text(1.0), "Alerts\n[pending] load_high: load.1 > 4 (now 5) for 0s");
+    // Dipping under the threshold starts the wait over
+    run(&mut alerts, &mut store, &[(2.0, 3.0), (3.0, 5.0), (4.0, 5.0)]);
+    assert_eq!(alerts.rules[0].state, State::Pending(3.0));
+    assert_eq!(run(&mut alerts, &mut store, &[(5.0, 5.0)]), vec![("load_high".to_owned(), true, 5.0)]);
+    // Firing keeps the time it first went over
+    assert_eq!(alerts.rules[0].state, State::Firing(3.0));
+    assert_eq!(alerts.text(6.0), "Alerts\n[WARN] load_high: load.1 > 4 (now 5) for 3s");
+    assert!(run(&mut alerts, &mut store, &[(6.0, 6.0)]).is_empty());
+    assert_eq!(run(&mut alerts, &mut store, &[(7.0, 1.0)]), vec![("load_high".to_owned(), false, 7.0)]);
+    assert_eq!(alerts.rules[0].state, State::Inactive);
+    assert!(alerts.firing().is_empty());
+    assert_eq!(alerts.text(7.0), "");
+  }
+
+  #[test]
+  fn a_missing_metric_resolves() {
+    let mut alerts = Alerts::from_config(&Config::parse("[alert gone]\nmetric = swap.used\nop = >=\nthreshold = 0\n"));
+    let mut store = Store::new();
+    store.push("swap.used", 0.0, 1.0);
+    assert_eq!(alerts.evaluate(&store, 0.0).len(), 1);
+    let empty = Store::new();
+    let t = alerts.evaluate(&empty, 1.0);
+    assert_eq!((t.len(), t[0].firing, t[0].value.is_nan()), (1, false, true));
+  }
+
+  #[test]
+  fn worst_severity_colours_a_label() {
+    let mut alerts = Alerts::from_config(&Config::parse(RULES));
+    let mut store = Store::new();
+    run(&mut alerts, &mut store, &[(0.0, 9.0), (1.0, 9.0), (2.0, 9.0)]);
+    assert_eq!(alerts.firing().into_iter().collect::<Vec<_>>(), vec![
+      ("load_high".to_owned(), "WARN".to_owned()),
+      ("load_very_high".to_owned(), "CRIT".to_owned()),
+    ]);
+    let worst = alerts.worst();
+    assert_eq!((worst["CPU RAM HDD"], worst["Sessions"]), (Severity::Warning, Severity::Critical));
+    assert_eq!(alerts.colour("Sessions"), None);
+    alerts.touched = worst;
+    assert_eq!(alerts.colour("Sessions"), Some(COLOUR_ERROR));
+    assert_eq!(alerts.colour("FPS"), None);
+  }
+}
@@ -0,0 +1,140 @@
+
+use {
+  std::{
+    env,
+    path::PathBuf,
+  },
+  crate::{
+    snapshot::Format,
+  },
+};
+
+pub const USAGE: &str = "\
+Usage: raum-en-sysinfo [options]
+
+Options:
+  --config <path>           read settings from <path> instead of the default config
+  --dump-history [pattern]  print stored metric history and exit, optionally only
+                            metrics matching a pattern like 'disk.*.used'
+  --once                    collect everything once, print it and exit without
+                            opening a window
+  --format <format>         output format for --once: text, json or yaml
+  --agent [addr]            run headless, serving snapshots to viewers on addr
+                            (default from [agent] listen, or 127.0.0.1:9102)
+  --view <addr,...>         show panels for the agents at each host:port
+  --demo [seed]             show made up metrics from a seeded generator instead
+                            of this machine's, for demos and screenshots
+  --record <path>           save every sample the collectors take to <path>
+  --replay <path>           play a recording back instead of collecting; with
+                            --agent, exit once it runs out
+  --speed <x>               replay speed: 1 for as recorded, 10 for ten times
+                            faster, or max
+  --publish                 with --demo or --replay, still run alert hooks, send
+                            exports and write this host's grid tile
+  --report <path>           collect everything once and write a system report to
+                            <path>, as HTML or Markdown going by its extension
+  --diff <old> <new>        compare two snapshots saved with --once --format json
+                            and list what changed; exits 1 if anything did
+  --redact                  mask host names, addresses, users, /home paths and
+                            commands on screen and in printed output
+  --help                    show this message";
+
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+  pub config: Option<PathBuf>,
+  /// Pattern of metrics to dump, `*` if none was given
+  pub dump_history: Option<String>,
+  pub once: bool,
+  pub format: Option<Format>,
+  /// Address to listen on, empty to use the config's
+  pub agent: Option<String>,
+  /// Agents to connect to
+  pub view: Vec<String>,
+  /// Seed for made up metrics, empty to use the config's
+  pub demo: Option<String>,
+  pub record: Option<PathBuf>,
+  pub replay: Option<PathBuf>,
+  /// Replay speed, 0 for as fast as possible
+  pub speed: Option<f64>,
+  /// Let made up or replayed metrics reach hooks, exports and the grid
+  pub publish: bool,
+  /// Where to write a system report, ending in .html or .md
+  pub report: Option<PathBuf>,
+  /// Old and new snapshot files to compare
+  pub diff: Option<(PathBuf, PathBuf)>,
+  /// Mask sensitive text, as with a [redact] section
+  pub redact: bool,
+  pub help: bool,
+}
+impl Args {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn from_env() -> Result<Self, String> {
+    Self::parse(env::args().skip(1).collect())
+  }
+  pub fn parse(args: Vec<String>) -> Result<Self, String> {
+    let mut out = Self::new();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "--config" => out.config = Some(PathBuf::from(value(&arg, args.next())?)),
+        "--dump-history" => {
+          // The pattern is optional, so only take the next arg if it isn't
+          // another option.
+          let pattern = match args.peek() {
+            Some(next) if !next.starts_with("--") => args.next().unwrap(),
+            _ => "*".to_owned(),
+          };
+          out.dump_history = Some(pattern);
+        }
+        "--once" => out.once = true,
+        "--format" => {
+          let format = value(&arg, args.next())?;
+          out.format = Some(Format::parse(&format).ok_or_else(|| format!("Unknown format: {}", format))?);
+        }
+        "--agent" => {
+          let addr = match args.peek() {
+            Some(next) if !next.starts_with("--") => args.next().unwrap(),
+            _ => String::new(),
+          };
+          out.agent = Some(addr);
+        }
+        "--view" => {
+          let addrs = value(&arg, args.next())?;
+          out.view.extend(addrs.split(',').map(|a| a.trim().to_owned()).filter(|a| !a.is_empty()));
+        }
+        "--demo" => {
+          let seed = match args.peek() {
+            Some(next) if !next.starts_with("--") => args.next().unwrap(),
+            _ => String::new(),
+          };
+          out.demo = Some(seed);
+        }
+        "--record" => out.record = Some(PathBuf::from(value(&arg, args.next())?)),
+        "--replay" => out.replay = Some(PathBuf::from(value(&arg, args.next())?)),
+        "--speed" => {
+          let speed = value(&arg, args.next())?;
+          out.speed = Some(match speed.as_str() {
+            "max" => 0.0,
+            _ => speed.parse().ok().filter(|s: &f64| *s > 0.0).ok_or_else(|| format!("Bad speed: {}", speed))?,
+          });
+        }
+        "--publish" => out.publish = true,
+        "--report" => out.report = Some(PathBuf::from(value(&arg, args.next())?)),
+        "--diff" => {
+          let old = value(&arg, args.next())?;
+          out.diff = Some((PathBuf::from(old), PathBuf::from(value(&arg, args.next())?)));
+        }
+        "--redact" => out.redact = true,
+        "--help" | "-h" => out.help = true,
+        _ => return Err(format!("Unknown option: {}", arg)),
+      }
+    }
+    Ok(out)
+  }
+}
+
+fn value(arg: &str, next: Option<String>) -> Result<String, String> {
+  next.ok_or_else(|| format!("{} needs a value", arg))
+}
@@ -0,0 +1,144 @@
+
+use {
+  std::{
+    env,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+  },
+  crate::{
+    args::Args,
+    util::HashMap,
+  },
+};
+
+// Config is a plain ini style file:
+//
+//   [sessions]
+//   utmp = /var/run/utmp
+//
+//   [command uptime]
+//   cmd = uptime
+//
+// The first word of a section header is its kind, anything after that is its
+// name. Lines starting with '#' or ';' are comments.
+
+#[derive(Debug, Clone, Default)]
+pub struct Section {
+  pub kind: String,
+  pub name: String,
+  pub vals: HashMap<String, String>,
+}
+impl Section {
+  pub fn new(kind: &str, name: &str) -> Self {
+    Self {
+      kind: kind.to_owned(),
+      name: name.to_owned(),
+      vals: HashMap::new(),
+    }
+  }
+  pub fn get(&self, key: &str) -> Option<&str> {
+    self.vals.get(key).map(|val| val.as_str())
+  }
+  pub fn get_str(&self, key: &str, default: &str) -> String {
+    self.get(key).unwrap_or(default).to_owned()
+  }
+  /// Parses the value for `key`, falling back to `default` if it's missing
+  /// or doesn't parse. Bad values are reported rather than silently eaten.
+  pub fn get_or<T: FromStr>(&self, key: &str, default: T) -> T {
+    match self.get(key) {
+      Some(val) => match val.parse() {
+        Ok(val) => val,
+        Err(_) => {
+          println!("Config: [{} {}] bad value for {}: {}", self.kind, self.name, key, val);
+          default
+        }
+      },
+      None => default,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+  pub path: Option<PathBuf>,
+  pub sections: Vec<Section>,
+}
+impl Config {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// Loads the file given by `--config <path>`, or the default config file
+  /// if there is one. A missing default file just means an empty config.
+  pub fn from_args(args: &Args) -> Self {
+    let mut out = match (&args.config, default_path()) {
+      (Some(path), _) => Self::load(path.clone()),
+      (None, Some(ref path)) if path.is_file() => Self::load(path.clone()),
+      _ => Self::new(),
+    };
+    // `--demo` stands in for a [demo] section, with its seed if one's given
+    if let Some(ref seed) = args.demo {
+      let mut section = out.section("demo").cloned().unwrap_or_else(|| Section::new("demo", ""));
+      if !seed.is_empty() { section.vals.insert("seed".to_owned(), seed.clone()); }
+      out.sections.retain(|s| s.kind != "demo");
+      out.sections.push(section);
+    }
+    // `--redact` turns on masking with the defaults if there's no section
+    if args.redact && out.section("redact").is_none() { out.sections.push(Section::new("redact", "")); }
+    out
+  }
+  pub fn load(path: PathBuf) -> Self {
+    let mut out = match fs::read_to_string(&path) {
+      Ok(text) => Self::parse(&text),
+      Err(e) => {
+        println!("Config: can't read {}: {}", path.display(), e);
+        Self::new()
+      }
+    };
+    out.path = Some(path);
+    out
+  }
+  pub fn parse(text: &str) -> Self {
+    let mut out = Self::new();
+    for (num, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') || line.starts_with(';') { continue }
+      if line.starts_with('[') && line.ends_with(']') {
+        let header = line[1..line.len() - 1].trim();
+        let mut parts = header.splitn(2, char::is_whitespace);
+        let kind = parts.next().unwrap_or("");
+        let name = parts.next().unwrap_or("").trim();
+        out.sections.push(Section::new(kind, name));
+        continue
+      }
+      match (line.find('='), out.sections.last_mut()) {
+        (Some(eq), Some(section)) => {
+          let key = line[..eq].trim().to_owned();
+          let val = line[eq + 1..].trim().to_owned();
+          section.vals.insert(key, val);
+        }
+        _ => println!("Config: ignoring line {}: {}", num + 1, line),
+      }
+    }
+    out
+  }
+  /// The first section of the given kind
+  pub fn section(&self, kind: &str) -> Option<&Section> {
+    self.sections.iter().find(|s| s.kind == kind)
+  }
+  /// Every section of the given kind, in file order
+  pub fn sections(&self, kind: &str) -> Vec<&Section> {
+    self.sections.iter().filter(|s| s.kind == kind).collect()
+  }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+  match env::var_os("XDG_CONFIG_HOME") {
+    Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("raumEnSysInfo")),
+    _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("raumEnSysInfo")),
+  }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+  config_dir().map(|dir| dir.join("config.ini"))
+}
@@ -0,0 +1,239 @@
+
+use {
+  std::{
+    env,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+      fs::{DirBuilderExt, PermissionsExt},
+      net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+    time::Duration,
+  },
+  serde_json::{json, Map, Value},
+  crate::{
+    config::Section,
+    gamemgr::GameMgr,
+    text::TextMgr,
+  },
+};
+
+// The control socket takes one JSON object per line and answers each with
+// one line, `{"ok":true,...}` or `{"ok":false,"error":"..."}`:
+//
+//   {"cmd":"show","label":"Sessions"}
+//   {"cmd":"hide","label":"Sessions"}
+//   {"cmd":"colour","label":"Title","colour":[1.0,0.0,0.0]}
+//   {"cmd":"colour","label":"Title","colour":"#ff8000"}
+//   {"cmd":"reload_fonts"}
+//   {"cmd":"dump"}
+//
+// Commands are carried out on the main thread between frames, since that's
+// the only one that can touch the GL context.
+
+/// How long a connection waits on the main thread before giving up
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  Show(String),
+  Hide(String),
+  Colour(String, (f32, f32, f32)),
+  ReloadFonts,
+  /// Every label's text, whether it's shown, and its colour
+  Dump,
+}
+impl Command {
+  pub fn parse(line: &str) -> Result<Self, String> {
+    let val: Value = serde_json::from_str(line).map_err(|e| format!("bad json: {}", e))?;
+    let cmd = val.get("cmd").and_then(|c| c.as_str()).ok_or("no cmd")?;
+    let label = || -> Result<String, String> {
+      val.get("label").and_then(|l| l.as_str()).map(|l| l.to_owned()).ok_or_else(|| format!("{} needs a label", cmd))
+    };
+    match cmd {
+      "show" => Ok(Command::Show(label()?)),
+      "hide" => Ok(Command::Hide(label()?)),
+      "colour" | "color" => {
+        let colour = val.get("colour").or_else(|| val.get("color")).ok_or("colour needs a colour")?;
+        Ok(Command::Colour(label()?, parse_colour(colour)?))
+      }
+      "reload_fonts" => Ok(Command::ReloadFonts),
+      "dump" => Ok(Command::Dump),
+      _ => Err(format!("unknown cmd {}", cmd)),
+    }
+  }
+}
+
+/// `[r, g, b]` from 0 to 1, or `#rrggbb`
+fn parse_colour(val: &Value) -> Result<(f32, f32, f32), String> {
+  if let Some(hex) = val.as_str() {
+    let hex = hex.trim_start_matches('#');
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok()).map(|c| c as f32 / 255.0);
+    return match (hex.len(), channel(0), channel(2), channel(4)) {
+      (6, Some(r), Some(g), Some(b)) => Ok((r, g, b)),
+      _ => Err(format!("bad colour {}", val)),
+    }
+  }
+  let rgb: Vec<f64> = val.as_array().map(|a| a.iter().filter_map(|c| c.as_f64()).collect()).unwrap_or_default();
+  match rgb.as_slice() {
+    [r, g, b] if rgb.iter().all(|c| (0.0..=1.0).contains(c)) => Ok((*r as f32, *g as f32, *b as f32)),
+    _ => Err(format!("bad colour {}, expected [r, g, b] from 0 to 1 or #rrggbb", val)),
+  }
+}
+
+/// Listens on a unix socket for commands that change the running display.
+///
+///   [control]
+///   socket = /run/user/1000/raumEnSysInfo.sock
+///
+/// The socket defaults to `$XDG_RUNTIME_DIR/raumEnSysInfo.sock` and is only
+/// usable by its owner.
+pub struct Control {
+  pub path: PathBuf,
+  commands: Receiver<(Command, Sender<Value>)>,
+}
+impl Control {
+  pub fn from_section(section: &Section) -> Result<Self, String> {
+    let path = match section.get("socket") {
+      Some(path) => PathBuf::from(path),
+      None => default_path(),
+    };
+    if path.exists() {
+      // Only clear away a socket nobody's listening on
+      if UnixStream::connect(&path).is_ok() { return Err(format!("{} is already in use", path.display())) }
+      fs::remove_file(&path).map_err(|e| format!("can't remove old {}: {}", path.display(), e))?;
+    }
+    let listener = bind_private(&path).map_err(|e| format!("can't listen on {}: {}", path.display(), e))?;
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        match stream {
+          Ok(stream) => {
+            let tx = tx.clone();
+            thread::spawn(move || serve(stream, tx));
+          }
+          Err(e) => println!("Control: accept failed: {}", e),
+        }
+      }
+    });
+    Ok(Self { path, commands: rx })
+  }
+  /// Carries out any commands that have come in. Cheap when there are none.
+  pub fn apply(&self, mgr: GameMgr) {
+    let mut pending = self.commands.try_iter().peekable();
+    if pending.peek().is_none() { return }
+    let _textmgr = mgr.clone().textmgr.take().unwrap();
+    let mut textmgr = _textmgr.lock().unwrap();
+    for (cmd, reply) in pending {
+      let result = match cmd {
+        Command::Show(ref label) => known(&textmgr, label).map(|_| {
+          textmgr.enable_label(mgr.clone(), label);
+          json!({})
+        }),
+        Command::Hide(ref label) => known(&textmgr, label).map(|_| {
+          textmgr.disable_label(label);
+          json!({})
+        }),
+        Command::Colour(ref label, (r, g, b)) => known(&textmgr, label).map(|_| {
+          if let Some(gtext) = textmgr.texts.get_mut(label) { gtext.set_colour(r, g, b); }
+          json!({})
+        }),
+        Command::ReloadFonts => textmgr.reload_fonts(mgr.clone()).map(|count| json!({ "fonts": count })),
+        Command::Dump => {
+          let mut labels = Map::new();
+          for (label, gtext) in &textmgr.texts {
+            let shown = textmgr.active_text.get(&gtext.font).map(|l| l.contains(label)).unwrap_or(false);
+            let c = &gtext.effect.colour;
+            labels.insert(label.clone(), json!({ "text": gtext.text, "shown": shown, "colour": [c.x, c.y, c.z] }));
+          }
+          Ok(json!({ "labels": labels }))
+        }
+      };
+      let _ = reply.send(match result {
+        Ok(mut val) => { val["ok"] = json!(true); val }
+        Err(e) => json!({ "ok": false, "error": e }),
+      });
+    }
+  }
+}
+
+fn known(textmgr: &TextMgr, label: &str) -> Result<(), String> {
+  if textmgr.texts.contains_key(label) { Ok(()) } else { Err(format!("no label {}", label)) }
+}
+
+fn serve(stream: UnixStream, tx: Sender<(Command, Sender<Value>)>) {
+  let mut out = match stream.try_clone() {
+    Ok(out) => out,
+    Err(_) => return,
+  };
+  for line in BufReader::new(stream).lines() {
+    let line = match line { Ok(line) => line, Err(_) => return };
+    if line.trim().is_empty() { continue }
+    let answer = match Command::parse(&line) {
+      Ok(cmd) => {
+        let (reply_tx, reply_rx) = channel();
+        if tx.send((cmd, reply_tx)).is_err() { return }
+        reply_rx.recv_timeout(REPLY_TIMEOUT).unwrap_or_else(|_| json!({ "ok": false, "error": "display didn't answer" }))
+      }
+      Err(e) => json!({ "ok": false, "error": e }),
+    };
+    if writeln!(out, "{}", answer).is_err() { return }
+  }
+}
+
+/// Binds the socket in a directory only the owner can get into and moves it
+/// into place once it's 0600, so it's never reachable with looser permissions
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+  let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+  let dir = path.with_file_name(format!(".{}.{}", name, process::id()));
+  fs::DirBuilder::new().mode(0o700).create(&dir)?;
+  let tmp = dir.join("sock");
+  let bound = UnixListener::bind(&tmp).and_then(|listener| {
+    fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+    fs::rename(&tmp, path)?;
+    Ok(listener)
+  });
+  let _ = fs::remove_dir_all(&dir);
+  bound
+}
+
+fn default_path() -> PathBuf {
+  match env::var_os("XDG_RUNTIME_DIR") {
+    Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("raumEnSysInfo.sock"),
+    // Without a runtime dir, keep users on a shared machine apart
+    _ => {
+      let user = env::var("USER").unwrap_or_else(|_| "user".to_owned());
+      env::temp_dir().join(format!("raumEnSysInfo-{}.sock", user))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    std::os::unix::fs::{FileTypeExt, MetadataExt},
+    crate::{config::Config, util::temp_dir},
+  };
+
+  #[test]
+  fn socket_is_owner_only() {
+    let dir = temp_dir("control");
+    let path = dir.join("ctl.sock");
+    let config = Config::parse(&format!("[control]\nsocket = {}\n", path.display()));
+    let section = config.section("control").unwrap();
+    let control = Control::from_section(section).unwrap();
+    let meta = fs::metadata(&control.path).unwrap();
+    assert!(meta.file_type().is_socket());
+    assert_eq!(meta.mode() & 0o777, 0o600);
+    // Nothing is left behind from binding it
+    let names: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(names, vec!["ctl.sock"]);
+    assert!(Control::from_section(section).err().unwrap().ends_with("is already in use"));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,267 @@
+
+use {
+  std::collections::BTreeSet,
+  bytesize::ByteSize,
+  crate::{
+    config::{Config, Section},
+    metrics::{glob_match, smooth::specificity},
+    snapshot::{fmt_metric, fmt_time, Snapshot},
+  },
+};
+
+/// How far a metric can move between snapshots before it's reported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tolerance {
+  Ignore,
+  /// Percent of the old value
+  Pct(f64),
+  /// In the metric's own units, bytes for sizes
+  Abs(f64),
+}
+impl Tolerance {
+  /// `ignore`, `10%`, or an amount like `5` or `2 GiB`
+  pub fn parse(s: &str) -> Option<Self> {
+    let s = s.trim();
+    if s == "ignore" { return Some(Tolerance::Ignore) }
+    if let Some(pct) = s.strip_suffix('%') {
+      return pct.trim().parse().ok().filter(|p: &f64| *p >= 0.0).map(Tolerance::Pct)
+    }
+    if let Ok(abs) = s.parse::<f64>() { return if abs >= 0.0 { Some(Tolerance::Abs(abs)) } else { None } }
+    s.parse::<ByteSize>().ok().map(|b| Tolerance::Abs(b.as_u64() as f64))
+  }
+  pub fn exceeded(&self, old: f64, new: f64) -> bool {
+    let delta = (new - old).abs();
+    match *self {
+      Tolerance::Ignore => false,
+      Tolerance::Pct(pct) => if old == 0.0 { delta > 0.0 } else { delta / old.abs() * 100.0 > pct },
+      Tolerance::Abs(abs) => delta > abs,
+    }
+  }
+}
+
+/// Tolerances by metric glob, most specific first. A `[diff]` section
+/// replaces the defaults, which skip anything that moves from second to
+/// second and allow 10% on the rest.
+///
+///   [diff]
+///   disk.*.used = 5%
+///   disk./var.used = 2 GiB
+///   load.* = ignore
+///   * = 10%
+pub struct Tolerances(pub Vec<(String, Tolerance)>);
+impl Default for Tolerances {
+  fn default() -> Self {
+    let defaults = [
+      ("cpu.*", Tolerance::Ignore),
+      ("load.*", Tolerance::Ignore),
+      ("uptime", Tolerance::Ignore),
+      ("net.*", Tolerance::Ignore),
+      ("disk.*.util", Tolerance::Ignore),
+      ("*.rate", Tolerance::Ignore),
+      ("*.smooth", Tolerance::Ignore),
+      ("mem.free", Tolerance::Ignore),
+      ("*", Tolerance::Pct(10.0)),
+    ];
+    let mut out: Vec<(String, Tolerance)> = defaults.iter().map(|(g, t)| (g.to_string(), *t)).collect();
+    out.sort_by_key(|(glob, _)| specificity(glob));
+    Tolerances(out)
+  }
+}
+impl Tolerances {
+  pub fn from_config(config: &Config) -> Self {
+    match config.section("diff") {
+      Some(section) => Self::from_section(section),
+      None => Self::default(),
+    }
+  }
+  pub fn from_section(section: &Section) -> Self {
+    let mut out = Vec::new();
+    for (glob, spec) in &section.vals {
+      match Tolerance::parse(spec) {
+        Some(tolerance) => out.push((glob.clone(), tolerance)),
+        None => println!("Config: [diff] bad tolerance for {}: {}", glob, spec),
+      }
+    }
+    out.sort_by_key(|(glob, _)| specificity(glob));
+    Tolerances(out)
+  }
+  /// Metrics no glob matches are ignored
+  pub fn get(&self, name: &str) -> Tolerance {
+    self.0.iter().find(|(glob, _)| glob_match(glob, name)).map(|(_, t)| *t).unwrap_or(Tolerance::Ignore)
+  }
+}
+
+/// What changed between two snapshots of a machine, one line per change,
+/// with hardware and software first and metrics after.
+pub fn diff(old: &Snapshot, new: &Snapshot, tolerances: &Tolerances) -> Vec<String> {
+  let mut out = Vec::new();
+  let mut changed = |what: &str, a: &str, b: &str| {
+    if a != b { out.push(format!("{}: {} -> {}", what, or_none(a), or_none(b))) }
+  };
+  changed("Host", &old.host, &new.host);
+  changed("Kernel", &old.kernel, &new.kernel);
+  changed("CPU", &old.cpu, &new.cpu);
+  changed("Microcode", &old.microcode, &new.microcode);
+  for (name, what) in &[("mem.total", "Memory total"), ("swap.total", "Swap total")] {
+    let (a, b) = (old.metrics.get(*name), new.metrics.get(*name));
+    if a != b { changed(what, &fmt_opt(name, a), &fmt_opt(name, b)); }
+  }
+  let (old_mounts, new_mounts) = (mounts(old), mounts(new));
+  for mount in new_mounts.difference(&old_mounts) {
+    let size = new.metrics.get(&format!("disk.{}.total", mount)).map(|t| fmt_metric("disk.", *t)).unwrap_or_default();
+    out.push(format!("Mount added: {} {}", mount, size).trim_end().to_owned());
+  }
+  for mount in old_mounts.difference(&new_mounts) { out.push(format!("Mount removed: {}", mount)); }
+  let (old_ifaces, new_ifaces) = (interfaces(old), interfaces(new));
+  for iface in new_ifaces.difference(&old_ifaces) { out.push(format!("Interface added: {}", iface)); }
+  for iface in old_ifaces.difference(&new_ifaces) { out.push(format!("Interface removed: {}", iface)); }
+  let alerts: BTreeSet<&String> = old.alerts.keys().collect();
+  for (name, severity) in &new.alerts {
+    if !alerts.contains(name) { out.push(format!("Alert firing: {} ({})", name, severity)); }
+  }
+  for name in old.alerts.keys().filter(|name| !new.alerts.contains_key(*name)) {
+    out.push(format!("Alert resolved: {}", name));
+  }
+  // Metrics of mounts and interfaces that came or went are covered above
+  let covered = |name: &str| {
+    name == "mem.total" || name == "swap.total"
+      || new_mounts.symmetric_difference(&old_mounts).any(|m| name.starts_with(&format!("disk.{}.", m)))
+      || new_ifaces.symmetric_difference(&old_ifaces).any(|i| name.starts_with(&format!("net.{}.", i)))
+  };
+  for (name, a) in &old.metrics {
+    if covered(name) { continue }
+    let tolerance = tolerances.get(name);
+    match new.metrics.get(name) {
+      Some(b) if tolerance.exceeded(*a, *b) => {
+        out.push(format!("{}: {} -> {} ({})", name, fmt_metric(name, *a), fmt_metric(name, *b), fmt_delta(name, *a, *b)));
+      }
+      None if tolerance != Tolerance::Ignore => out.push(format!("Metric removed: {}", name)),
+      _ => (),
+    }
+  }
+  for name in new.metrics.keys() {
+    if !old.metrics.contains_key(name) && !covered(name) && tolerances.get(name) != Tolerance::Ignore {
+      out.push(format!("Metric added: {}", name));
+    }
+  }
+  out
+}
+
+/// A heading for the diff saying which snapshots are being compared
+pub fn header(old: &Snapshot, new: &Snapshot) -> String {
+  format!("{} at {} -> {} at {}", old.host, fmt_time(old.time), new.host, fmt_time(new.time))
+}
+
+/// Mount points, from their `disk.<mount>.total` metrics
+fn mounts(snapshot: &Snapshot) -> BTreeSet<String> {
+  snapshot.metrics.keys()
+    .filter_map(|k| k.strip_prefix("disk.").and_then(|k| k.strip_suffix(".total")))
+    .map(|m| m.to_owned())
+    .collect()
+}
+
+/// Network interfaces, from their `net.<iface>.rx` counters
+fn interfaces(snapshot: &Snapshot) -> BTreeSet<String> {
+  snapshot.metrics.keys()
+    .filter_map(|k| k.strip_prefix("net.").and_then(|k| k.strip_suffix(".rx")))
+    .map(|i| i.to_owned())
+    .collect()
+}
+
+fn fmt_opt(name: &str, value: Option<&f64>) -> String {
+  value.map(|v| fmt_metric(name, *v)).unwrap_or_default()
+}
+
+fn fmt_delta(name: &str, old: f64, new: f64) -> String {
+  let sign = if new >= old { "+" } else { "-" };
+  let delta = fmt_metric(name, (new - old).abs());
+  if old == 0.0 { format!("{}{}", sign, delta) } else { format!("{}{}, {:+.1}%", sign, delta, (new - old) / old.abs() * 100.0) }
+}
+
+fn or_none(s: &str) -> &str {
+  if s.is_empty() { "(none)" } else { s }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+  fn snapshot(kernel: &str, metrics: &[(&str, f64)], alerts: &[(&str, &str)]) -> Snapshot {
+    Snapshot {
+      host: "rack1".to_owned(),
+      time: 1_700_000_000.0,
+      cpu: "Test CPU".to_owned(),
+      kernel: kernel.to_owned(),
+      metrics: metrics.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+      alerts: alerts.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+      ..Snapshot::new()
+    }
+  }
+
+  #[test]
+  fn parses_tolerances() {
+    assert_eq!(Tolerance::parse("ignore"), Some(Tolerance::Ignore));
+    assert_eq!(Tolerance::parse(" 10% "), Some(Tolerance::Pct(10.0)));
+    assert_eq!(Tolerance::parse("2.5 %"), Some(Tolerance::Pct(2.5)));
+    assert_eq!(Tolerance::parse("5"), Some(Tolerance::Abs(5.0)));
+    assert_eq!(Tolerance::parse("2 GiB"), Some(Tolerance::Abs(2.0 * GIB)));
+    for bad in &["-5", "-1%", "lots", "%"] { assert_eq!(Tolerance::parse(bad), None, "{}", bad); }
+  }
+
+  #[test]
+  fn tolerances_are_exceeded() {
+    assert!(!Tolerance::Ignore.exceeded(1.0, 1e9));
+    assert!(!Tolerance::Pct(10.0).exceeded(100.0, 110.0));
+    assert!(Tolerance::Pct(10.0).exceeded(100.0, 89.0));
+    assert!(Tolerance::Pct(10.0).exceeded(-100.0, -111.0));
+    // Any move away from zero is infinitely many percent
+    assert!(Tolerance::Pct(10.0).exceeded(0.0, 0.1));
+    assert!(!Tolerance::Pct(10.0).exceeded(0.0, 0.0));
+    assert!(!Tolerance::Abs(5.0).exceeded(10.0, 15.0));
+    assert!(Tolerance::Abs(5.0).exceeded(10.0, 4.0));
+  }
+
+  #[test]
+  fn most_specific_tolerance_wins() {
+    let config = Config::parse("[diff]\n* = 10%\ndisk.*.used = 5%\ndisk./var.used = 2 GiB\n");
+    let tolerances = Tolerances::from_config(&config);
+    assert_eq!(tolerances.get("disk./var.used"), Tolerance::Abs(2.0 * GIB));
+    assert_eq!(tolerances.get("disk./.used"), Tolerance::Pct(5.0));
+    assert_eq!(tolerances.get("load.1"), Tolerance::Pct(10.0));
+    assert_eq!(Tolerances::default().get("load.1"), Tolerance::Ignore);
+  }
+
+  #[test]
+  fn renders_a_diff() {
+    let old = snapshot("6.1.0-17-amd64", &[
+      ("mem.total", 8.0 * GIB),
+      ("disk./.total", 100.0 * GIB), ("disk./.used", 50.0 * GIB),
+      ("disk./mnt/usb.total", 32.0 * GIB), ("disk./mnt/usb.used", GIB),
+      ("net.eth0.rx", 5.0), ("net.eth0.tx", 5.0),
+      ("load.1", 0.5), ("procs", 200.0), ("users", 2.0),
+    ], &[("mem_high", "warning")]);
+    let new = snapshot("6.1.0-18-amd64", &[
+      ("mem.total", 16.0 * GIB),
+      ("disk./.total", 100.0 * GIB), ("disk./.used", 60.0 * GIB),
+      ("disk./data.total", 500.0 * GIB), ("disk./data.used", 0.0),
+      ("net.eth0.rx", 9.0), ("net.eth0.tx", 9.0), ("net.wlan0.rx", 1.0), ("net.wlan0.tx", 1.0),
+      ("load.1", 3.0), ("procs", 205.0), ("threads", 900.0),
+    ], &[("disk_full", "critical")]);
+    assert_eq!(diff(&old, &new, &Tolerances::default()), vec![
+      "Kernel: 6.1.0-17-amd64 -> 6.1.0-18-amd64",
+      "Memory total: 8.0 GiB -> 16.0 GiB",
+      "Mount added: /data 500.0 GiB",
+      "Mount removed: /mnt/usb",
+      "Interface added: wlan0",
+      "Alert firing: disk_full (critical)",
+      "Alert resolved: mem_high",
+      "disk./.used: 50.0 GiB -> 60.0 GiB (+10.0 GiB, +20.0%)",
+      "Metric removed: users",
+      "Metric added: threads",
+    ]);
+    assert!(diff(&new, &new, &Tolerances::default()).is_empty());
+    assert_eq!(diff(&new, &old, &Tolerances::default())[3..5], ["Mount removed: /data", "Interface removed: wlan0"]);
+  }
+}
@@ -0,0 +1,377 @@
+
+use {
+  std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+  },
+  crate::{
+    config::{Config, Section},
+    export::{split_labels, Sink},
+    metrics::{glob_match, Sample},
+    redact,
+    snapshot::{fmt_time, hostname},
+    util::HashSet,
+  },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+  /// One row per tick, one column per metric
+  Csv,
+  /// InfluxDB line protocol, one line per metric per tick
+  Influx,
+}
+impl LogFormat {
+  pub fn parse(s: &str) -> Option<Self> {
+    match s {
+      "csv" => Some(LogFormat::Csv),
+      "influx" | "line" => Some(LogFormat::Influx),
+      _ => None,
+    }
+  }
+}
+
+/// Appends every sample tick to a file, rotating it once it gets too big.
+///
+///   [log capacity]
+///   path = /var/log/raumEnSysInfo/samples.csv
+///   format = csv
+///   metrics = mem.*, disk.*
+///   max_mb = 10
+///   keep = 5
+///
+/// A CSV file's header is every column it has. When a new metric turns up
+/// the file is rotated and the new one starts with the wider header, so no
+/// file ever changes shape halfway down. Columns are the redacted metric
+/// names, which is also what a header left from last time is matched
+/// against, so a restart carries on with the same file. Metrics that redact
+/// to the same name share a column, the first one in a tick filling it.
+pub struct SampleLog {
+  pub name: String,
+  pub path: PathBuf,
+  pub format: LogFormat,
+  /// Globs of the metrics to log, everything if empty
+  pub metrics: Vec<String>,
+  pub max_bytes: u64,
+  /// How many rotated files to keep, as `<path>.1` (newest) to `<path>.<keep>`
+  pub keep: u32,
+  host: String,
+  /// Masks metric names for the header, `redact::redact` outside tests
+  mask: fn(&str) -> String,
+  /// Redacted metric names
+  columns: Vec<String>,
+  known: HashSet<String>,
+  file: Option<File>,
+  size: u64,
+}
+impl SampleLog {
+  pub fn from_section(section: &Section) -> Result<Self, String> {
+    let path = PathBuf::from(section.get("path").ok_or("no path")?);
+    let format = match section.get("format") {
+      Some(format) => LogFormat::parse(format).ok_or_else(|| format!("unknown format {}", format))?,
+      // Going by the file name saves saying it twice
+      None if path.extension().map(|x| x == "csv").unwrap_or(false) => LogFormat::Csv,
+      None => LogFormat::Influx,
+    };
+    Ok(Self {
+      name: section.name.clone(),
+      path,
+      format,
+      metrics: section.get("metrics").map(|m| {
+        m.split(',').map(|g| g.trim().to_owned()).filter(|g| !g.is_empty()).collect()
+      }).unwrap_or_default(),
+      max_bytes: (section.get_or("max_mb", 10.0_f64) * 1024.0 * 1024.0) as u64,
+      keep: section.get_or("keep", 5),
+      host: hostname(),
+      mask: redact::redact,
+      columns: Vec::new(),
+      known: HashSet::new(),
+      file: None,
+      size: 0,
+    })
+  }
+  pub fn from_config(config: &Config) -> Vec<Self> {
+    let mut out = Vec::new();
+    for section in config.sections("log") {
+      match Self::from_section(section) {
+        Ok(log) => out.push(log),
+        Err(e) => println!("Config: [log {}] {}", section.name, e),
+      }
+    }
+    out
+  }
+  fn wanted(&self, name: &str) -> bool {
+    self.metrics.is_empty() || self.metrics.iter().any(|g| glob_match(g, name))
+  }
+  fn write(&mut self, ts: f64, samples: &[&Sample]) -> io::Result<()> {
+    if self.file.is_none() { self.open()? }
+    let mut text = String::new();
+    match self.format {
+      LogFormat::Csv => {
+        let mut new: Vec<String> = samples.iter()
+          .map(|s| (self.mask)(&s.name))
+          .filter(|name| !self.known.contains(name))
+          .collect();
+        if !new.is_empty() {
+          new.sort();
+          new.dedup();
+          // Only a file that already has rows needs moving out of the way
+          let had_rows = self.size > 0;
+          for name in new { self.known.insert(name.clone()); self.columns.push(name); }
+          if had_rows { self.rotate()? }
+          text.push_str(&self.header());
+        }
+        text.push_str(&self.csv_row(ts, samples));
+      }
+      LogFormat::Influx => {
+        for s in samples { text.push_str(&redact::redact(&self.influx_line(ts, s))); }
+      }
+    }
+    if self.size > 0 && self.size + text.len() as u64 > self.max_bytes {
+      self.rotate()?;
+      if self.format == LogFormat::Csv { text = self.header() + &self.csv_row(ts, samples); }
+    }
+    let file = self.file.as_mut().expect("log file is open");
+    file.write_all(text.as_bytes())?;
+    self.size += text.len() as u64;
+    Ok(())
+  }
+  fn open(&mut self) -> io::Result<()> {
+    if let Some(dir) = self.path.parent() {
+      if !dir.as_os_str().is_empty() { fs::create_dir_all(dir)? }
+    }
+    // Carry on with the columns of a CSV file left from last time
+    if self.format == LogFormat::Csv && self.columns.is_empty() {
+      if let Some(columns) = read_header(&self.path) {
+        self.known = columns.iter().cloned().collect();
+        self.columns = columns;
+      }
+    }
+    let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+    self.size = file.metadata()?.len();
+    // A CSV file with rows but no header we understand can't be added to
+    if self.format == LogFormat::Csv && self.size > 0 && self.columns.is_empty() {
+      drop(file);
+      return self.rotate()
+    }
+    self.file = Some(file);
+    Ok(())
+  }
+  /// Shifts `<path>.N` up by one, dropping the oldest, and starts afresh
+  fn rotate(&mut self) -> io::Result<()> {
+    self.file = None;
+    if self.keep == 0 {
+      fs::remove_file(&self.path)?;
+    } else {
+      let _ = fs::remove_file(rotated(&self.path, self.keep));
+      for n in (1..self.keep).rev() {
+        let from = rotated(&self.path, n);
+        if from.exists() { fs::rename(&from, rotated(&self.path, n + 1))? }
+      }
+      fs::rename(&self.path, rotated(&self.path, 1))?;
+    }
+    self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+    self.size = 0;
+    Ok(())
+  }
+  fn header(&self) -> String {
+    let mut row = vec!["time".to_owned(), "unix".to_owned()];
+    row.extend(self.columns.iter().map(|c| csv_field(c)));
+    row.join(",") + "\n"
+  }
+  /// The header holds all the names, so a row is only the time and numbers
+  fn csv_row(&self, ts: f64, samples: &[&Sample]) -> String {
+    let masked: Vec<(String, f64)> = samples.iter().map(|s| ((self.mask)(&s.name), s.value)).collect();
+    let mut row = vec![fmt_time(ts), format!("{:.3}", ts)];
+    for column in &self.columns {
+      // Metrics missing from this tick get an empty cell
+      row.push(masked.iter().find(|(name, _)| name == column).map(|(_, v)| format!("{}", v)).unwrap_or_default());
+    }
+    row.join(",") + "\n"
+  }
+  fn influx_line(&self, ts: f64, sample: &Sample) -> String {
+    // Line protocol can't carry NaN or infinities
+    if !sample.value.is_finite() { return String::new() }
+    let (base, labels) = split_labels(&sample.name);
+    let mut line = influx_escape(&base, false);
+    line.push_str(&format!(",host={}", influx_escape(&self.host, true)));
+    for (key, val) in labels {
+      line.push_str(&format!(",{}={}", key, influx_escape(&val, true)));
+    }
+    format!("{} value={} {}\n", line, sample.value, (ts * 1e9) as i64)
+  }
+}
+
+impl Sink for SampleLog {
+  fn push(&mut self, ts: f64, samples: &[Sample]) {
+    let samples: Vec<&Sample> = samples.iter().filter(|s| self.wanted(&s.name)).collect();
+    if samples.is_empty() { return }
+    if let Err(e) = self.write(ts, &samples) {
+      println!("Log {}: write to {} failed: {}", self.name, self.path.display(), e);
+      self.file = None;
+    }
+  }
+}
+
+fn rotated(path: &Path, n: u32) -> PathBuf {
+  let mut name = path.as_os_str().to_owned();
+  name.push(format!(".{}", n));
+  PathBuf::from(name)
+}
+
+fn csv_field(field: &str) -> String {
+  if field.contains(&[',', '"', '\n'][..]) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_owned()
+  }
+}
+
+/// Splits a CSV line, undoing the quoting `csv_field` does
+fn split_csv(line: &str) -> Vec<String> {
+  let mut out = Vec::new();
+  let mut field = String::new();
+  let mut quoted = false;
+  let mut chars = line.chars().peekable();
+  while let Some(c) = chars.next() {
+    match (c, quoted) {
+      ('"', true) if chars.peek() == Some(&'"') => { field.push('"'); chars.next(); }
+      ('"', _) => quoted = !quoted,
+      (',', false) => out.push(std::mem::take(&mut field)),
+      _ => field.push(c),
+    }
+  }
+  out.push(field);
+  out
+}
+
+/// The metric columns of an existing CSV log, if it has a header of ours
+fn read_header(path: &Path) -> Option<Vec<String>> {
+  let file = File::open(path).ok()?;
+  let mut line = String::new();
+  BufReader::new(file).read_line(&mut line).ok()?;
+  let fields = split_csv(line.trim_end_matches(&['\r', '\n'][..]));
+  if fields.len() < 2 || fields[0] != "time" || fields[1] != "unix" { return None }
+  Some(fields[2..].to_vec())
+}
+
+// Measurements escape commas and spaces, tags also escape `=`
+fn influx_escape(s: &str, tag: bool) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    if c == ',' || c == ' ' || (tag && c == '=') { out.push('\\') }
+    out.push(c);
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::util::temp_dir,
+  };
+
+  fn log(path: &Path) -> SampleLog {
+    let config = Config::parse(&format!("[log test]\npath = {}\n", path.display()));
+    SampleLog::from_section(config.sections("log")[0]).unwrap()
+  }
+
+  #[test]
+  fn csv_carries_on_after_a_restart() {
+    let dir = temp_dir("log-restart");
+    let path = dir.join("samples.csv");
+    let mut first = log(&path);
+    first.push(100.0, &[Sample::new("mem.used", 1.0), Sample::new("disk./home/al,ice.used", 2.0)]);
+    drop(first);
+    let mut second = log(&path);
+    second.push(101.0, &[Sample::new("disk./home/al,ice.used", 4.0), Sample::new("mem.used", 3.0)]);
+    let text = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "time,unix,\"disk./home/al,ice.used\",mem.used");
+    assert!(lines[1].ends_with(",100.000,2,1"));
+    assert!(lines[2].ends_with(",101.000,4,3"));
+    assert!(!rotated(&path, 1).exists());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn redacted_columns_carry_on_after_a_restart() {
+    let dir = temp_dir("log-restart-redacted");
+    let path = dir.join("samples.csv");
+    let mut first = log(&path);
+    first.mask = mask;
+    first.push(100.0, &[Sample::new("mem.used", 1.0), Sample::new("disk./home/alice.used", 2.0)]);
+    drop(first);
+    // The header holds the masked name, which the new metric maps onto
+    let mut second = log(&path);
+    second.mask = mask;
+    second.push(101.0, &[Sample::new("disk./home/bob.used", 4.0), Sample::new("mem.used", 3.0)]);
+    let text = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "time,unix,disk./home/[path].used,mem.used");
+    assert!(lines[1].ends_with(",100.000,2,1"));
+    assert!(lines[2].ends_with(",101.000,4,3"));
+    assert!(!rotated(&path, 1).exists());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  /// Stands in for a `[redact]` section masking /home paths
+  fn mask(name: &str) -> String {
+    match (name.find("/home/"), name.rfind('.')) {
+      (Some(start), Some(end)) if end > start => format!("{}/home/[path]{}", &name[..start], &name[end..]),
+      _ => name.to_owned(),
+    }
+  }
+
+  #[test]
+  fn csv_header_is_redacted() {
+    let dir = temp_dir("log-redact");
+    let path = dir.join("samples.csv");
+    let mut log = log(&path);
+    log.mask = mask;
+    log.push(100.0, &[Sample::new("disk./home/alice.used", 2.0), Sample::new("disk./home/bob.used", 3.0)]);
+    let text = fs::read_to_string(&path).unwrap();
+    assert!(!text.contains("alice") && !text.contains("bob"));
+    let lines: Vec<&str> = text.lines().collect();
+    // Both mask to the same name, so they share a column
+    assert_eq!(lines[0], "time,unix,disk./home/[path].used");
+    assert!(lines[1].ends_with(",100.000,2"));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn new_metric_rotates_to_a_wider_header() {
+    let dir = temp_dir("log-widen");
+    let path = dir.join("samples.csv");
+    let mut log = log(&path);
+    log.push(100.0, &[Sample::new("a", 1.0)]);
+    log.push(101.0, &[Sample::new("b", 2.0), Sample::new("a", 3.0)]);
+    let old = fs::read_to_string(rotated(&path, 1)).unwrap();
+    assert_eq!(old.lines().next(), Some("time,unix,a"));
+    let new = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = new.lines().collect();
+    assert_eq!(lines[0], "time,unix,a,b");
+    assert!(lines[1].ends_with(",101.000,3,2"));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn influx_lines() {
+    let dir = temp_dir("log-influx");
+    let mut log = log(&dir.join("samples.influx"));
+    log.host = "rack 1".to_owned();
+    let s = Sample::new("disk./mnt/a b.used", 5.0);
+    assert_eq!(log.influx_line(1.5, &s), "disk.used,host=rack\\ 1,mount=/mnt/a\\ b value=5 1500000000\n");
+    assert_eq!(log.influx_line(1.5, &Sample::new("x", f64::NAN)), "");
+  }
+
+  #[test]
+  fn csv_quoting_round_trips() {
+    let fields = ["plain", "with,comma", "with \"quotes\""];
+    let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+    assert_eq!(split_csv(&line.join(",")), fields);
+  }
+}
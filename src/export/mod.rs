@@ -0,0 +1,66 @@
+pub mod log;
+pub mod prometheus;
+pub mod statsd;
+
+use {
+  crate::{
+    config::Config,
+    export::{log::SampleLog, statsd::Statsd},
+    metrics::Sample,
+  },
+};
+
+// Exporters speak to systems that want a fixed metric name with the
+// variable parts split out as labels or tags, so `disk./home.avail` goes out
+// as `disk.avail` with `mount=/home`.
+
+/// Somewhere every sample tick gets sent
+pub trait Sink {
+  fn push(&mut self, ts: f64, samples: &[Sample]);
+}
+
+/// Every sink the config asks for
+pub fn sinks(config: &Config) -> Vec<Box<dyn Sink>> {
+  let mut out: Vec<Box<dyn Sink>> = Vec::new();
+  for log in SampleLog::from_config(config) { out.push(Box::new(log)) }
+  if let Some(section) = config.section("statsd") {
+    match Statsd::from_section(section) {
+      Ok(statsd) => out.push(Box::new(statsd)),
+      Err(e) => println!("Config: [statsd] {}", e),
+    }
+  }
+  out
+}
+
+/// Splits the per mount, per interface and per period part out of a metric
+/// name. Names without one come back as they are, with no labels.
+pub fn split_labels(name: &str) -> (String, Vec<(&'static str, String)>) {
+  if let Some((mount, field)) = split_middle(name, "disk.", &["total", "avail", "used"]) {
+    return (format!("disk.{}", field), vec![("mount", mount.to_owned())])
+  }
+  if let Some((device, field)) = split_middle(name, "disk.", &["util"]) {
+    return (format!("disk.{}", field), vec![("device", device.to_owned())])
+  }
+  if let Some((iface, field)) = split_middle(name, "net.", &["rx", "tx"]) {
+    return (format!("net.{}", field), vec![("interface", iface.to_owned())])
+  }
+  if let Some(period) = name.strip_prefix("load.") {
+    if !period.contains('.') { return ("load".to_owned(), vec![("period", period.to_owned())]) }
+  }
+  (name.to_owned(), Vec::new())
+}
+
+// `<prefix><middle>.<field>[.rate|.smooth]`, where the middle may itself
+// have dots in it, like a mount path or a vlan interface
+fn split_middle<'a>(name: &'a str, prefix: &str, fields: &[&str]) -> Option<(&'a str, String)> {
+  let rest = name.strip_prefix(prefix)?;
+  for field in fields {
+    for suffix in &["", ".rate", ".smooth"] {
+      let tail = format!(".{}{}", field, suffix);
+      if let Some(middle) = rest.strip_suffix(tail.as_str()) {
+        if !middle.is_empty() { return Some((middle, tail[1..].to_owned())) }
+      }
+    }
+  }
+  None
+}
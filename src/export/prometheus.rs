@@ -0,0 +1,301 @@
+
+use {
+  std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+  },
+  crate::{
+    config::Section,
+    export::split_labels,
+    metrics::Store,
+    util::{Arc, HashMap, Mutex},
+  },
+};
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:9101";
+const PREFIX: &str = "raumen_";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+  Gauge,
+  Counter,
+}
+impl Kind {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Kind::Gauge => "gauge",
+      Kind::Counter => "counter",
+    }
+  }
+}
+
+// Collected metrics by their name with the labels split out. Anything not
+// in here, like derived metrics, goes out as a gauge named after itself.
+const KNOWN: [(&str, &str, Kind, &str); 14] = [
+  ("cpu.usage", "cpu_usage_percent", Kind::Gauge, "CPU usage across all cores"),
+  ("load", "load_average", Kind::Gauge, "Load average over the period in minutes"),
+  ("mem.total", "memory_total_bytes", Kind::Gauge, "Total memory"),
+  ("mem.used", "memory_used_bytes", Kind::Gauge, "Memory in use"),
+  ("mem.free", "memory_free_bytes", Kind::Gauge, "Unused memory"),
+  ("swap.total", "swap_total_bytes", Kind::Gauge, "Total swap"),
+  ("swap.used", "swap_used_bytes", Kind::Gauge, "Swap in use"),
+  ("uptime", "uptime_seconds", Kind::Gauge, "Time since boot"),
+  ("net.rx", "network_receive_bytes_total", Kind::Counter, "Bytes received"),
+  ("net.tx", "network_transmit_bytes_total", Kind::Counter, "Bytes sent"),
+  ("disk.total", "filesystem_size_bytes", Kind::Gauge, "Filesystem size"),
+  ("disk.avail", "filesystem_avail_bytes", Kind::Gauge, "Filesystem space available to users"),
+  ("disk.used", "filesystem_used_bytes", Kind::Gauge, "Filesystem space in use"),
+  ("disk.util", "disk_busy_percent", Kind::Gauge, "Percent of the time the disk was busy"),
+];
+
+/// The Prometheus name, type and help text for a metric name with its
+/// labels already split out
+pub fn family(base: &str) -> (String, Kind, String) {
+  let known = |base: &str| KNOWN.iter().find(|k| k.0 == base);
+  if let Some(k) = known(base) { return (format!("{}{}", PREFIX, k.1), k.2, k.3.to_owned()) }
+  if let Some(k) = base.strip_suffix(".rate").and_then(known) {
+    let name = k.1.trim_end_matches("_total");
+    return (format!("{}{}_per_second", PREFIX, name), Kind::Gauge, format!("{} per second", k.3))
+  }
+  if let Some(k) = base.strip_suffix(".smooth").and_then(known) {
+    return (format!("{}{}_smooth", PREFIX, k.1), Kind::Gauge, format!("{}, smoothed", k.3))
+  }
+  (format!("{}{}", PREFIX, sanitize(base)), Kind::Gauge, format!("Metric {}", base))
+}
+
+/// Metric names are `[a-zA-Z_:][a-zA-Z0-9_:]*`, everything else becomes `_`
+pub fn sanitize(name: &str) -> String {
+  let mut out: String = name.chars()
+    .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+    .collect();
+  if out.starts_with(|c: char| c.is_ascii_digit()) { out.insert(0, '_') }
+  out
+}
+
+fn escape_label(val: &str) -> String {
+  val.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn escape_help(help: &str) -> String {
+  help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+pub fn fmt_value(v: f64) -> String {
+  if v.is_nan() { "NaN".to_owned() }
+  else if v.is_infinite() { if v > 0.0 { "+Inf".to_owned() } else { "-Inf".to_owned() } }
+  else { format!("{}", v) }
+}
+
+/// Every metric with a sample in the last `stale` seconds, in the text
+/// exposition format. The seconds are counted back from the newest sample
+/// rather than the clock, so a replay of an old recording is served too.
+pub fn render(store: &Store, stale: f64) -> String {
+  let now = store.newest();
+  let mut families: HashMap<String, (Kind, String, Vec<String>)> = HashMap::new();
+  let mut names = store.names();
+  names.sort_unstable();
+  for name in names {
+    let (ts, value) = match store.series(name).and_then(|s| s.latest()) {
+      Some(latest) => latest,
+      None => continue,
+    };
+    if now - ts > stale { continue }
+    let (base, labels) = split_labels(name);
+    let (fname, kind, help) = family(&base);
+    let labels: Vec<String> = labels.iter().map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v))).collect();
+    let line = if labels.is_empty() {
+      format!("{} {}", fname, fmt_value(value))
+    } else {
+      format!("{}{{{}}} {}", fname, labels.join(","), fmt_value(value))
+    };
+    families.entry(fname).or_insert_with(|| (kind, help, Vec::new())).2.push(line);
+  }
+  let mut fnames: Vec<&String> = families.keys().collect();
+  fnames.sort_unstable();
+  let mut out = String::new();
+  for fname in fnames {
+    let (kind, help, lines) = &families[fname];
+    out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", fname, escape_help(help), fname, kind.name()));
+    for line in lines { out.push_str(line); out.push('\n'); }
+  }
+  out
+}
+
+/// One sample line read back from the text format
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scraped {
+  pub name: String,
+  pub labels: Vec<(String, String)>,
+  pub value: f64,
+}
+
+/// Reads the samples out of a Prometheus or OpenMetrics text page. Comments,
+/// HELP and TYPE lines are skipped, as are lines that don't parse.
+pub fn parse_exposition(text: &str) -> Vec<Scraped> {
+  text.lines().filter_map(|line| {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') { return None }
+    parse_sample(line)
+  }).collect()
+}
+
+fn parse_sample(line: &str) -> Option<Scraped> {
+  let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+  let name = &line[..name_end];
+  let mut rest = &line[name_end..];
+  let mut labels = Vec::new();
+  if rest.starts_with('{') {
+    let (parsed, after) = parse_labels(&rest[1..])?;
+    labels = parsed;
+    rest = after;
+  }
+  // A timestamp may follow the value, it's ignored
+  let value = parse_value(rest.split_whitespace().next()?)?;
+  Some(Scraped { name: name.to_owned(), labels, value })
+}
+
+/// `a="x",b="y"}` up to and including the closing brace, with the rest of
+/// the line after it
+pub fn parse_labels(mut s: &str) -> Option<(Vec<(String, String)>, &str)> {
+  let mut out = Vec::new();
+  loop {
+    s = s.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    if let Some(rest) = s.strip_prefix('}') { return Some((out, rest)) }
+    let eq = s.find('=')?;
+    let key = s[..eq].trim().to_owned();
+    let (val, rest) = parse_quoted(s[eq + 1..].trim_start())?;
+    out.push((key, val));
+    s = rest;
+  }
+}
+
+/// A double quoted string with `\\`, `\"` and `\n` escapes, and what's
+/// left after it
+pub fn parse_quoted(s: &str) -> Option<(String, &str)> {
+  let mut chars = s.strip_prefix('"')?.char_indices();
+  let mut out = String::new();
+  while let Some((idx, c)) = chars.next() {
+    match c {
+      '"' => return Some((out, &s[idx + 2..])),
+      '\\' => match chars.next()?.1 {
+        'n' => out.push('\n'),
+        c => out.push(c),
+      },
+      c => out.push(c),
+    }
+  }
+  None
+}
+
+pub fn parse_value(s: &str) -> Option<f64> {
+  match s {
+    "NaN" => Some(f64::NAN),
+    "+Inf" | "Inf" => Some(f64::INFINITY),
+    "-Inf" => Some(f64::NEG_INFINITY),
+    s => s.parse().ok(),
+  }
+}
+
+/// Serves `/metrics` from the store until the program exits.
+///
+///   [prometheus]
+///   listen = 127.0.0.1:9101
+///   stale = 60
+pub fn serve(section: &Section, store: Arc<Mutex<Store>>) {
+  let listen = section.get_str("listen", DEFAULT_LISTEN);
+  let stale = section.get_or("stale", 60.0);
+  let listener = match TcpListener::bind(&listen) {
+    Ok(listener) => listener,
+    Err(e) => { println!("Prometheus: can't listen on {}: {}", listen, e); return }
+  };
+  println!("Prometheus: serving http://{}/metrics", listen);
+  thread::spawn(move || {
+    for stream in listener.incoming() {
+      match stream {
+        // Scrapes are small and infrequent, one at a time is plenty
+        Ok(stream) => if let Err(e) = answer(stream, &store, stale) {
+          println!("Prometheus: {}", e);
+        },
+        Err(e) => println!("Prometheus: accept failed: {}", e),
+      }
+    }
+  });
+}
+
+fn answer(mut stream: TcpStream, store: &Arc<Mutex<Store>>, stale: f64) -> std::io::Result<()> {
+  stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+  stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut request = String::new();
+  reader.read_line(&mut request)?;
+  // Headers don't matter, but read them so the client isn't cut off
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 || line.trim().is_empty() { break }
+  }
+  let mut parts = request.split_whitespace();
+  let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+  let path = path.split('?').next().unwrap_or("");
+  let (status, ctype, body) = match (method, path) {
+    ("GET", "/metrics") | ("HEAD", "/metrics") => {
+      let body = { let store = store.lock().unwrap(); render(&store, stale) };
+      ("200 OK", "text/plain; version=0.0.4; charset=utf-8", body)
+    }
+    ("GET", _) | ("HEAD", _) => ("404 Not Found", "text/plain", "Try /metrics\n".to_owned()),
+    _ => ("405 Method Not Allowed", "text/plain", "Only GET\n".to_owned()),
+  };
+  let head = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+    status, ctype, body.len());
+  stream.write_all(head.as_bytes())?;
+  if method != "HEAD" { stream.write_all(body.as_bytes())?; }
+  stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::metrics::Sample,
+  };
+
+  #[test]
+  fn replayed_samples_are_served() {
+    let mut store = Store::new();
+    // A recording from 2023, long before the clock says it is now
+    store.record(1_700_000_000.0, &[
+      Sample::new("mem.used", 4096.0),
+      Sample::new("disk./home.avail", 10.0),
+      Sample::new("net.eth0.rx", 99.0),
+    ]);
+    store.record(1_700_000_100.0, &[Sample::new("mem.used", 8192.0)]);
+    let page = render(&store, 60.0);
+    assert_eq!(page, "\
+# HELP raumen_memory_used_bytes Memory in use
+# TYPE raumen_memory_used_bytes gauge
+raumen_memory_used_bytes 8192
+");
+    let page = render(&store, 300.0);
+    assert!(page.contains("raumen_filesystem_avail_bytes{mount=\"/home\"} 10\n"));
+    assert!(page.contains("# TYPE raumen_network_receive_bytes_total counter\nraumen_network_receive_bytes_total{interface=\"eth0\"} 99\n"));
+  }
+
+  #[test]
+  fn reads_back_what_it_serves() {
+    let mut store = Store::new();
+    store.record(100.0, &[Sample::new("disk./a \"b\".used", 1.5), Sample::new("load.1", 0.25)]);
+    let scraped = parse_exposition(&render(&store, 60.0));
+    assert_eq!(scraped, vec![
+      Scraped { name: "raumen_filesystem_used_bytes".to_owned(), labels: vec![("mount".to_owned(), "/a \"b\"".to_owned())], value: 1.5 },
+      Scraped { name: "raumen_load_average".to_owned(), labels: vec![("period".to_owned(), "1".to_owned())], value: 0.25 },
+    ]);
+  }
+
+  #[test]
+  fn names_are_made_legal() {
+    assert_eq!(sanitize("9lives.x-y"), "_9lives_x_y");
+    assert_eq!(family("mem_pct"), ("raumen_mem_pct".to_owned(), Kind::Gauge, "Metric mem_pct".to_owned()));
+    assert_eq!(parse_value("+Inf"), Some(f64::INFINITY));
+  }
+}
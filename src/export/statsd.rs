@@ -0,0 +1,232 @@
+
+use {
+  std::{
+    net::UdpSocket,
+  },
+  crate::{
+    config::Section,
+    export::{prometheus::{self, Kind}, split_labels, Sink},
+    metrics::{glob_match, Sample},
+    snapshot::hostname,
+    util::HashMap,
+  },
+};
+
+pub const DEFAULT_HOST: &str = "127.0.0.1:8125";
+/// Fits an IPv4 packet on ethernet with room to spare
+pub const DEFAULT_MTU: usize = 1432;
+
+/// Pushes every sample to a StatsD server over UDP. Counters (the network
+/// byte counts) go out as `|c` with the change since the last push,
+/// everything else as `|g` gauges.
+///
+///   [statsd]
+///   host = 127.0.0.1:8125
+///   prefix = raumen.
+///   tags = env:prod, team:ops
+///   dogstatsd = true
+///   mtu = 1432
+///   metrics = cpu.*, mem.*, disk.*
+///
+/// With `dogstatsd` the per mount and per interface parts of a name become
+/// tags, along with `host` and the `tags` given. Without it names are sent
+/// whole and tags are left off, since plain StatsD doesn't know them.
+pub struct Statsd {
+  pub host: String,
+  pub prefix: String,
+  pub tags: Vec<String>,
+  pub dogstatsd: bool,
+  pub mtu: usize,
+  pub metrics: Vec<String>,
+  socket: UdpSocket,
+  last: HashMap<String, f64>,
+  failing: bool,
+}
+impl Statsd {
+  pub fn from_section(section: &Section) -> Result<Self, String> {
+    let host = section.get_str("host", DEFAULT_HOST);
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+    // Connecting a UDP socket only fixes where it sends, nothing goes out yet
+    socket.connect(&host).map_err(|e| format!("{}: {}", host, e))?;
+    let dogstatsd = section.get_or("dogstatsd", true);
+    let mut tags: Vec<String> = section.get("tags").map(|t| {
+      t.split(',').map(|t| t.trim().to_owned()).filter(|t| !t.is_empty()).collect()
+    }).unwrap_or_default();
+    if dogstatsd { tags.insert(0, format!("host:{}", tag_value(&hostname()))); }
+    Ok(Self {
+      host,
+      prefix: section.get_str("prefix", ""),
+      tags,
+      dogstatsd,
+      mtu: section.get_or("mtu", DEFAULT_MTU).max(64),
+      metrics: section.get("metrics").map(|m| {
+        m.split(',').map(|g| g.trim().to_owned()).filter(|g| !g.is_empty()).collect()
+      }).unwrap_or_default(),
+      socket,
+      last: HashMap::new(),
+      failing: false,
+    })
+  }
+  fn wanted(&self, name: &str) -> bool {
+    self.metrics.is_empty() || self.metrics.iter().any(|g| glob_match(g, name))
+  }
+  /// The StatsD line for one sample, if there's anything to send
+  pub fn line(&mut self, sample: &Sample) -> Option<String> {
+    if !sample.value.is_finite() { return None }
+    let (base, labels) = split_labels(&sample.name);
+    let (value, kind) = match prometheus::family(&base).1 {
+      Kind::Counter => {
+        let last = self.last.insert(sample.name.clone(), sample.value);
+        // A counter that went backwards was reset, wait for the next push
+        match last {
+          Some(last) if sample.value >= last => (sample.value - last, "c"),
+          _ => return None,
+        }
+      }
+      Kind::Gauge => (sample.value, "g"),
+    };
+    if !self.dogstatsd {
+      return Some(format!("{}{}:{}|{}", self.prefix, stat_name(&sample.name), value, kind))
+    }
+    let mut tags: Vec<String> = labels.iter().map(|(k, v)| format!("{}:{}", k, tag_value(v))).collect();
+    tags.extend(self.tags.iter().cloned());
+    let mut line = format!("{}{}:{}|{}", self.prefix, stat_name(&base), value, kind);
+    if !tags.is_empty() { line.push_str(&format!("|#{}", tags.join(","))); }
+    Some(line)
+  }
+}
+impl Sink for Statsd {
+  fn push(&mut self, _ts: f64, samples: &[Sample]) {
+    let mut lines = Vec::new();
+    for sample in samples {
+      if !self.wanted(&sample.name) { continue }
+      if let Some(line) = self.line(sample) { lines.push(line) }
+    }
+    for packet in batch(&lines, self.mtu) {
+      match self.socket.send(packet.as_bytes()) {
+        Ok(_) => self.failing = false,
+        // Nobody listening is normal for UDP, so only say so once
+        Err(e) => {
+          if !self.failing { println!("StatsD: send to {} failed: {}", self.host, e); }
+          self.failing = true;
+        }
+      }
+    }
+  }
+}
+
+/// Packs lines into newline separated packets of at most `mtu` bytes. A
+/// line too long to share a packet goes in one of its own.
+pub fn batch(lines: &[String], mtu: usize) -> Vec<String> {
+  let mut out = Vec::new();
+  let mut packet = String::new();
+  for line in lines {
+    if !packet.is_empty() && packet.len() + 1 + line.len() > mtu {
+      out.push(std::mem::take(&mut packet));
+    }
+    if !packet.is_empty() { packet.push('\n'); }
+    packet.push_str(line);
+  }
+  if !packet.is_empty() { out.push(packet); }
+  out
+}
+
+// `:`, `|` and `@` mean something in a StatsD line, and slashes from mount
+// paths make a mess of dotted hierarchies
+fn stat_name(name: &str) -> String {
+  name.chars().map(|c| match c {
+    ':' | '|' | '@' | '/' | '#' => '_',
+    c if c.is_whitespace() => '_',
+    c => c,
+  }).collect()
+}
+
+fn tag_value(val: &str) -> String {
+  val.chars().map(|c| match c {
+    ',' | '|' | '#' => '_',
+    c if c.is_whitespace() => '_',
+    c => c,
+  }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    std::time::Duration,
+    crate::config::Config,
+  };
+
+  fn listen() -> (UdpSocket, String) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    (socket, addr)
+  }
+
+  fn statsd(options: &str) -> Statsd {
+    let config = Config::parse(&format!("[statsd]\n{}", options));
+    Statsd::from_section(config.section("statsd").unwrap()).unwrap()
+  }
+
+  fn received(socket: &UdpSocket) -> Vec<String> {
+    let mut buf = [0u8; 65536];
+    let mut out = Vec::new();
+    let n = socket.recv(&mut buf).unwrap();
+    out.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+    // Anything else from the same push is already on its way
+    socket.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    while let Ok(n) = socket.recv(&mut buf) {
+      out.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+    }
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    out
+  }
+
+  #[test]
+  fn sends_plain_lines() {
+    let (socket, addr) = listen();
+    let mut statsd = statsd(&format!("host = {}\nprefix = raumen.\ndogstatsd = false\nmetrics = mem.*, net.*", addr));
+    statsd.push(0.0, &[
+      Sample::new("mem.used", 1024.0),
+      Sample::new("cpu.usage", 12.5),
+      Sample::new("net.eth0.rx", 1000.0),
+    ]);
+    // The counter has nothing to compare with on the first push
+    assert_eq!(received(&socket), vec!["raumen.mem.used:1024|g"]);
+    statsd.push(1.0, &[Sample::new("mem.used", 2048.0), Sample::new("net.eth0.rx", 1500.0)]);
+    assert_eq!(received(&socket), vec!["raumen.mem.used:2048|g\nraumen.net.eth0.rx:500|c"]);
+  }
+
+  #[test]
+  fn dogstatsd_tags() {
+    let (socket, addr) = listen();
+    let mut statsd = statsd(&format!("host = {}\ntags = env:prod", addr));
+    statsd.push(0.0, &[Sample::new("disk./home.used", 5.0)]);
+    let host = tag_value(&hostname());
+    assert_eq!(received(&socket), vec![format!("disk.used:5|g|#mount:/home,host:{},env:prod", host)]);
+  }
+
+  #[test]
+  fn splits_packets_at_the_mtu() {
+    let (socket, addr) = listen();
+    let mut statsd = statsd(&format!("host = {}\ndogstatsd = false\nmtu = 64", addr));
+    let samples: Vec<Sample> = (0..10).map(|i| Sample::new(&format!("custom.metric{}", i), i as f64)).collect();
+    statsd.push(0.0, &samples);
+    let packets = received(&socket);
+    // Each line is 18 bytes, so three fit with their newlines and a fourth doesn't
+    assert_eq!(packets.len(), 4);
+    assert!(packets.iter().all(|p| p.len() <= 64));
+    let lines: Vec<&str> = packets.iter().flat_map(|p| p.split('\n')).collect();
+    let want: Vec<String> = (0..10).map(|i| format!("custom.metric{}:{}|g", i, i)).collect();
+    assert_eq!(lines, want);
+  }
+
+  #[test]
+  fn long_lines_get_their_own_packet() {
+    let long = "x".repeat(100);
+    let lines = vec!["a:1|g".to_owned(), long.clone(), "b:2|g".to_owned(), "c:3|g".to_owned()];
+    assert_eq!(batch(&lines, 64), vec!["a:1|g".to_owned(), long, "b:2|g\nc:3|g".to_owned()]);
+    assert!(batch(&[], 64).is_empty());
+  }
+}
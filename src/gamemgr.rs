@@ -81,6 +81,13 @@ impl GameMgr {
     // println!("texture: image<{}> tex_id<{}>", name, texture.tex_id);
     hm.insert(name.to_string(), Arc::new(texture));
   }
+  /// Puts an already decoded image in place of the texture called `name`,
+  /// freeing the old one
+  pub fn replace_texture(&mut self, name: &str, img: image::RgbaImage) {
+    let texture = self.loader.lock().unwrap().upload_texture(name, img);
+    let old = self.textures.lock().unwrap().insert(name.to_string(), Arc::new(texture));
+    if let Some(old) = old { self.loader.lock().unwrap().rm_texture(old.tex_id); }
+  }
   pub fn texture(&self, name: &str) -> Arc<Texture> {
     let _arc = self.textures.clone();
     let mut hm = _arc.lock().unwrap();
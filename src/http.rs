@@ -0,0 +1,268 @@
+
+use {
+  std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+  },
+};
+
+// Just enough HTTP to post a webhook or fetch a page. Requests go out as
+// HTTP/1.0 so servers answer with a plain body instead of chunks, but
+// chunked answers are still undone for servers that send them anyway.
+
+/// Biggest body read before giving up on the rest
+pub const MAX_BODY: usize = 8 * 1024 * 1024;
+
+/// Only plain http is spoken, put a local proxy in front of anything that
+/// needs TLS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+  pub host: String,
+  pub port: u16,
+  pub path: String,
+}
+impl Url {
+  pub fn parse(url: &str) -> Result<Self, String> {
+    let rest = match url.strip_prefix("http://") {
+      Some(rest) => rest,
+      None if url.starts_with("https://") => return Err("https isn't supported, only http".to_owned()),
+      None => return Err(format!("not an http url: {}", url)),
+    };
+    let (hostport, path) = match rest.find('/') {
+      Some(idx) => (&rest[..idx], &rest[idx..]),
+      None => (rest, "/"),
+    };
+    let (host, port) = match hostport.rfind(':') {
+      // Leave bracketed IPv6 addresses without a port alone
+      Some(idx) if !hostport[idx..].contains(']') => {
+        let port = hostport[idx + 1..].parse().map_err(|_| format!("bad port in {}", url))?;
+        (&hostport[..idx], port)
+      }
+      _ => (hostport, 80),
+    };
+    if host.is_empty() { return Err(format!("no host in {}", url)) }
+    Ok(Self { host: host.to_owned(), port, path: path.to_owned() })
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+  pub status: u16,
+  /// The whole status line, like `HTTP/1.1 404 Not Found`
+  pub status_line: String,
+  pub headers: Vec<(String, String)>,
+  pub body: String,
+}
+impl Response {
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+  }
+}
+
+/// Sends a request, with `body` as `(content type, content)`, and reads the
+/// whole answer. `timeout` applies to connecting and to each read or write.
+pub fn request(method: &str, url: &Url, body: Option<(&str, &str)>, timeout: Duration) -> io::Result<Response> {
+  let mut stream = connect(url, timeout)?;
+  stream.set_read_timeout(Some(timeout))?;
+  stream.set_write_timeout(Some(timeout))?;
+  let mut req = format!("{} {} HTTP/1.0\r\nHost: {}:{}\r\nUser-Agent: raumEnSysInfo\r\nConnection: close\r\n",
+    method, url.path, url.host, url.port);
+  if let Some((ctype, content)) = body {
+    req.push_str(&format!("Content-Type: {}\r\nContent-Length: {}\r\n\r\n{}", ctype, content.len(), content));
+  } else {
+    req.push_str("\r\n");
+  }
+  stream.write_all(req.as_bytes())?;
+  let mut raw = Vec::new();
+  let mut buf = [0u8; 8192];
+  loop {
+    match stream.read(&mut buf) {
+      Ok(0) => break,
+      Ok(n) => {
+        raw.extend_from_slice(&buf[..n]);
+        if raw.len() > MAX_BODY { break }
+      }
+      // A timeout or reset part way leaves a body that can't be trusted
+      Err(e) => return Err(e),
+    }
+  }
+  parse_response(&raw)
+}
+
+fn connect(url: &Url, timeout: Duration) -> io::Result<TcpStream> {
+  let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("{} didn't resolve", url.host));
+  // `[::1]` keeps its brackets for the Host header, but resolving wants
+  // the bare address
+  let host = url.host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(&url.host);
+  for addr in (host, url.port).to_socket_addrs()? {
+    match TcpStream::connect_timeout(&addr, timeout) {
+      Ok(stream) => return Ok(stream),
+      Err(e) => last_err = e,
+    }
+  }
+  Err(last_err)
+}
+
+fn parse_response(raw: &[u8]) -> io::Result<Response> {
+  let bad = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_owned());
+  let split = raw.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(|| bad("no end to the headers"))?;
+  let head = String::from_utf8_lossy(&raw[..split]);
+  let mut lines = head.lines();
+  let status_line = lines.next().unwrap_or("").to_owned();
+  let status = status_line.split_whitespace().nth(1).and_then(|c| c.parse().ok())
+    .ok_or_else(|| bad(&format!("bad status line: {}", status_line)))?;
+  let headers: Vec<(String, String)> = lines.filter_map(|l| {
+    let colon = l.find(':')?;
+    Some((l[..colon].trim().to_owned(), l[colon + 1..].trim().to_owned()))
+  }).collect();
+  let mut out = Response { status, status_line, headers, body: String::new() };
+  let body = &raw[split + 4..];
+  let chunked = out.header("Transfer-Encoding").map(|te| te.eq_ignore_ascii_case("chunked")).unwrap_or(false);
+  out.body = if chunked {
+    String::from_utf8_lossy(&dechunk(body)).into_owned()
+  } else {
+    String::from_utf8_lossy(body).into_owned()
+  };
+  Ok(out)
+}
+
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+  let mut out = Vec::new();
+  while let Some(eol) = body.windows(2).position(|w| w == b"\r\n") {
+    let size = String::from_utf8_lossy(&body[..eol]);
+    let size = usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16).unwrap_or(0);
+    body = &body[eol + 2..];
+    if size == 0 || size > body.len() { out.extend_from_slice(&body[..size.min(body.len())]); break }
+    out.extend_from_slice(&body[..size]);
+    body = body.get(size + 2..).unwrap_or(&[]);
+  }
+  out
+}
+
+#[cfg(test)]
+pub mod tests {
+  use {
+    super::*,
+    std::{
+      net::TcpListener,
+      sync::mpsc::{channel, Receiver},
+      thread,
+    },
+  };
+
+  /// Answers one connection per response in turn, handing back each request
+  /// it got, headers and body. The connection is closed after each answer.
+  pub fn serve(responses: Vec<String>) -> (Url, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+      for resp in responses {
+        let (mut stream, _) = match listener.accept() { Ok(conn) => conn, Err(_) => return };
+        let _ = tx.send(read_request(&mut stream));
+        let _ = stream.write_all(resp.as_bytes());
+      }
+    });
+    (Url { host: "127.0.0.1".to_owned(), port, path: "/".to_owned() }, rx)
+  }
+
+  fn read_request(stream: &mut TcpStream) -> String {
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut raw = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+      if let Some(end) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+        let head = String::from_utf8_lossy(&raw[..end]).to_lowercase();
+        let len = head.lines().filter_map(|l| l.strip_prefix("content-length:")).next()
+          .and_then(|l| l.trim().parse().ok()).unwrap_or(0);
+        if raw.len() >= end + 4 + len { break }
+      }
+      match stream.read(&mut buf) {
+        Ok(0) | Err(_) => break,
+        Ok(n) => raw.extend_from_slice(&buf[..n]),
+      }
+    }
+    String::from_utf8_lossy(&raw).into_owned()
+  }
+
+  pub fn answer(status: &str, body: &str) -> String {
+    format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body)
+  }
+
+  #[test]
+  fn urls() {
+    assert_eq!(Url::parse("http://example.com"), Ok(Url { host: "example.com".to_owned(), port: 80, path: "/".to_owned() }));
+    assert_eq!(Url::parse("http://[::1]:9100/metrics?x=1"), Ok(Url { host: "[::1]".to_owned(), port: 9100, path: "/metrics?x=1".to_owned() }));
+    assert_eq!(Url::parse("http://[::1]/"), Ok(Url { host: "[::1]".to_owned(), port: 80, path: "/".to_owned() }));
+    assert!(Url::parse("https://example.com").is_err());
+    assert!(Url::parse("http://host:port/").is_err());
+    assert!(Url::parse("http:///path").is_err());
+  }
+
+  #[test]
+  fn connects_to_ipv6() {
+    // Not every sandbox has a loopback IPv6 address
+    let listener = match TcpListener::bind("[::1]:0") { Ok(listener) => listener, Err(_) => return };
+    let url = Url::parse(&format!("http://[::1]:{}/metrics", listener.local_addr().unwrap().port())).unwrap();
+    let server = thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let req = read_request(&mut stream);
+      stream.write_all(answer("200 OK", "up 1\n").as_bytes()).unwrap();
+      req
+    });
+    let resp = request("GET", &url, None, Duration::from_secs(5)).unwrap();
+    assert_eq!((resp.status, resp.body.as_str()), (200, "up 1\n"));
+    assert!(server.join().unwrap().contains(&format!("\r\nHost: [::1]:{}\r\n", url.port)));
+  }
+
+  #[test]
+  fn sends_request_and_reads_answer() {
+    let (mut url, requests) = serve(vec![answer("201 Created", "made it")]);
+    url.path = "/hooks/a".to_owned();
+    let resp = request("POST", &url, Some(("application/json", "{\"a\":1}")), Duration::from_secs(5)).unwrap();
+    assert_eq!((resp.status, resp.status_line.as_str(), resp.body.as_str()), (201, "HTTP/1.1 201 Created", "made it"));
+    assert_eq!(resp.header("content-length"), Some("7"));
+    let req = requests.recv().unwrap();
+    let (head, body) = req.split_at(req.find("\r\n\r\n").unwrap());
+    let lines: Vec<&str> = head.lines().collect();
+    assert_eq!(lines[0], "POST /hooks/a HTTP/1.0");
+    assert!(lines.contains(&format!("Host: 127.0.0.1:{}", url.port).as_str()));
+    assert!(lines.contains(&"Content-Type: application/json"));
+    assert!(lines.contains(&"Content-Length: 7"));
+    assert_eq!(body, "\r\n\r\n{\"a\":1}");
+  }
+
+  #[test]
+  fn undoes_chunking() {
+    let (url, _requests) = serve(vec![
+      "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7;x=y\r\n, world\r\n0\r\n\r\n".to_owned(),
+    ]);
+    let resp = request("GET", &url, None, Duration::from_secs(5)).unwrap();
+    assert_eq!(resp.body, "hello, world");
+  }
+
+  #[test]
+  fn cut_off_answers_are_errors() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = Url { host: "127.0.0.1".to_owned(), port: listener.local_addr().unwrap().port(), path: "/".to_owned() };
+    let (tx, rx) = channel::<()>();
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      read_request(&mut stream);
+      let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nhalf of it");
+      // Hold the connection open until the client has given up on it
+      let _ = rx.recv();
+    });
+    let err = request("GET", &url, None, Duration::from_millis(200)).unwrap_err();
+    assert!(matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut));
+    let _ = tx.send(());
+  }
+
+  #[test]
+  fn bad_answers() {
+    let (url, _requests) = serve(vec!["HTTP/1.1 200 OK\r\nno end".to_owned(), "nonsense\r\n\r\n".to_owned()]);
+    assert!(request("GET", &url, None, Duration::from_secs(5)).is_err());
+    assert!(request("GET", &url, None, Duration::from_secs(5)).is_err());
+  }
+}
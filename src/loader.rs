@@ -67,16 +67,13 @@ impl Loader {
     BindVertexArray(0_u32);
   }}
   pub fn load_texture(&mut self, tex_name: &str) -> Texture {
-    // use image;
-    use std::path::Path;
-    let path: &str = &format!("res/img/{}.png", tex_name);
-    let img = match image::open(&Path::new(path)) {
-      Ok(image) => {
-        // println!("Image loaded");
-        image.to_rgba()
-      },
-      _ => panic!("Failed to load image")
-    };
+    match decode_texture(tex_name) {
+      Ok(img) => self.upload_texture(tex_name, img),
+      Err(e) => panic!("Failed to load image: {}", e),
+    }
+  }
+  /// Hands an image from `decode_texture` to GL
+  pub fn upload_texture(&mut self, tex_name: &str, img: image::RgbaImage) -> Texture {
     let (width, height) = img.dimensions();
     let img_raw = img.into_raw();
     let mut tex_id: GLuint = 0;
@@ -107,6 +104,13 @@ impl Loader {
         break; } }
     unsafe { DeleteVertexArrays(1_i32, &id); }
   }
+  pub fn rm_texture(&mut self, id: u32) {
+    for i in 0..self.textures.len() {
+      if self.textures[i] == id {
+        self.textures.remove(i);
+        break; } }
+    unsafe { DeleteTextures(1_i32, &id); }
+  }
   pub fn clean_up(&mut self) { unsafe {
     for vao in &self.vaos {
       DeleteVertexArrays(1_i32, vao);
@@ -120,6 +124,13 @@ impl Loader {
   }}
 }
 
+/// Reads and decodes `res/img/<tex_name>.png` without touching GL, so a bad
+/// image can be turned down before anything is replaced
+pub fn decode_texture(tex_name: &str) -> Result<image::RgbaImage, String> {
+  let path = format!("res/img/{}.png", tex_name);
+  image::open(&path).map(|img| img.to_rgba8()).map_err(|e| format!("{}: {}", path, e))
+}
+
 pub fn verts_pos_to_glfloats_2d(verts: &[RVertex2D]) -> Vec<GLfloat> {
   let mut out = Vec::new();
   for vert in verts {
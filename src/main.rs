@@ -11,17 +11,19 @@ extern crate cupid;
 extern crate sysinfo;
 extern crate systemstat;
 extern crate bytesize;
-// extern crate subprocess;
+extern crate regex;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate subprocess;
 
 use {
+  std::io::Write,
   gl::*,
   glutin::{
     // dpi::*,
     event::{Event, WindowEvent, Event::DeviceEvent, },
     event_loop::{ControlFlow, EventLoop, },
   },
-  sysinfo::SystemExt,
-  systemstat::{System, Platform},
   // crate::{
   //   // render::{ * },
   //   // shader::{ Shader, },
@@ -29,16 +31,36 @@ use {
 };
 
 // in project stuff
+// Everything printed is masked first when redaction is on, see redact.rs
+macro_rules! println {
+  () => (::std::println!());
+  ($($arg:tt)*) => (::std::println!("{}", crate::redact::redact(&format!($($arg)*))));
+}
+
+pub mod alert;
+pub mod args;
+pub mod config;
+pub mod control;
+pub mod diff;
 pub mod display; // I think I still need this for storing window dimensions
+pub mod export;
 pub mod gamemgr;
+pub mod http;
 pub mod input;
 pub mod loader; // Can be simplified
+pub mod metrics;
+pub mod redact;
+pub mod remote;
+pub mod report;
 pub mod render;
 pub mod shader;
+pub mod snapshot;
+pub mod stats;
 pub mod text;
 pub mod texture; // needed for font atlas but needed things can be ported out
 pub mod timer;
 pub mod util;
+pub mod widget;
 
 pub use display::Display;
 pub use input::Handler;
@@ -47,11 +69,144 @@ pub use render::{RenderMgr, };
 pub use shader::Shader;
 pub use timer::Timer;
 
+use {
+  glutin::event::VirtualKeyCode as VKC,
+  alert::{fmt_secs, Alerts, Hooks, ALERT_LABEL, },
+  args::{Args, USAGE, },
+  config::Config,
+  control::Control,
+  diff::Tolerances,
+  export::{prometheus, Sink, },
+  input::KeyCode,
+  snapshot::{Format, Snapshot, },
+  metrics::{Collector, Derived, Filters, History, Recorder, Replay, Sample, Store, now_secs, },
+  remote::{Agent, Grid, view_widgets, },
+  stats::{ProcTree, Sessions, sessions::UTMP_PATH, },
+  util::{Arc, Mutex, },
+  widget::Widgets,
+};
+
+// Labels that make up the main page, hidden while another page is up
+const MAIN_LABELS: [&str; 2] = ["CPU RAM HDD", "Sessions"];
+
 fn main() {
   // Test code for parsing fnt files
   // use text::metafile::test_noms;
   // test_noms();
   
+  let args = match Args::from_env() {
+    Ok(args) => args,
+    Err(e) => { println!("{}\n\n{}", e, USAGE); std::process::exit(2) }
+  };
+  if args.help { println!("{}", USAGE); return }
+  let config = Config::from_args(&args);
+  redact::init(&config);
+  for addr in &args.view { redact::add_host(redact::host_part(addr)); }
+  let mut history = History::from_section(config.section("history"));
+  if let Some(ref pattern) = args.dump_history {
+    let stdout = std::io::stdout();
+    if let Err(e) = history.dump(pattern, &mut stdout.lock()) { println!("History: {}", e); }
+    return
+  }
+  if args.once {
+    // Ignore write errors so piping into `head` doesn't end in a panic
+    let text = Snapshot::take(&config).render(args.format.unwrap_or(Format::Text));
+    let _ = writeln!(std::io::stdout(), "{}", redact::redact(&text));
+    return
+  }
+  if let Some((ref old, ref new)) = args.diff {
+    let (old, new) = match (Snapshot::load(&old.to_string_lossy()), Snapshot::load(&new.to_string_lossy())) {
+      (Ok(old), Ok(new)) => (old, new),
+      (Err(e), _) | (_, Err(e)) => { println!("Diff: {}", e); std::process::exit(2) }
+    };
+    let changes = diff::diff(&old, &new, &Tolerances::from_config(&config));
+    println!("{}", diff::header(&old, &new));
+    if changes.is_empty() { println!("No changes"); return }
+    for change in &changes { println!("  {}", change); }
+    std::process::exit(1)
+  }
+  if let Some(ref path) = args.report {
+    match report::write(path, &config) {
+      Ok(()) => println!("Report written to {}", path.display()),
+      Err(e) => { println!("Report: {}", e); std::process::exit(1) }
+    }
+    return
+  }
+  
+  let mut collector = Collector::from_config(&config);
+  if let Some(ref path) = args.replay {
+    match Replay::load(path, args.speed.unwrap_or(1.0)) {
+      Ok(replay) => {
+        redact::add_host(&replay.host);
+        println!("Replay: {} ticks over {} from {}", replay.tick_count(), fmt_secs(replay.duration()), replay.host);
+        collector.replay = Some(replay);
+      }
+      Err(e) => { println!("Replay: {}", e); std::process::exit(1) }
+    }
+  }
+  if let Some(ref path) = args.record {
+    match Recorder::create(path, &Snapshot::describe(&collector)) {
+      Ok(recorder) => collector.recorder = Some(recorder),
+      Err(e) => { println!("Record: {}", e); std::process::exit(1) }
+    }
+  }
+  // Made up or replayed metrics have no business in the real history, and
+  // only go anywhere else that people or other machines act on if asked to
+  if !collector.is_live() { history.enabled = false; }
+  let publish = collector.publishes(args.publish);
+  if !publish { println!("Not live: alert hooks, exports and the grid tile are off, --publish turns them on"); }
+  let store = Arc::new(Mutex::new(Store::from_section(config.section("store"))));
+  if history.enabled {
+    let mut store = store.lock().unwrap();
+    let count = history.load_into(&mut store, now_secs());
+    println!("Loaded {} samples of history from {}", count, history.dir.display());
+  }
+  let mut filters = Filters::from_config(&config);
+  let mut derived = Derived::from_section(config.section("derived"));
+  let mut sinks = if publish { export::sinks(&config) } else { Vec::new() };
+  sample(&mut collector, &mut filters, &store, &mut derived, &mut history, &mut sinks);
+  if let Some(section) = config.section("prometheus").filter(|_| publish) { prometheus::serve(section, store.clone()); }
+  let mut alerts = Alerts::from_config(&config);
+  let mut hooks = Hooks::from_config(&config);
+  hooks.enabled = publish;
+  let mut grid = config.section("grid").map(Grid::from_section);
+  // Other hosts' tiles can still be looked at
+  if let Some(ref mut grid) = grid { grid.publish &= publish; }
+  if let Some(ref listen) = args.agent {
+    let listen = match listen.as_str() {
+      "" => config.section("agent").map(|s| s.get_str("listen", "")).unwrap_or_default(),
+      addr => addr.to_owned(),
+    };
+    let listen = remote::with_port(if listen.is_empty() { "127.0.0.1" } else { &listen });
+    let agent = match Agent::listen(&listen) {
+      Ok(agent) => agent,
+      Err(e) => { println!("Agent: can't listen on {}: {}", listen, e); std::process::exit(1) }
+    };
+    println!("Agent: serving snapshots on {}", agent.addr);
+    // No window to show alerts in, but their hooks still run
+    loop {
+      if args.speed != Some(0.0) { std::thread::sleep(std::time::Duration::from_secs(1)); }
+      for _ in 0..collector.due() {
+        let samples = sample(&mut collector, &mut filters, &store, &mut derived, &mut history, &mut sinks);
+        let now = collector.time();
+        {
+          let store = store.lock().unwrap();
+          hooks.notify(&alerts.evaluate(&store, now));
+        }
+        let mut snapshot = Snapshot::from_samples(&collector, now, samples.iter());
+        snapshot.alerts = alerts.firing();
+        agent.broadcast(&snapshot);
+        if let Some(ref mut grid) = grid { grid.publish(&snapshot, now_secs()); }
+      }
+      // Headless replays are for checking alert rules, so there's no point
+      // carrying on once the data runs out
+      if collector.replay.as_ref().map(|r| r.finished()).unwrap_or(false) {
+        println!("Replay: finished");
+        return
+      }
+    }
+  }
+  
   // Specify OpenGL version
   let gl_request = glutin::GlRequest::Specific(glutin::Api::OpenGl, (4, 3));
   let gl_profile = glutin::GlProfile::Core;
@@ -77,12 +232,27 @@ fn main() {
   let mut render_mgr = RenderMgr::new();
   let mut mgr = render_mgr.mgr.clone();
   
-  let mut system = sysinfo::System::new();
-  
-  let cpu = cpu_name();
-  let ram = get_ram_total(&mut system);
-  let cpu_ram = mk_cpu_ram_str(&cpu, &ram, &mut system);
-  let hdd = get_hdd();
+  let cpu = cpu_name(&collector);
+  let ram = get_ram_total(&store);
+  let cpu_ram = mk_cpu_ram_str(&cpu, &ram, &store);
+  let mut proc_tree = ProcTree::new();
+  let mut show_procs = false;
+  let mut show_grid = grid.as_ref().map(|g| g.show).unwrap_or(false);
+  // Starting on the grid page means hiding the main one straight away
+  let mut page_changed = show_grid;
+  let mut sessions = match config.section("sessions") {
+    Some(section) => Sessions::with_path(&section.get_str("utmp", UTMP_PATH)),
+    None => Sessions::new(),
+  };
+  sessions.refresh();
+  let control = config.section("control").and_then(|section| match Control::from_section(section) {
+    Ok(control) => { println!("Control: listening on {}", control.path.display()); Some(control) }
+    Err(e) => { println!("Config: [control] {}", e); None }
+  });
+  let mut widgets = Widgets::from_config(&config);
+  for widget in view_widgets(&args.view) { widgets.widgets.push(Box::new(widget)); }
+  // A viewer is there for the remote panels, which go where the local ones were
+  let main_labels: &[&str] = if args.view.is_empty() { &MAIN_LABELS } else { &[] };
   
   let mut fps: f32 = 30.0;
   let mut once_per_sec = false;
@@ -104,8 +274,13 @@ fn main() {
     textmgr.add_font(mgr.clone(), "pirate");
     textmgr.add_font(mgr.clone(), "sans");
     textmgr.new_text(mgr.clone(), "Title", "SysInfo", "pirate", 4.0, 0.0, 0.0, 1.0, true, true);
-    textmgr.new_text(mgr.clone(), "CPU RAM HDD", &[cpu_ram, hdd.clone()].join(""), "sans", 2.0, 0.0, 0.4, 1.0, true, true);
+    let show_main = args.view.is_empty();
+    textmgr.new_text(mgr.clone(), "CPU RAM HDD", &[cpu_ram, get_hdd(&store)].join(""), "sans", 2.0, 0.0, 0.4, 1.0, true, show_main);
     textmgr.new_text(mgr.clone(), "FPS", "FPS: 0.0", "sans", 1.5, 0.0, 0.0, 0.3, false, true);
+    textmgr.new_text(mgr.clone(), "Sessions", &sessions.text(), "sans", 1.0, 0.02, 0.75, 0.96, false, show_main);
+    widgets.add_labels(&mut textmgr, mgr.clone());
+    textmgr.new_text(mgr.clone(), ALERT_LABEL, "Alerts", "sans", 1.0, 0.6, 0.05, 0.38, false, false);
+    textmgr.new_text(mgr.clone(), "Process Tree", "", "sans", 1.0, 0.02, 0.12, 0.96, false, false);
   }
   
   // Game loop!
@@ -153,18 +328,89 @@ fn main() {
             fps = handler.timer.fps;
             once_per_sec = true;
           }
+          let mut procs_changed = false;
+          if handler.read_kb_single(KeyCode::new(VKC::P)) {
+            show_procs = !show_procs;
+            show_grid = false;
+            page_changed = true;
+            if show_procs { proc_tree.refresh(); }
+          }
+          if grid.is_some() && handler.read_kb_single(KeyCode::new(VKC::G)) {
+            show_grid = !show_grid;
+            show_procs = false;
+            page_changed = true;
+          }
+          if show_procs {
+            if handler.read_kb_single(KeyCode::new(VKC::Tab)) { proc_tree.toggle_group_by(); procs_changed = true; }
+            if handler.read_kb_single(KeyCode::new(VKC::Up)) { proc_tree.select_prev(); procs_changed = true; }
+            if handler.read_kb_single(KeyCode::new(VKC::Down)) { proc_tree.select_next(); procs_changed = true; }
+            if handler.read_kb_single(KeyCode::new(VKC::Return)) { proc_tree.toggle_selected(); procs_changed = true; }
+          }
+          if procs_changed || page_changed {
+            let _textmgr = mgr.clone().textmgr.take().unwrap();
+            let mut textmgr = _textmgr.lock().unwrap();
+            textmgr.update_text(mgr.clone(), "Process Tree", &proc_tree.text());
+            if show_procs { textmgr.enable_label(mgr.clone(), "Process Tree"); }
+            else { textmgr.disable_label("Process Tree"); }
+          }
+          if page_changed {
+            page_changed = false;
+            let other_page = show_procs || show_grid;
+            let _textmgr = mgr.clone().textmgr.take().unwrap();
+            let mut textmgr = _textmgr.lock().unwrap();
+            for label in main_labels {
+              if other_page { textmgr.disable_label(label) } else { textmgr.enable_label(mgr.clone(), label) }
+            }
+            widgets.set_hidden(other_page, &mut textmgr, mgr.clone());
+            alerts.set_hidden(other_page, &mut textmgr, mgr.clone(), collector.time());
+            if let Some(ref mut grid) = grid {
+              if show_grid { grid.refresh(now_secs()); }
+              grid.set_hidden(!show_grid, &mut textmgr, mgr.clone());
+            }
+          }
         }
         if once_per_sec {
           once_per_sec = false;
           println!("Once per second FPS: {}", &format!("FPS: {:.3}", (fps * 1000.0).round() / 1000.0 ) );
-          let cpu_ram = mk_cpu_ram_str(&cpu, &ram, &mut system);
+          // Replays can be due several ticks at once, or none at all
+          let mut samples = Vec::new();
+          for _ in 0..collector.due() {
+            samples = sample(&mut collector, &mut filters, &store, &mut derived, &mut history, &mut sinks);
+            let store = store.lock().unwrap();
+            hooks.notify(&alerts.evaluate(&store, collector.time()));
+          }
+          if !samples.is_empty() && collector.replay.as_ref().map(|r| r.finished()).unwrap_or(false) {
+            println!("Replay: finished");
+          }
+          let cpu_ram = mk_cpu_ram_str(&cpu, &ram, &store);
           let _textmgr = mgr.clone().textmgr.take().unwrap();
           let mut textmgr = _textmgr.lock().unwrap();
-          textmgr.update_text(mgr.clone(), "CPU RAM HDD", &[cpu_ram, hdd.clone()].join(""));
+          textmgr.update_text(mgr.clone(), "CPU RAM HDD", &[cpu_ram, get_hdd(&store)].join(""));
           textmgr.update_text(mgr.clone(), "FPS", &format!("FPS: {:.3}", (fps * 1000.0).round() / 1000.0 ) );
+          sessions.refresh();
+          textmgr.update_text(mgr.clone(), "Sessions", &sessions.text());
+          if show_procs {
+            proc_tree.refresh();
+            textmgr.update_text(mgr.clone(), "Process Tree", &proc_tree.text());
+          }
+          let cleared = alerts.show(&mut textmgr, mgr.clone(), collector.time());
+          widgets.restore_colours(&mut textmgr, &cleared);
+          if let Some(ref mut grid) = grid {
+            // Tiles go stale by the files' real age, whatever's being replayed
+            let now = now_secs();
+            if !samples.is_empty() {
+              let mut snapshot = Snapshot::from_samples(&collector, collector.time(), samples.iter());
+              snapshot.alerts = alerts.firing();
+              grid.publish(&snapshot, now);
+            }
+            if show_grid {
+              grid.refresh(now);
+              grid.show(&mut textmgr, mgr.clone());
+            }
+          }
         }
-        
-        
+        widgets.update(mgr.clone(), &alerts);
+        if let Some(ref control) = control { control.apply(mgr.clone()); }
         
         windowed_context.window().request_redraw();
       }
@@ -207,14 +453,6 @@ pub fn eof(string: &str) -> String {
   [string, EOF].join("")
 }
 
-// pub fn call_cmd(cmd: &str) -> Result<String, String> {
-//     use subprocess::{Exec,Redirection};
-//     let out = Exec::shell(cmd)
-//         .stdout(Redirection::Pipe)
-//         .capture().map_err(|e|e.to_string())?
-//         .stdout_str();
-//     return Ok(out.trim().to_owned());
-// }
 // use nom::{multispace, rest_s};
 // named!(_cpu_name<&str, String>,
 //   do_parse!(
@@ -223,66 +461,55 @@ pub fn eof(string: &str) -> String {
 //   )
 // );
 
-fn mk_cpu_ram_str(cpu: &str, ram: &str, system: &mut sysinfo::System) -> String {
-  let ram_used = get_ram_used(system);
+fn sample(collector: &mut Collector, filters: &mut Filters, store: &Arc<Mutex<Store>>,
+          derived: &mut Derived, history: &mut History, sinks: &mut [Box<dyn Sink>]) -> Vec<Sample> {
+  let mut samples = collector.collect();
+  let now = collector.time();
+  filters.apply(now, &mut samples);
+  {
+    let mut store = store.lock().unwrap();
+    store.record(now, &samples);
+    samples.extend(derived.apply(&mut store, now));
+  }
+  history.append(now, &samples);
+  for sink in sinks { sink.push(now, &samples); }
+  samples
+}
+
+fn mk_cpu_ram_str(cpu: &str, ram: &str, store: &Arc<Mutex<Store>>) -> String {
+  let ram_used = get_ram_used(store);
   [cpu.to_owned(), ram.to_owned(), ram_used].join("\n")
 }
 
-fn cpu_name() -> String {
-  // use cupid;
-  let info = cupid::master();
-  match info {
-    Some(x) => {
-      match x.brand_string() {
-        Some(x) => { ["CPU: ".to_owned(), x.to_owned()].join("") }
-        _ => { "Could not get CPU Name".to_owned() }
-      }
-    }
-    _ => { "Could not get CPU Name".to_owned() }
+fn cpu_name(collector: &Collector) -> String {
+  match collector.cpu_brand() {
+    Some(brand) => ["CPU: ".to_owned(), brand].join(""),
+    None => "Could not get CPU Name".to_owned(),
   }
 }
 
-fn get_ram_total(system: &mut sysinfo::System) -> String {
-  system.refresh_all();
-  let ram_total = ((system.get_total_memory() as f32 / 1024.0) / 1024.0).round();
+fn get_ram_total(store: &Arc<Mutex<Store>>) -> String {
+  let store = store.lock().unwrap();
+  let ram_total = (store.latest("mem.total").unwrap_or(0.0) / 1024.0 / 1024.0 / 1024.0).round();
   format!("Total Memory: {} GB", ram_total )
 }
 
-fn get_ram_used(system: &mut sysinfo::System) -> String {
-  system.refresh_all();
-  let ram_used = (((system.get_used_memory() as f32 / 1024.0) / 1024.0) * 1000.0).round() / 1000.0;
+fn get_ram_used(store: &Arc<Mutex<Store>>) -> String {
+  let store = store.lock().unwrap();
+  let used = store.latest("mem.used.smooth").or_else(|| store.latest("mem.used"));
+  let ram_used = ((used.unwrap_or(0.0) / 1024.0 / 1024.0 / 1024.0) * 1000.0).round() / 1000.0;
   format!("Used Memory : {:.3} GB", ram_used )
 }
 
-fn get_hdd() -> String {
-  let sys = System::new();
+fn get_hdd(store: &Arc<Mutex<Store>>) -> String {
+  let store = store.lock().unwrap();
   let mut out = String::new();
-  match sys.mounts() {
-    Ok(mounts) => {
-      for mount in mounts.iter() {
-        if mount.total == bytesize::ByteSize::b(0) { continue; }
-        let mnt = mount.fs_mounted_on.clone();
-        if is_dir_or_subdir_linux(&mnt, "/boot") { continue; }
-        if is_dir_or_subdir_linux(&mnt, "/dev") { continue; }
-        if is_dir_or_subdir_linux(&mnt, "/run") { continue; }
-        if is_dir_or_subdir_linux(&mnt, "/snap") { continue; }
-        if is_dir_or_subdir_linux(&mnt, "/sys") { continue; }
-        out = format!("{}\n{} Size: {}; Free: {}",
-          out, mount.fs_mounted_on, mount.total, mount.avail);
-      }
-    }
-    Err(x) => println!("\nMounts: error: {}", x)
+  for name in store.glob("disk.*.total") {
+    let mount = &name["disk.".len()..name.len() - ".total".len()];
+    let total = store.latest(name).unwrap_or(0.0);
+    let avail = store.latest(&format!("disk.{}.avail", mount)).unwrap_or(0.0);
+    out = format!("{}\n{} Size: {}; Free: {}",
+      out, mount, bytesize::ByteSize::b(total as u64), bytesize::ByteSize::b(avail as u64));
   }
   out
 }
-
-fn is_dir_or_subdir_linux(test: &str, path: &str) -> bool {
-  let tc = test.chars().count();
-  let pc = path.chars().count();
-  let pc2 = pc + 1;
-  let path2: &str = &format!("{}/", path);
-  if (tc == pc && test == path) 
-  || (tc > pc2 && &test[..pc2] == path2)
-  { return true; }
-  false
-}
\ No newline at end of file
@@ -0,0 +1,231 @@
+
+use {
+  std::{
+    fs,
+    path::Path,
+  },
+  sysinfo::{NetworkExt, NetworksExt, ProcessorExt, SystemExt},
+  systemstat::{self, Platform},
+  crate::{
+    config::Config,
+    metrics::{demo::{self, Demo}, now_secs, Recorder, Replay, Sample},
+    snapshot::{hostname, kernel_release, microcode},
+    util::HashMap,
+  },
+};
+
+/// Takes a sample of everything the local machine reports, or of a made up
+/// one with a `[demo]` section, or plays a recording back
+pub struct Collector {
+  system: sysinfo::System,
+  demo: Option<Demo>,
+  pub replay: Option<Replay>,
+  pub recorder: Option<Recorder>,
+  /// When the last sample was taken, in recorded time for a replay
+  time: f64,
+  /// Each disk's milliseconds spent doing I/O at the last sample, and when
+  disk_ticks: HashMap<String, (f64, f64)>,
+}
+impl Default for Collector {
+  fn default() -> Self {
+    let mut system = sysinfo::System::new();
+    system.refresh_networks_list();
+    // CPU usage is worked out between two refreshes, so get the first one
+    // out of the way now.
+    system.refresh_cpu();
+    Self { system, demo: None, replay: None, recorder: None, time: 0.0, disk_ticks: HashMap::new() }
+  }
+}
+
+impl Collector {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn from_config(config: &Config) -> Self {
+    match config.section("demo") {
+      Some(section) => Self {
+        system: sysinfo::System::new(),
+        demo: Some(Demo::from_section(section)),
+        replay: None,
+        recorder: None,
+        time: 0.0,
+        disk_ticks: HashMap::new(),
+      },
+      None => Self::new(),
+    }
+  }
+  /// Whether the samples are this machine's right now
+  pub fn is_live(&self) -> bool {
+    self.demo.is_none() && self.replay.is_none()
+  }
+  /// Whether samples should reach alert hooks, exports and the grid: live
+  /// ones always, made up or replayed ones only when `opt_in` says so
+  pub fn publishes(&self, opt_in: bool) -> bool {
+    self.is_live() || opt_in
+  }
+  /// The name of the machine the samples are from
+  pub fn host(&self) -> String {
+    match (&self.replay, &self.demo) {
+      (Some(replay), _) => replay.host.clone(),
+      (None, Some(_)) => demo::HOST_NAME.to_owned(),
+      (None, None) => hostname(),
+    }
+  }
+  /// The CPU's name, the made up or recorded one if it isn't live
+  pub fn cpu_brand(&self) -> Option<String> {
+    match (&self.replay, &self.demo) {
+      (Some(replay), _) => Some(replay.cpu.clone()),
+      (None, Some(_)) => Some(demo::CPU_NAME.to_owned()),
+      (None, None) => cpu_brand(),
+    }
+  }
+  /// Kernel release, empty for the demo
+  pub fn kernel(&self) -> String {
+    match (&self.replay, &self.demo) {
+      (Some(replay), _) => replay.kernel.clone(),
+      (None, Some(_)) => String::new(),
+      (None, None) => kernel_release(),
+    }
+  }
+  /// CPU microcode revision, empty for the demo
+  pub fn microcode(&self) -> String {
+    match (&self.replay, &self.demo) {
+      (Some(replay), _) => replay.microcode.clone(),
+      (None, Some(_)) => String::new(),
+      (None, None) => microcode(),
+    }
+  }
+  /// Samples to take now: always one, except for a replay that's running
+  /// faster or slower than real time
+  pub fn due(&mut self) -> usize {
+    match self.replay {
+      Some(ref mut replay) => replay.due(),
+      None => 1,
+    }
+  }
+  /// When the last sample was taken
+  pub fn time(&self) -> f64 {
+    self.time
+  }
+  pub fn collect(&mut self) -> Vec<Sample> {
+    let (time, samples) = match (&mut self.replay, &mut self.demo) {
+      (Some(replay), _) => replay.next_tick().unwrap_or((self.time, Vec::new())),
+      (None, Some(demo)) => (now_secs(), demo.collect()),
+      (None, None) => (now_secs(), self.collect_live()),
+    };
+    self.time = time;
+    if let Some(ref mut recorder) = self.recorder { recorder.record(time, &samples); }
+    samples
+  }
+  fn collect_live(&mut self) -> Vec<Sample> {
+    let sys = &mut self.system;
+    sys.refresh_cpu();
+    sys.refresh_memory();
+    sys.refresh_networks();
+    let mut out = vec![
+      Sample::new("cpu.usage", sys.get_global_processor_info().get_cpu_usage() as f64),
+      // sysinfo reports memory in KiB
+      Sample::new("mem.total", sys.get_total_memory() as f64 * 1024.0),
+      Sample::new("mem.used", sys.get_used_memory() as f64 * 1024.0),
+      Sample::new("mem.free", sys.get_free_memory() as f64 * 1024.0),
+      Sample::new("swap.total", sys.get_total_swap() as f64 * 1024.0),
+      Sample::new("swap.used", sys.get_used_swap() as f64 * 1024.0),
+      Sample::new("uptime", sys.get_uptime() as f64),
+    ];
+    let load = sys.get_load_average();
+    out.push(Sample::new("load.1", load.one));
+    out.push(Sample::new("load.5", load.five));
+    out.push(Sample::new("load.15", load.fifteen));
+    for (iface, data) in sys.get_networks().iter() {
+      out.push(Sample::new(&format!("net.{}.rx", iface), data.get_total_received() as f64));
+      out.push(Sample::new(&format!("net.{}.tx", iface), data.get_total_transmitted() as f64));
+    }
+    match systemstat::System::new().mounts() {
+      Ok(mounts) => {
+        for mount in mounts.iter() {
+          if mount.total.as_u64() == 0 || skip_mount(&mount.fs_mounted_on) { continue }
+          let total = mount.total.as_u64() as f64;
+          let avail = mount.avail.as_u64() as f64;
+          let free = mount.free.as_u64() as f64;
+          out.push(Sample::new(&format!("disk.{}.total", mount.fs_mounted_on), total));
+          out.push(Sample::new(&format!("disk.{}.avail", mount.fs_mounted_on), avail));
+          out.push(Sample::new(&format!("disk.{}.used", mount.fs_mounted_on), total - free));
+        }
+      }
+      Err(e) => println!("Collector: can't list mounts: {}", e),
+    }
+    self.disk_util(&mut out);
+    out
+  }
+  /// `disk.<device>.util`, the percent of the time since the last sample
+  /// each disk was busy, like iostat's %util
+  fn disk_util(&mut self, out: &mut Vec<Sample>) {
+    let text = match fs::read_to_string("/proc/diskstats") {
+      Ok(text) => text,
+      Err(_) => return,
+    };
+    let now = now_secs();
+    for (dev, ticks) in parse_diskstats(&text) {
+      // Partitions aren't listed in /sys/block, and loop and ram devices
+      // aren't real disks
+      if dev.starts_with("loop") || dev.starts_with("ram") || !Path::new("/sys/block").join(&dev).exists() { continue }
+      if let Some((then, prev)) = self.disk_ticks.insert(dev.clone(), (now, ticks)) {
+        if now > then && ticks >= prev {
+          let util = (ticks - prev) / ((now - then) * 1000.0) * 100.0;
+          out.push(Sample::new(&format!("disk.{}.util", dev), util.min(100.0)));
+        }
+      }
+    }
+  }
+}
+
+/// Device names and their milliseconds spent doing I/O, out of
+/// /proc/diskstats
+pub fn parse_diskstats(text: &str) -> Vec<(String, f64)> {
+  text.lines().filter_map(|line| {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    // major minor name, then io_ticks is the tenth stat
+    let ticks = fields.get(12)?.parse().ok()?;
+    Some((fields.get(2)?.to_string(), ticks))
+  }).collect()
+}
+
+pub fn cpu_brand() -> Option<String> {
+  cupid::master().and_then(|info| info.brand_string().map(|b| b.trim().to_owned()))
+}
+
+/// Mounts that aren't real storage and aren't worth showing
+pub fn skip_mount(mnt: &str) -> bool {
+  ["/boot", "/dev", "/run", "/snap", "/sys"].iter().any(|path| is_dir_or_subdir_linux(mnt, path))
+}
+
+pub fn is_dir_or_subdir_linux(test: &str, path: &str) -> bool {
+  let tc = test.chars().count();
+  let pc = path.chars().count();
+  let pc2 = pc + 1;
+  let path2: &str = &format!("{}/", path);
+  (tc == pc && test == path) || (tc > pc2 && &test[..pc2] == path2)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn diskstats_io_ticks() {
+    let text = "\
+ 259       0 nvme0n1 52 0 2448 9 3 0 24 1 0 1530 11 0 0 0 0 0 0
+ 259       1 nvme0n1p1 20 0 1040 3 0 0 0 0 0 12 3 0 0 0 0 0 0
+   8       0 sda 1 2 3
+";
+    assert_eq!(parse_diskstats(text), vec![("nvme0n1".to_owned(), 1530.0), ("nvme0n1p1".to_owned(), 12.0)]);
+  }
+
+  #[test]
+  fn skips_pseudo_mounts() {
+    assert!(skip_mount("/dev"));
+    assert!(skip_mount("/run/user/1000"));
+    assert!(!skip_mount("/"));
+    assert!(!skip_mount("/devices"));
+  }
+}
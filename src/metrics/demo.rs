@@ -0,0 +1,191 @@
+
+use {
+  std::f64::consts::PI,
+  crate::{
+    config::Section,
+    metrics::Sample,
+  },
+};
+
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// What the demo machine calls itself and its CPU
+pub const HOST_NAME: &str = "demo";
+pub const CPU_NAME: &str = "Demo CPU 8-Core @ 3.60GHz";
+const CORES: f64 = 8.0;
+
+/// Made up but believable metrics for demos and screenshots. The same seed
+/// gives the same series every time, one step per `collect`, however fast
+/// or slow that's called.
+///
+///   [demo]
+///   seed = 42
+///   spikes = 0.02
+///
+/// `spikes` is the chance each step of a burst of CPU, memory and network
+/// load lasting a few steps.
+pub struct Demo {
+  rng: Rng,
+  pub spikes: f64,
+  step: u64,
+  /// Steps left of the current spike
+  spike_left: u32,
+  cpu: f64,
+  mem_used: f64,
+  swap_used: f64,
+  load: [f64; 3],
+  rx: f64,
+  tx: f64,
+  lo: f64,
+  disks: Vec<(&'static str, f64, f64)>,
+  /// Percent of the time the one disk is busy
+  util: f64,
+}
+impl Demo {
+  pub fn new(seed: u64) -> Self {
+    Self {
+      rng: Rng(seed),
+      spikes: 0.02,
+      step: 0,
+      spike_left: 0,
+      cpu: 15.0,
+      mem_used: 6.0 * GIB,
+      swap_used: 0.2 * GIB,
+      load: [1.2, 1.0, 0.8],
+      rx: 48.0 * GIB,
+      tx: 9.0 * GIB,
+      lo: 2.0 * GIB,
+      disks: vec![("/", 512.0 * GIB, 187.0 * GIB), ("/home", 1863.0 * GIB, 1104.0 * GIB)],
+      util: 3.0,
+    }
+  }
+  pub fn from_section(section: &Section) -> Self {
+    let mut out = Self::new(section.get_or("seed", 1));
+    out.spikes = section.get_or("spikes", out.spikes).clamp(0.0, 1.0);
+    out
+  }
+  pub fn collect(&mut self) -> Vec<Sample> {
+    self.step += 1;
+    let t = self.step as f64;
+    if self.spike_left > 0 {
+      self.spike_left -= 1;
+    } else if self.rng.next() < self.spikes {
+      self.spike_left = 3 + (self.rng.next() * 8.0) as u32;
+    }
+    let spiking = self.spike_left > 0;
+    // A slow daily-looking swell plus jitter, pinned near the top in a spike
+    let target = if spiking {
+      85.0 + self.rng.next() * 15.0
+    } else {
+      18.0 + 12.0 * (t * 2.0 * PI / 300.0).sin() + self.rng.spread(8.0)
+    };
+    self.cpu = (self.cpu * 0.4 + target * 0.6).clamp(0.5, 100.0);
+    let mem_total = 16.0 * GIB;
+    let mem_target = if spiking { 12.5 * GIB } else { 6.0 * GIB + 1.5 * GIB * (t * 2.0 * PI / 900.0).sin() };
+    self.mem_used = (self.mem_used * 0.9 + mem_target * 0.1 + self.rng.spread(40.0 * MIB)).clamp(GIB, mem_total);
+    self.swap_used = (self.swap_used + if self.mem_used > 12.0 * GIB { 8.0 * MIB } else { -MIB }).clamp(0.1 * GIB, 4.0 * GIB);
+    // Load averages decay towards the busy core count like the kernel's do
+    let busy = self.cpu / 100.0 * CORES;
+    for (load, secs) in self.load.iter_mut().zip(&[60.0_f64, 300.0, 900.0]) {
+      let decay = (-1.0 / *secs).exp();
+      *load = *load * decay + busy * (1.0 - decay);
+    }
+    let rx_rate = if spiking { 40.0 * MIB } else { 300.0 * 1024.0 } * (1.0 + self.rng.spread(0.5));
+    let tx_rate = if spiking { 6.0 * MIB } else { 60.0 * 1024.0 } * (1.0 + self.rng.spread(0.5));
+    self.rx += rx_rate.max(0.0).round();
+    self.tx += tx_rate.max(0.0).round();
+    self.lo += (20.0 * 1024.0 * (1.0 + self.rng.spread(0.3))).round();
+    // Disks fill slowly, and now and then someone clears some space
+    for (_, total, used) in self.disks.iter_mut() {
+      *used += self.rng.next() * if spiking { 200.0 * MIB } else { MIB };
+      if self.rng.next() < 0.002 { *used *= 0.95; }
+      *used = used.min(*total * 0.98);
+    }
+    let util_target = if spiking { 70.0 + self.rng.next() * 30.0 } else { 2.0 + self.rng.next() * 6.0 };
+    self.util = self.util * 0.5 + util_target * 0.5;
+    let mut out = vec![
+      Sample::new("cpu.usage", (self.cpu * 10.0).round() / 10.0),
+      Sample::new("mem.total", mem_total),
+      Sample::new("mem.used", self.mem_used.round()),
+      Sample::new("mem.free", (mem_total - self.mem_used).round()),
+      Sample::new("swap.total", 4.0 * GIB),
+      Sample::new("swap.used", self.swap_used.round()),
+      Sample::new("uptime", 3.0 * 86400.0 + 4321.0 + t),
+      Sample::new("load.1", self.load[0]),
+      Sample::new("load.5", self.load[1]),
+      Sample::new("load.15", self.load[2]),
+      Sample::new("net.eth0.rx", self.rx),
+      Sample::new("net.eth0.tx", self.tx),
+      Sample::new("net.lo.rx", self.lo),
+      Sample::new("net.lo.tx", self.lo),
+      Sample::new("disk.nvme0n1.util", (self.util * 10.0).round() / 10.0),
+    ];
+    for (mount, total, used) in &self.disks {
+      // Filesystems keep some space back for root, so avail is a bit less than free
+      out.push(Sample::new(&format!("disk.{}.total", mount), *total));
+      out.push(Sample::new(&format!("disk.{}.avail", mount), (total * 0.95 - used).max(0.0).round()));
+      out.push(Sample::new(&format!("disk.{}.used", mount), used.round()));
+    }
+    out
+  }
+}
+
+/// splitmix64, which is plenty for made up numbers and needs no crate
+struct Rng(u64);
+impl Rng {
+  /// From 0 to 1
+  fn next(&mut self) -> f64 {
+    self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = self.0;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+  }
+  /// From `-size` to `size`
+  fn spread(&mut self, size: f64) -> f64 {
+    (self.next() * 2.0 - 1.0) * size
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{config::Config, metrics::Collector},
+  };
+
+  fn series(mut demo: Demo, steps: usize) -> Vec<Vec<Sample>> {
+    (0..steps).map(|_| demo.collect()).collect()
+  }
+
+  #[test]
+  fn same_seed_same_series() {
+    let a = series(Demo::new(42), 500);
+    assert_eq!(a, series(Demo::new(42), 500));
+    assert_ne!(a, series(Demo::new(43), 500));
+    // Spikes come from the same generator, so they line up too
+    let spiky = |seed| { let mut d = Demo::new(seed); d.spikes = 0.2; series(d, 200) };
+    assert_eq!(spiky(7), spiky(7));
+  }
+
+  #[test]
+  fn believable_values() {
+    for samples in series(Demo::new(1), 1000) {
+      let get = |name: &str| samples.iter().find(|s| s.name == name).map(|s| s.value).unwrap();
+      assert!((0.5..=100.0).contains(&get("cpu.usage")));
+      assert!(get("mem.used") <= get("mem.total"));
+      assert!(get("disk./home.used") <= get("disk./home.total"));
+      assert!(samples.iter().all(|s| s.value.is_finite() && s.value >= 0.0));
+    }
+  }
+
+  #[test]
+  fn demos_publish_only_when_asked() {
+    let collector = Collector::from_config(&Config::parse("[demo]\nseed = 3\n"));
+    assert!(!collector.is_live());
+    assert!(!collector.publishes(false));
+    assert!(collector.publishes(true));
+  }
+}
@@ -0,0 +1,144 @@
+
+use {
+  std::{
+    fs,
+    path::PathBuf,
+  },
+  crate::{
+    config::{config_dir, Section},
+    metrics::{expr::{self, Expr}, Sample, Store},
+    util::HashSet,
+  },
+};
+
+/// Metrics worked out from other metrics each tick, read from a file of
+/// `name = expression` lines:
+///
+///   mem_pct = mem.used / mem.total * 100
+///   disk_free_min = min(disk.*.avail)
+///   disk_busy = max(disk.*.util)
+///   net_in = rate(net.eth0.rx)
+///
+/// Definitions are worked out in file order, so later ones can use
+/// earlier ones.
+#[derive(Default)]
+pub struct Derived {
+  pub defs: Vec<(String, Expr)>,
+  failing: HashSet<String>,
+}
+impl Derived {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// Parses a metrics file, reporting and skipping bad lines
+  pub fn parse(text: &str) -> Self {
+    let mut out = Self::new();
+    for (num, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') { continue }
+      let (name, body) = match line.find('=') {
+        Some(eq) => (line[..eq].trim(), &line[eq + 1..]),
+        None => { println!("Metrics: line {}: expected name = expression", num + 1); continue }
+      };
+      match expr::parse(body) {
+        Ok(e) => out.defs.push((name.to_owned(), e)),
+        Err(e) => println!("Metrics: line {}: {}", num + 1, e),
+      }
+    }
+    out
+  }
+  /// `[derived]` can point `file` somewhere, otherwise `metrics` next to
+  /// the config file is used if it exists.
+  pub fn from_section(section: Option<&Section>) -> Self {
+    let path = match section.and_then(|s| s.get("file")) {
+      Some(file) => PathBuf::from(file),
+      None => match config_dir() {
+        Some(dir) if dir.join("metrics").is_file() => dir.join("metrics"),
+        _ => return Self::new(),
+      },
+    };
+    match fs::read_to_string(&path) {
+      Ok(text) => Self::parse(&text),
+      Err(e) => { println!("Metrics: can't read {}: {}", path.display(), e); Self::new() }
+    }
+  }
+  /// Works out every definition against what's in the store, adds the
+  /// results to it and hands them back. A definition that can't be worked
+  /// out this tick, say a rate() with only one sample so far, is skipped.
+  pub fn apply(&mut self, store: &mut Store, ts: f64) -> Vec<Sample> {
+    let mut out = Vec::new();
+    for (name, e) in &self.defs {
+      match e.eval(store) {
+        Ok(value) if value.is_finite() => {
+          store.push(name, ts, value);
+          out.push(Sample::new(name, value));
+          self.failing.remove(name);
+        }
+        Ok(_) => (),
+        Err(err) => {
+          // Say why once, rather than every second
+          if self.failing.insert(name.clone()) { println!("Metrics: {}: {}", name, err); }
+        }
+      }
+    }
+    out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The examples from the README
+  const METRICS: &str = "
+mem_pct = mem.used / mem.total * 100
+disk_free_min = min(disk.*.avail)
+disk_busy = max(disk.*.util)
+home_free = `disk./home.avail`
+net_in = rate(net.eth0.rx)
+cpu_capped = clamp(cpu.usage * 2, 0, 100)
+";
+
+  fn tick(store: &mut Store, derived: &mut Derived, ts: f64, rx: f64) -> Vec<Sample> {
+    store.record(ts, &[
+      Sample::new("mem.used", 4.0),
+      Sample::new("mem.total", 16.0),
+      Sample::new("disk./.avail", 300.0),
+      Sample::new("disk./home.avail", 200.0),
+      Sample::new("disk.sda.util", 12.5),
+      Sample::new("disk.nvme0n1.util", 80.0),
+      Sample::new("net.eth0.rx", rx),
+      Sample::new("cpu.usage", 70.0),
+    ]);
+    derived.apply(store, ts)
+  }
+
+  #[test]
+  fn readme_examples() {
+    let mut derived = Derived::parse(METRICS);
+    assert_eq!(derived.defs.len(), 6);
+    let mut store = Store::new();
+    let first = tick(&mut store, &mut derived, 100.0, 1000.0);
+    // No rate until there are two samples
+    assert!(first.iter().all(|s| s.name != "net_in"));
+    let out = tick(&mut store, &mut derived, 102.0, 3000.0);
+    assert_eq!(out, vec![
+      Sample::new("mem_pct", 25.0),
+      Sample::new("disk_free_min", 200.0),
+      Sample::new("disk_busy", 80.0),
+      Sample::new("home_free", 200.0),
+      Sample::new("net_in", 1000.0),
+      Sample::new("cpu_capped", 100.0),
+    ]);
+    assert_eq!(store.latest("disk_busy"), Some(80.0));
+  }
+
+  #[test]
+  fn later_definitions_use_earlier_ones() {
+    let mut derived = Derived::parse("a = mem.used * 2\nb = a + 1\nbad line\nc = (");
+    assert_eq!(derived.defs.len(), 2);
+    let mut store = Store::new();
+    let out = tick(&mut store, &mut derived, 100.0, 0.0);
+    assert_eq!(out, vec![Sample::new("a", 8.0), Sample::new("b", 9.0)]);
+  }
+}
@@ -0,0 +1,275 @@
+
+use {
+  nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag, take_while, take_while1},
+    character::complete::{char, multispace0, one_of},
+    combinator::{all_consuming, map, recognize},
+    multi::{many0, separated_list},
+    number::complete::double,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+  },
+  crate::{
+    metrics::{smooth::Rate, Store},
+  },
+};
+
+// Grammar, loosest binding first:
+//
+//   expr  = term (('+' | '-') term)*
+//   term  = unary (('*' | '/' | '%') unary)*
+//   unary = '-' unary | atom
+//   atom  = '(' expr ')' | func '(' expr (',' expr)* ')' | metric | number
+//
+// A bare metric name is dotted words, numbers or `*` globs: `mem.used`,
+// `load.15`, `disk.*.avail`. Anything else, like a mount path, goes in
+// backticks: `disk./home.avail`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Rem,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Num(f64),
+  Metric(String),
+  Neg(Box<Expr>),
+  Bin(Op, Box<Expr>, Box<Expr>),
+  Call(String, Vec<Expr>),
+}
+
+fn ws<'a, O, F>(f: F) -> impl Fn(&'a str) -> IResult<&'a str, O>
+  where F: Fn(&'a str) -> IResult<&'a str, O>
+{
+  delimited(multispace0, f, multispace0)
+}
+
+fn ident(input: &str) -> IResult<&str, &str> {
+  recognize(pair(
+    take_while1(|c: char| c.is_ascii_alphabetic() || c == '_'),
+    take_while(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+  ))(input)
+}
+
+fn segment(input: &str) -> IResult<&str, &str> {
+  alt((tag("*"), take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_')))(input)
+}
+
+fn metric(input: &str) -> IResult<&str, Expr> {
+  alt((
+    map(delimited(char('`'), is_not("`"), char('`')), |s: &str| Expr::Metric(s.to_owned())),
+    map(recognize(pair(ident, many0(preceded(char('.'), segment)))), |s: &str| Expr::Metric(s.to_owned())),
+  ))(input)
+}
+
+fn call(input: &str) -> IResult<&str, Expr> {
+  map(
+    pair(terminated(ident, ws(char('('))), terminated(separated_list(ws(char(',')), expr), ws(char(')')))),
+    |(name, args)| Expr::Call(name.to_owned(), args),
+  )(input)
+}
+
+fn atom(input: &str) -> IResult<&str, Expr> {
+  // Names go before numbers, or `inf...` would be read as infinity
+  ws(alt((
+    delimited(char('('), expr, char(')')),
+    call,
+    metric,
+    map(double, Expr::Num),
+  )))(input)
+}
+
+fn unary(input: &str) -> IResult<&str, Expr> {
+  alt((
+    map(preceded(ws(char('-')), unary), |e| Expr::Neg(Box::new(e))),
+    atom,
+  ))(input)
+}
+
+fn fold(first: Expr, rest: Vec<(char, Expr)>) -> Expr {
+  rest.into_iter().fold(first, |acc, (op, e)| {
+    let op = match op {
+      '+' => Op::Add,
+      '-' => Op::Sub,
+      '*' => Op::Mul,
+      '/' => Op::Div,
+      _ => Op::Rem,
+    };
+    Expr::Bin(op, Box::new(acc), Box::new(e))
+  })
+}
+
+fn term(input: &str) -> IResult<&str, Expr> {
+  map(pair(unary, many0(tuple((ws(one_of("*/%")), unary)))), |(first, rest)| fold(first, rest))(input)
+}
+
+pub fn expr(input: &str) -> IResult<&str, Expr> {
+  map(pair(term, many0(tuple((ws(one_of("+-")), term)))), |(first, rest)| fold(first, rest))(input)
+}
+
+pub fn parse(input: &str) -> Result<Expr, String> {
+  match all_consuming(ws(expr))(input) {
+    Ok((_, e)) => Ok(e),
+    Err(nom::Err::Error((rest, _))) | Err(nom::Err::Failure((rest, _))) => {
+      Err(format!("can't parse at '{}'", rest))
+    }
+    Err(nom::Err::Incomplete(_)) => Err("unexpected end".to_owned()),
+  }
+}
+
+impl Expr {
+  /// Works the expression out against the latest values in the store
+  pub fn eval(&self, store: &Store) -> Result<f64, String> {
+    match self {
+      Expr::Num(n) => Ok(*n),
+      Expr::Metric(name) => {
+        let names = store.glob(name);
+        match names.len() {
+          0 => Err(format!("no metric {}", name)),
+          1 => store.latest(names[0]).ok_or_else(|| format!("no value for {}", name)),
+          _ => Err(format!("{} matches {} metrics, use min/max/avg/sum", name, names.len())),
+        }
+      }
+      Expr::Neg(e) => Ok(-e.eval(store)?),
+      Expr::Bin(op, a, b) => {
+        let (a, b) = (a.eval(store)?, b.eval(store)?);
+        Ok(match op {
+          Op::Add => a + b,
+          Op::Sub => a - b,
+          Op::Mul => a * b,
+          Op::Div => a / b,
+          Op::Rem => a % b,
+        })
+      }
+      Expr::Call(name, args) => call_fn(name, args, store),
+    }
+  }
+}
+
+// Arguments of the aggregate functions, with globs spread out into every
+// metric they match
+fn spread(args: &[Expr], store: &Store) -> Result<Vec<f64>, String> {
+  let mut out = Vec::new();
+  for arg in args {
+    match arg {
+      Expr::Metric(name) => {
+        for name in store.glob(name) {
+          if let Some(v) = store.latest(name) { out.push(v) }
+        }
+      }
+      _ => out.push(arg.eval(store)?),
+    }
+  }
+  Ok(out)
+}
+
+fn call_fn(name: &str, args: &[Expr], store: &Store) -> Result<f64, String> {
+  match name {
+    "min" | "max" | "avg" | "sum" | "count" => {
+      let vals = spread(args, store)?;
+      if vals.is_empty() && name != "count" && name != "sum" { return Err(format!("{}() of nothing", name)) }
+      Ok(match name {
+        "min" => vals.iter().cloned().fold(f64::INFINITY, f64::min),
+        "max" => vals.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        "avg" => vals.iter().sum::<f64>() / vals.len() as f64,
+        "sum" => vals.iter().sum(),
+        _ => vals.len() as f64,
+      })
+    }
+    "abs" if args.len() == 1 => Ok(args[0].eval(store)?.abs()),
+    "clamp" if args.len() == 3 => {
+      let (v, lo, hi) = (args[0].eval(store)?, args[1].eval(store)?, args[2].eval(store)?);
+      Ok(v.max(lo).min(hi))
+    }
+    "rate" if args.len() == 1 => match &args[0] {
+      Expr::Metric(metric) => rate(store, metric),
+      _ => Err("rate() takes a metric name".to_owned()),
+    },
+    _ => Err(format!("unknown function {}() with {} arguments", name, args.len())),
+  }
+}
+
+/// Per second change between the last two samples of a counter, counting
+/// from zero again after a reset the way `[counters]` rates do
+fn rate(store: &Store, name: &str) -> Result<f64, String> {
+  let names = store.glob(name);
+  if names.len() != 1 { return Err(format!("rate() needs exactly one metric, {} matches {}", name, names.len())) }
+  let series = store.series(names[0]).ok_or_else(|| format!("no metric {}", name))?;
+  let mut last = series.raw().rev();
+  let mut rate = Rate::new(None);
+  match (last.next(), last.next()) {
+    (Some((t1, v1)), Some((t0, v0))) => {
+      rate.update(*t0, *v0);
+      rate.update(*t1, *v1)
+    }
+    _ => None,
+  }.ok_or_else(|| format!("not enough samples of {} for rate()", name))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn store(metrics: &[(&str, f64)]) -> Store {
+    let mut store = Store::new();
+    for (name, value) in metrics { store.push(name, 100.0, *value); }
+    store
+  }
+
+  fn eval(source: &str, store: &Store) -> Result<f64, String> {
+    parse(source)?.eval(store)
+  }
+
+  #[test]
+  fn precedence() {
+    let store = store(&[("mem.used", 3.0), ("mem.total", 4.0)]);
+    assert_eq!(eval("1 + 2 * 3", &store), Ok(7.0));
+    assert_eq!(eval("(1 + 2) * 3", &store), Ok(9.0));
+    assert_eq!(eval("10 - 4 - 3", &store), Ok(3.0));
+    assert_eq!(eval("2 * -3 % 4", &store), Ok(-2.0));
+    assert_eq!(eval("mem.used / mem.total * 100", &store), Ok(75.0));
+    assert_eq!(eval("-mem.used + clamp(mem.total, 0, 2)", &store), Ok(-1.0));
+    assert!(parse("1 +").is_err());
+    assert!(parse("mem.used mem.total").is_err());
+  }
+
+  #[test]
+  fn unknown_metrics_and_functions() {
+    let store = store(&[("mem.used", 3.0)]);
+    assert_eq!(eval("mem.free + 1", &store), Err("no metric mem.free".to_owned()));
+    assert_eq!(eval("`disk./home.avail`", &store), Err("no metric disk./home.avail".to_owned()));
+    assert_eq!(eval("median(mem.used)", &store), Err("unknown function median() with 1 arguments".to_owned()));
+    assert_eq!(eval("max(disk.*.used)", &store), Err("max() of nothing".to_owned()));
+    assert_eq!(eval("count(disk.*.used)", &store), Ok(0.0));
+  }
+
+  #[test]
+  fn globs_need_an_aggregate() {
+    let store = store(&[("disk./.used", 1.0), ("disk./home.used", 5.0)]);
+    assert_eq!(eval("disk.*.used", &store), Err("disk.*.used matches 2 metrics, use min/max/avg/sum".to_owned()));
+    assert_eq!(eval("sum(disk.*.used)", &store), Ok(6.0));
+    assert_eq!(eval("max(disk.*.used, 7)", &store), Ok(7.0));
+    assert_eq!(eval("avg(disk.*.used)", &store), Ok(3.0));
+  }
+
+  #[test]
+  fn rates() {
+    let mut store = Store::new();
+    store.push("net.eth0.rx", 100.0, 1000.0);
+    assert_eq!(eval("rate(net.eth0.rx)", &store), Err("not enough samples of net.eth0.rx for rate()".to_owned()));
+    store.push("net.eth0.rx", 102.0, 1400.0);
+    assert_eq!(eval("rate(net.eth0.rx)", &store), Ok(200.0));
+    // A reset counts from zero rather than going negative
+    store.push("net.eth0.rx", 104.0, 100.0);
+    assert_eq!(eval("rate(net.eth0.rx)", &store), Ok(50.0));
+    store.push("net.wlan0.rx", 104.0, 0.0);
+    assert!(eval("rate(net.*.rx)", &store).unwrap_err().starts_with("rate() needs exactly one metric"));
+    assert_eq!(eval("rate(1)", &store), Err("rate() takes a metric name".to_owned()));
+  }
+}
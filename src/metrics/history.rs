@@ -0,0 +1,369 @@
+
+use {
+  std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+  },
+  crate::{
+    config::Section,
+    metrics::{glob_match, Sample, Store},
+    redact,
+    util::HashMap,
+  },
+};
+
+// History is kept in one file per hour, `<unix hour>.hist`, so retention is
+// just deleting old files. Each file is a run of records:
+//
+//   u32 payload length, u32 FNV-1a of the payload, payload
+//
+// and a payload is one of
+//
+//   1, u16 id, name bytes                 gives a metric name an id
+//   2, f64 ts, (u16 id, f64 value)...     every sample of one tick
+//
+// all little endian. Ids are per file. Records are buffered and flushed
+// once per tick, so a crash can cut the last tick's records short anywhere;
+// loading stops at the first record that's short or fails its checksum and
+// the file is truncated back to the last good one.
+
+const TAG_NAME: u8 = 1;
+const TAG_TICK: u8 = 2;
+const EXT: &str = "hist";
+const SEGMENT_SECS: f64 = 3600.0;
+
+pub fn data_dir() -> Option<PathBuf> {
+  match env::var_os("XDG_DATA_HOME") {
+    Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("raumEnSysInfo")),
+    _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share/raumEnSysInfo")),
+  }
+}
+
+fn fnv1a(data: &[u8]) -> u32 {
+  let mut hash: u32 = 0x811c_9dc5;
+  for byte in data {
+    hash ^= *byte as u32;
+    hash = hash.wrapping_mul(0x0100_0193);
+  }
+  hash
+}
+
+struct Segment {
+  hour: i64,
+  out: BufWriter<File>,
+  ids: HashMap<String, u16>,
+  unsynced: u32,
+}
+impl Segment {
+  fn open(path: &Path, hour: i64) -> io::Result<Self> {
+    // Reading the file back both recovers the ids it already uses and cuts
+    // off any torn record left at the end.
+    let mut ids = HashMap::new();
+    if path.exists() {
+      let names = read_segment(path, true, &mut |_, _, _| ())?;
+      for (id, name) in names { ids.insert(name, id); }
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(Self { hour, out: BufWriter::new(file), ids, unsynced: 0 })
+  }
+  fn write_record(&mut self, payload: &[u8]) -> io::Result<()> {
+    let mut record = Vec::with_capacity(payload.len() + 8);
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&fnv1a(payload).to_le_bytes());
+    record.extend_from_slice(payload);
+    self.out.write_all(&record)
+  }
+  fn append(&mut self, ts: f64, samples: &[Sample], sync_every: u32) -> io::Result<()> {
+    let mut tick = vec![TAG_TICK];
+    tick.extend_from_slice(&ts.to_le_bytes());
+    for sample in samples {
+      let id = match self.ids.get(&sample.name) {
+        Some(id) => *id,
+        None => {
+          if self.ids.len() >= u16::MAX as usize { continue }
+          let id = self.ids.len() as u16;
+          let mut payload = vec![TAG_NAME];
+          payload.extend_from_slice(&id.to_le_bytes());
+          payload.extend_from_slice(sample.name.as_bytes());
+          self.write_record(&payload)?;
+          self.ids.insert(sample.name.clone(), id);
+          id
+        }
+      };
+      tick.extend_from_slice(&id.to_le_bytes());
+      tick.extend_from_slice(&sample.value.to_le_bytes());
+    }
+    self.write_record(&tick)?;
+    self.out.flush()?;
+    self.unsynced += 1;
+    if self.unsynced >= sync_every {
+      self.out.get_ref().sync_data()?;
+      self.unsynced = 0;
+    }
+    Ok(())
+  }
+}
+
+/// Reads every good record in a segment, calling `f(ts, name, value)` for
+/// each sample. Returns the file's id to name table. With `repair`, anything
+/// after the first bad record is cut off the file. Only the writer should
+/// repair, to a reader the record being written right now looks torn.
+fn read_segment(path: &Path, repair: bool, f: &mut dyn FnMut(f64, &str, f64)) -> io::Result<Vec<(u16, String)>> {
+  let data = fs::read(path)?;
+  let mut names: HashMap<u16, String> = HashMap::new();
+  let mut pos = 0;
+  while pos + 8 <= data.len() {
+    let len = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+    let sum = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]);
+    let start = pos + 8;
+    if len == 0 || start + len > data.len() || fnv1a(&data[start..start + len]) != sum { break }
+    let payload = &data[start..start + len];
+    match payload[0] {
+      TAG_NAME if payload.len() >= 3 => {
+        let id = u16::from_le_bytes([payload[1], payload[2]]);
+        names.insert(id, String::from_utf8_lossy(&payload[3..]).into_owned());
+      }
+      TAG_TICK if payload.len() >= 9 => {
+        let ts = f64::from_le_bytes(le8(&payload[1..9]));
+        for chunk in payload[9..].chunks_exact(10) {
+          let id = u16::from_le_bytes([chunk[0], chunk[1]]);
+          if let Some(name) = names.get(&id) { f(ts, name, f64::from_le_bytes(le8(&chunk[2..10]))); }
+        }
+      }
+      _ => break,
+    }
+    pos = start + len;
+  }
+  if repair && pos < data.len() {
+    println!("History: dropping {} bytes of damaged data from {}", data.len() - pos, path.display());
+    OpenOptions::new().write(true).open(path)?.set_len(pos as u64)?;
+  }
+  Ok(names.into_iter().collect())
+}
+
+fn le8(bytes: &[u8]) -> [u8; 8] {
+  let mut out = [0; 8];
+  out.copy_from_slice(&bytes[..8]);
+  out
+}
+
+/// Metric samples saved to disk so history survives a restart.
+///
+/// `retention` is in hours and `sync` is how many ticks go by between
+/// fsyncs. `dir` defaults to `$XDG_DATA_HOME/raumEnSysInfo/history`.
+///
+///   [history]
+///   retention = 48
+///   max_mb = 100
+///   sync = 60
+pub struct History {
+  pub dir: PathBuf,
+  pub enabled: bool,
+  pub retention_secs: f64,
+  pub max_bytes: u64,
+  pub sync_every: u32,
+  segment: Option<Segment>,
+}
+impl History {
+  pub fn new(dir: PathBuf) -> Self {
+    Self {
+      dir,
+      enabled: true,
+      retention_secs: 48.0 * 3600.0,
+      max_bytes: 100 * 1024 * 1024,
+      sync_every: 60,
+      segment: None,
+    }
+  }
+  /// With no `dir` and nowhere to put it by default, history is off rather
+  /// than written to wherever it was started from.
+  pub fn from_section(section: Option<&Section>) -> Self {
+    let dir = match section.and_then(|s| s.get("dir")) {
+      Some(dir) => Some(PathBuf::from(dir)),
+      None => data_dir().map(|dir| dir.join("history")),
+    };
+    let mut out = Self::new(dir.clone().unwrap_or_default());
+    out.enabled = dir.is_some();
+    if !out.enabled { println!("History: neither $XDG_DATA_HOME nor $HOME is set, give [history] a dir to keep it"); }
+    let section = match section {
+      Some(section) => section,
+      None => return out,
+    };
+    out.enabled &= section.get_or("enabled", true);
+    out.retention_secs = section.get_or("retention", 48.0_f64) * 3600.0;
+    out.max_bytes = (section.get_or("max_mb", 100.0_f64) * 1024.0 * 1024.0) as u64;
+    out.sync_every = section.get_or("sync", 60_u32).max(1);
+    out
+  }
+  /// Segment files, oldest first, along with the hour each one starts at
+  fn segments(&self) -> Vec<(i64, PathBuf)> {
+    if self.dir.as_os_str().is_empty() { return Vec::new() }
+    let mut out: Vec<(i64, PathBuf)> = match fs::read_dir(&self.dir) {
+      Ok(entries) => entries.flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|x| x == EXT).unwrap_or(false))
+        .filter_map(|p| {
+          let hour = p.file_stem()?.to_str()?.parse().ok()?;
+          Some((hour, p))
+        })
+        .collect(),
+      Err(_) => Vec::new(),
+    };
+    out.sort();
+    out
+  }
+  /// Deletes segments that are past the retention time, then the oldest
+  /// ones until everything fits in `max_bytes`.
+  pub fn prune(&mut self, now: f64) {
+    let oldest_hour = ((now - self.retention_secs) / SEGMENT_SECS).floor() as i64;
+    let segments = self.segments();
+    let mut total: u64 = segments.iter().map(|(_, p)| fs::metadata(p).map(|m| m.len()).unwrap_or(0)).sum();
+    let current = self.segment.as_ref().map(|s| s.hour);
+    for (hour, path) in segments {
+      if Some(hour) == current { continue }
+      if hour >= oldest_hour && total <= self.max_bytes { break }
+      total = total.saturating_sub(fs::metadata(&path).map(|m| m.len()).unwrap_or(0));
+      if let Err(e) = fs::remove_file(&path) { println!("History: can't remove {}: {}", path.display(), e); }
+    }
+  }
+  /// Calls `f(ts, name, value)` for every stored sample since `since`
+  pub fn for_each(&self, since: f64, f: &mut dyn FnMut(f64, &str, f64)) {
+    let first_hour = (since / SEGMENT_SECS).floor() as i64;
+    for (hour, path) in self.segments() {
+      if hour < first_hour { continue }
+      let mut filtered = |ts: f64, name: &str, value: f64| if ts >= since { f(ts, name, value) };
+      if let Err(e) = read_segment(&path, false, &mut filtered) {
+        println!("History: can't read {}: {}", path.display(), e);
+      }
+    }
+  }
+  /// Fills the store with everything still within the retention time
+  pub fn load_into(&self, store: &mut Store, now: f64) -> usize {
+    let mut count = 0;
+    self.for_each(now - self.retention_secs, &mut |ts, name, value| {
+      store.push(name, ts, value);
+      count += 1;
+    });
+    count
+  }
+  pub fn append(&mut self, ts: f64, samples: &[Sample]) {
+    if !self.enabled { return }
+    let hour = (ts / SEGMENT_SECS).floor() as i64;
+    if self.segment.as_ref().map(|s| s.hour) != Some(hour) {
+      if let Err(e) = fs::create_dir_all(&self.dir) {
+        println!("History: can't create {}: {}", self.dir.display(), e);
+        self.enabled = false;
+        return
+      }
+      let path = self.dir.join(format!("{}.{}", hour, EXT));
+      match Segment::open(&path, hour) {
+        Ok(segment) => self.segment = Some(segment),
+        Err(e) => { println!("History: can't open {}: {}", path.display(), e); return }
+      }
+      self.prune(ts);
+    }
+    if let Some(ref mut segment) = self.segment {
+      if let Err(e) = segment.append(ts, samples, self.sync_every) {
+        println!("History: write failed: {}", e);
+        self.segment = None;
+      }
+    }
+  }
+  /// Writes every stored sample of metrics matching `pattern` as
+  /// `<unix time> <name> <value>` lines.
+  pub fn dump(&self, pattern: &str, out: &mut dyn Write) -> io::Result<()> {
+    let mut result = Ok(());
+    self.for_each(0.0, &mut |ts, name, value| {
+      if result.is_ok() && glob_match(pattern, name) {
+        result = writeln!(out, "{:.3} {} {}", ts, redact::redact(name), value);
+      }
+    });
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::util::temp_dir,
+  };
+
+  /// The start of an hour, so ticks a few seconds apart share a segment
+  const START: f64 = 472_222.0 * SEGMENT_SECS;
+
+  fn history(dir: &Path) -> History {
+    let mut history = History::new(dir.to_owned());
+    history.retention_secs = 1000.0 * SEGMENT_SECS;
+    history
+  }
+
+  fn stored(history: &History) -> Vec<(f64, String, f64)> {
+    let mut out = Vec::new();
+    history.for_each(0.0, &mut |ts, name, value| out.push((ts - START, name.to_owned(), value)));
+    out
+  }
+
+  fn hours(history: &History) -> Vec<i64> {
+    history.segments().iter().map(|(hour, _)| hour - 472_222).collect()
+  }
+
+  #[test]
+  fn a_torn_record_is_cut_off() {
+    let dir = temp_dir("history-torn");
+    let mut first = history(&dir);
+    first.append(START, &[Sample::new("load.1", 1.0)]);
+    first.append(START + 1.0, &[Sample::new("load.1", 2.0), Sample::new("mem.used", 3.0)]);
+    drop(first);
+    let path = dir.join("472222.hist");
+    let len = fs::metadata(&path).unwrap().len();
+    OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 5).unwrap();
+    // Readers skip the torn tick and leave the file alone
+    let mut second = history(&dir);
+    assert_eq!(stored(&second), vec![(0.0, "load.1".to_owned(), 1.0)]);
+    assert_eq!(fs::metadata(&path).unwrap().len(), len - 5);
+    // The writer cuts it off before adding to the file
+    second.append(START + 2.0, &[Sample::new("mem.used", 4.0)]);
+    assert_eq!(stored(&second), vec![(0.0, "load.1".to_owned(), 1.0), (2.0, "mem.used".to_owned(), 4.0)]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn prunes_past_retention_and_size() {
+    let dir = temp_dir("history-prune");
+    let mut history = history(&dir);
+    for hour in 0..6 { history.append(START + hour as f64 * SEGMENT_SECS, &[Sample::new("load.1", 1.0)]); }
+    assert_eq!(hours(&history), vec![0, 1, 2, 3, 4, 5]);
+    // Two hours back from halfway through hour 5 is halfway through hour 3
+    history.retention_secs = 2.0 * SEGMENT_SECS;
+    history.prune(START + 5.5 * SEGMENT_SECS);
+    assert_eq!(hours(&history), vec![3, 4, 5]);
+    // Going over the size drops the oldest, but never the one being written
+    history.max_bytes = 0;
+    history.prune(START + 5.5 * SEGMENT_SECS);
+    assert_eq!(hours(&history), vec![5]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn loads_and_dumps() {
+    let dir = temp_dir("history-load");
+    let mut history = history(&dir);
+    history.append(START, &[Sample::new("mem.used", 1.0), Sample::new("load.1", 0.5)]);
+    history.append(START + 1.0, &[Sample::new("mem.used", 2.0), Sample::new("mem.total", 8.0)]);
+    let mut store = Store::new();
+    assert_eq!(history.load_into(&mut store, START + 10.0), 4);
+    assert_eq!((store.latest("mem.used"), store.latest("load.1"), store.latest("mem.total")), (Some(2.0), Some(0.5), Some(8.0)));
+    // Only what's within the retention time
+    history.retention_secs = 5.0;
+    assert_eq!(history.load_into(&mut Store::new(), START + 5.5), 2);
+    let mut out = Vec::new();
+    history.dump("mem.*", &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), format!(
+      "{0}.000 mem.used 1\n{1}.000 mem.used 2\n{1}.000 mem.total 8\n", START, START + 1.0,
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
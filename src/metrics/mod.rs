@@ -0,0 +1,61 @@
+pub mod collect;
+pub mod demo;
+pub mod derived;
+pub mod expr;
+pub mod history;
+pub mod replay;
+pub mod smooth;
+pub mod store;
+
+pub use {
+  crate::{
+    metrics::{
+      collect::Collector,
+      derived::Derived,
+      history::History,
+      replay::{Recorder, Replay},
+      smooth::Filters,
+      store::Store,
+    },
+  },
+};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Metric names are dotted paths: `mem.used`, `disk./home.avail`,
+// `net.eth0.rx`. Sizes are in bytes, percentages are 0 to 100.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+  pub name: String,
+  pub value: f64,
+}
+impl Sample {
+  pub fn new(name: &str, value: f64) -> Self {
+    Self { name: name.to_owned(), value }
+  }
+}
+
+/// Current unix time in seconds
+pub fn now_secs() -> f64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of
+/// characters, including dots.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+  let parts: Vec<&str> = pattern.split('*').collect();
+  if parts.len() == 1 { return pattern == name }
+  let (first, last) = (parts[0], parts[parts.len() - 1]);
+  if !name.starts_with(first) || name.len() < first.len() + last.len() || !name.ends_with(last) {
+    return false
+  }
+  let mut rest = &name[first.len()..name.len() - last.len()];
+  for part in &parts[1..parts.len() - 1] {
+    match rest.find(part) {
+      Some(idx) => rest = &rest[idx + part.len()..],
+      None => return false,
+    }
+  }
+  true
+}
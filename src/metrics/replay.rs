@@ -0,0 +1,284 @@
+
+use {
+  std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    time::Instant,
+  },
+  serde_json::{json, Map, Value},
+  crate::{
+    metrics::{now_secs, Sample},
+    snapshot::Snapshot,
+  },
+};
+
+// A recording is the collector's output, one JSON object per line. It starts
+// with a header:
+//
+//   {"type":"recording","version":1,"host":"rack1","cpu":"...","kernel":"...","microcode":"0xf0","start":1700000000.0}
+//
+// followed by a line per tick:
+//
+//   {"time":1700000001.0,"samples":{"cpu.usage":12.5,"mem.used":...}}
+//
+// Only what the collectors saw is kept. Rates, smoothing and derived metrics
+// are worked out again on replay, so changes to those can be tried against
+// old data.
+
+pub const VERSION: u64 = 1;
+
+/// Writes every tick the collector takes to a file
+pub struct Recorder {
+  out: BufWriter<File>,
+  failing: bool,
+}
+impl Recorder {
+  /// Starts a recording of the machine `about` describes
+  pub fn create(path: &Path, about: &Snapshot) -> Result<Self, String> {
+    let file = File::create(path).map_err(|e| format!("can't create {}: {}", path.display(), e))?;
+    let mut out = BufWriter::new(file);
+    let header = json!({
+      "type": "recording",
+      "version": VERSION,
+      "host": about.host,
+      "cpu": about.cpu,
+      "kernel": about.kernel,
+      "microcode": about.microcode,
+      "start": now_secs(),
+    });
+    writeln!(out, "{}", header).and_then(|_| out.flush()).map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+    Ok(Self { out, failing: false })
+  }
+  pub fn record(&mut self, time: f64, samples: &[Sample]) {
+    let samples: Map<String, Value> = samples.iter().map(|s| (s.name.clone(), json!(s.value))).collect();
+    // Flushed every tick so a crash loses at most the one being written
+    let result = writeln!(self.out, "{}", json!({ "time": time, "samples": samples })).and_then(|_| self.out.flush());
+    match result {
+      Ok(()) => self.failing = false,
+      Err(e) => {
+        if !self.failing { println!("Record: write failed: {}", e); }
+        self.failing = true;
+      }
+    }
+  }
+}
+
+/// Plays a recording back as if the collector were taking it live, at
+/// `speed` times the recorded pace, or as fast as it'll go if that's 0.
+pub struct Replay {
+  pub host: String,
+  pub cpu: String,
+  pub kernel: String,
+  pub microcode: String,
+  pub speed: f64,
+  ticks: Vec<(f64, Vec<Sample>)>,
+  pos: usize,
+  started: Option<Instant>,
+}
+impl Replay {
+  pub fn load(path: &Path, speed: f64) -> Result<Self, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    let mut lines = text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+    let header: Value = match lines.next() {
+      Some((_, line)) => serde_json::from_str(line).map_err(|e| format!("{}: bad header: {}", path.display(), e))?,
+      None => return Err(format!("{} is empty", path.display())),
+    };
+    if header.get("type").and_then(|t| t.as_str()) != Some("recording") {
+      return Err(format!("{} isn't a recording", path.display()))
+    }
+    match header.get("version").and_then(|v| v.as_u64()) {
+      Some(VERSION) => (),
+      other => return Err(format!("{} is recording version {:?}, this reads {}", path.display(), other, VERSION)),
+    }
+    let mut ticks = Vec::new();
+    for (num, line) in lines {
+      let tick = serde_json::from_str::<Value>(line).ok().and_then(|val| {
+        let time = val.get("time")?.as_f64()?;
+        let samples = val.get("samples")?.as_object()?.iter()
+          .filter_map(|(name, v)| v.as_f64().map(|v| Sample::new(name, v)))
+          .collect();
+        Some((time, samples))
+      });
+      match tick {
+        Some(tick) => ticks.push(tick),
+        // The last line of a recording that was still going can be cut short
+        None => { println!("Replay: {}: skipping bad line {}", path.display(), num + 1); }
+      }
+    }
+    if ticks.is_empty() { return Err(format!("{} has no ticks", path.display())) }
+    let field = |name: &str| header.get(name).and_then(|v| v.as_str()).unwrap_or("").to_owned();
+    Ok(Self {
+      host: field("host"),
+      cpu: field("cpu"),
+      // Not in the first recordings
+      kernel: field("kernel"),
+      microcode: field("microcode"),
+      speed: speed.max(0.0),
+      ticks,
+      pos: 0,
+      started: None,
+    })
+  }
+  /// How many ticks should have been played by now. The clock starts on the
+  /// first call, with the first tick due straight away.
+  pub fn due(&mut self) -> usize {
+    let started = *self.started.get_or_insert_with(Instant::now);
+    let remaining = self.ticks.len() - self.pos;
+    if self.speed == 0.0 { return remaining }
+    let until = self.ticks[0].0 + started.elapsed().as_secs_f64() * self.speed;
+    self.ticks[self.pos..].iter().take_while(|(time, _)| *time <= until).count()
+  }
+  pub fn next_tick(&mut self) -> Option<(f64, Vec<Sample>)> {
+    let tick = self.ticks.get(self.pos).cloned()?;
+    self.pos += 1;
+    Some(tick)
+  }
+  pub fn finished(&self) -> bool {
+    self.pos >= self.ticks.len()
+  }
+  /// Seconds covered by the recording
+  pub fn duration(&self) -> f64 {
+    self.ticks.last().map(|t| t.0).unwrap_or(0.0) - self.ticks[0].0
+  }
+  pub fn tick_count(&self) -> usize {
+    self.ticks.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    std::{thread, time::Duration},
+    crate::{
+      alert::{Alerts, Hooks},
+      config::Config,
+      metrics::{Collector, Derived, Store},
+      util::temp_dir,
+    },
+  };
+
+  const START: f64 = 1_700_000_000.0;
+
+  const RULES: &str = "
+[alert mem_high]
+metric = mem_pct
+threshold = 90
+for = 2
+severity = critical
+
+[alert load_high]
+metric = load.1
+op = >=
+threshold = 4
+";
+
+  /// Records `mem.used` out of 100 and `load.1` for each second
+  fn record(path: &Path, ticks: &[(f64, f64)]) {
+    let about = Snapshot { host: "rack1".to_owned(), cpu: "Test CPU".to_owned(), ..Snapshot::new() };
+    let mut recorder = Recorder::create(path, &about).unwrap();
+    for (idx, (used, load)) in ticks.iter().enumerate() {
+      let samples = vec![Sample::new("mem.total", 100.0), Sample::new("mem.used", *used), Sample::new("load.1", *load)];
+      recorder.record(START + idx as f64, &samples);
+    }
+  }
+
+  #[test]
+  fn replayed_alerts_fire_and_resolve() {
+    let dir = temp_dir("replay-alerts");
+    let path = dir.join("recording");
+    record(&path, &[
+      (50.0, 1.0), (95.0, 1.0), (95.0, 5.0), (95.0, 5.0), (95.0, 1.0),
+      (60.0, 1.0), (60.0, 1.0), (96.0, 1.0), (60.0, 1.0), (60.0, 1.0),
+    ]);
+    let replay = Replay::load(&path, 0.0).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!((replay.host.as_str(), replay.cpu.as_str(), replay.tick_count(), replay.duration()), ("rack1", "Test CPU", 10, 9.0));
+    let mut collector = Collector::new();
+    collector.replay = Some(replay);
+    let mut store = Store::new();
+    let mut derived = Derived::parse("mem_pct = mem.used / mem.total * 100");
+    let mut alerts = Alerts::from_config(&Config::parse(RULES));
+    let mut fired = Vec::new();
+    // At speed 0 the whole recording is due at once
+    assert_eq!(collector.due(), 10);
+    while !collector.replay.as_ref().unwrap().finished() {
+      let samples = collector.collect();
+      let now = collector.time();
+      store.record(now, &samples);
+      derived.apply(&mut store, now);
+      for t in alerts.evaluate(&store, now) {
+        fired.push((t.name, t.firing, t.ts - START));
+        if t.firing { assert!(alerts.firing().contains_key(&fired.last().unwrap().0)) }
+      }
+    }
+    // mem_high has to hold for 2s, so the spike at 7s never fires it
+    assert_eq!(fired, vec![
+      ("load_high".to_owned(), true, 2.0),
+      ("mem_high".to_owned(), true, 3.0),
+      ("load_high".to_owned(), false, 4.0),
+      ("mem_high".to_owned(), false, 5.0),
+    ]);
+    assert!(alerts.firing().is_empty());
+    assert_eq!((collector.time(), store.latest("mem_pct")), (START + 9.0, Some(60.0)));
+  }
+
+  /// Plays a recording that fires `load_high` through hooks gated the way
+  /// main does it, returning whether the hook ran
+  fn hook_ran(opt_in: bool) -> bool {
+    let dir = temp_dir(if opt_in { "replay-hook-on" } else { "replay-hook-off" });
+    let path = dir.join("recording");
+    let marker = dir.join("fired");
+    record(&path, &[(50.0, 1.0), (50.0, 5.0), (50.0, 1.0)]);
+    let mut collector = Collector::new();
+    collector.replay = Some(Replay::load(&path, 0.0).unwrap());
+    let config = Config::parse(&format!("{}command = touch {}\nmin_interval = 0\n", RULES, marker.display()));
+    let mut alerts = Alerts::from_config(&config);
+    let mut hooks = Hooks::from_config(&config);
+    hooks.enabled = collector.publishes(opt_in);
+    let mut store = Store::new();
+    let mut fired = 0;
+    while !collector.replay.as_ref().unwrap().finished() {
+      let samples = collector.collect();
+      store.record(collector.time(), &samples);
+      let transitions = alerts.evaluate(&store, collector.time());
+      fired += transitions.len();
+      hooks.notify(&transitions);
+    }
+    assert_eq!(fired, 2);
+    // Hooks run on their own thread, give it a moment either way
+    for _ in 0..100 {
+      if marker.exists() { break }
+      thread::sleep(Duration::from_millis(20));
+    }
+    let ran = marker.exists();
+    fs::remove_dir_all(&dir).unwrap();
+    ran
+  }
+
+  #[test]
+  fn replays_fire_no_hooks() {
+    assert!(!hook_ran(false));
+    assert!(hook_ran(true));
+  }
+
+  #[test]
+  fn bad_recordings() {
+    let dir = temp_dir("replay-bad");
+    let path = dir.join("recording");
+    fs::write(&path, "").unwrap();
+    assert!(Replay::load(&path, 1.0).err().unwrap().ends_with("is empty"));
+    fs::write(&path, "{\"type\":\"snapshot\"}\n").unwrap();
+    assert!(Replay::load(&path, 1.0).err().unwrap().ends_with("isn't a recording"));
+    fs::write(&path, "{\"type\":\"recording\",\"version\":2}\n{\"time\":1,\"samples\":{}}\n").unwrap();
+    assert!(Replay::load(&path, 1.0).err().unwrap().contains("is recording version Some(2)"));
+    // A recording cut off mid line keeps the ticks before it
+    fs::write(&path, "{\"type\":\"recording\",\"version\":1}\n{\"time\":1,\"samples\":{\"load.1\":2}}\n{\"time\":2,\"sam").unwrap();
+    let mut replay = Replay::load(&path, 1.0).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!((replay.tick_count(), replay.host.as_str(), replay.kernel.as_str()), (1, "", ""));
+    assert_eq!(replay.next_tick().map(|t| t.1), Some(vec![Sample::new("load.1", 2.0)]));
+    assert!(replay.finished());
+  }
+}
@@ -0,0 +1,263 @@
+
+use {
+  std::{
+    collections::VecDeque,
+  },
+  crate::{
+    config::{Config, Section},
+    metrics::{glob_match, Sample},
+    util::{HashMap, HashSet},
+  },
+};
+
+/// Turns successive readings of a counter into a per second rate. A counter
+/// that goes backwards either wrapped around at `wrap`, or was reset to
+/// zero, in which case everything it's counted since is new.
+#[derive(Debug, Clone, Default)]
+pub struct Rate {
+  pub wrap: Option<f64>,
+  last: Option<(f64, f64)>,
+}
+impl Rate {
+  pub fn new(wrap: Option<f64>) -> Self {
+    Self { wrap, last: None }
+  }
+  pub fn update(&mut self, ts: f64, value: f64) -> Option<f64> {
+    let last = self.last.replace((ts, value));
+    let (last_ts, last_value) = last?;
+    let dt = ts - last_ts;
+    if dt <= 0.0 { return None }
+    let delta = if value >= last_value {
+      value - last_value
+    } else {
+      match self.wrap {
+        // Only call it a wrap if the old value was in the top half of the
+        // range, otherwise it's far more likely to have been a reset.
+        Some(wrap) if last_value > wrap / 2.0 => wrap - last_value + value,
+        _ => value,
+      }
+    };
+    Some(delta / dt)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum Smoother {
+  /// Exponential moving average, `alpha` is the weight of each new value
+  Ema { alpha: f64, value: Option<f64> },
+  /// Plain average of the last `len` values
+  Window { len: usize, values: VecDeque<f64> },
+}
+impl Smoother {
+  pub fn ema(alpha: f64) -> Self {
+    Smoother::Ema { alpha: alpha.clamp(0.0, 1.0), value: None }
+  }
+  pub fn window(len: usize) -> Self {
+    Smoother::Window { len: len.max(1), values: VecDeque::new() }
+  }
+  /// `ema <alpha>` or `avg <samples>`
+  pub fn parse(spec: &str) -> Option<Self> {
+    let mut parts = spec.split_whitespace();
+    match (parts.next()?, parts.next()) {
+      ("ema", Some(alpha)) => alpha.parse().ok().map(Self::ema),
+      ("ema", None) => Some(Self::ema(0.3)),
+      ("avg", Some(len)) | ("window", Some(len)) => len.parse().ok().map(Self::window),
+      _ => None,
+    }
+  }
+  pub fn update(&mut self, sample: f64) -> f64 {
+    match self {
+      Smoother::Ema { alpha, value } => {
+        let next = match *value {
+          Some(prev) => prev + *alpha * (sample - prev),
+          None => sample,
+        };
+        *value = Some(next);
+        next
+      }
+      Smoother::Window { len, values } => {
+        values.push_back(sample);
+        while values.len() > *len { values.pop_front(); }
+        values.iter().sum::<f64>() / values.len() as f64
+      }
+    }
+  }
+}
+
+/// Rates and smoothing applied to samples before they're stored. Counters
+/// get a `<name>.rate` metric, smoothed metrics a `<name>.smooth` one; the
+/// raw value is always kept too.
+///
+///   [counters]
+///   net.*.rx = 64
+///   net.*.tx = 64
+///
+///   [smooth]
+///   mem.used = ema 0.3
+///   cpu.usage = avg 5
+///
+/// A counter's value is the bit width it wraps at, or `none`. Smoothing
+/// also applies to rates, e.g. `net.*.rx.rate = ema 0.5`.
+pub struct Filters {
+  pub counters: Vec<(String, Option<f64>)>,
+  pub smooth: Vec<(String, Smoother)>,
+  rates: HashMap<String, Rate>,
+  smoothers: HashMap<String, Smoother>,
+}
+impl Default for Filters {
+  fn default() -> Self {
+    Self {
+      counters: vec![
+        ("net.*.rx".to_owned(), Some(2f64.powi(64))),
+        ("net.*.tx".to_owned(), Some(2f64.powi(64))),
+      ],
+      smooth: vec![
+        ("mem.used".to_owned(), Smoother::ema(0.3)),
+        ("cpu.usage".to_owned(), Smoother::ema(0.3)),
+      ],
+      rates: HashMap::new(),
+      smoothers: HashMap::new(),
+    }
+  }
+}
+
+impl Filters {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// Sections that are left out keep the defaults, an empty section turns
+  /// that part off.
+  pub fn from_config(config: &Config) -> Self {
+    let mut out = Self::new();
+    if let Some(section) = config.section("counters") { out.counters = parse_counters(section); }
+    if let Some(section) = config.section("smooth") { out.smooth = parse_smooth(section); }
+    out
+  }
+  /// Adds rates and smoothed values for `samples` to the end of it
+  pub fn apply(&mut self, ts: f64, samples: &mut Vec<Sample>) {
+    let mut rates = Vec::new();
+    for sample in samples.iter() {
+      let wrap = match self.counters.iter().find(|(glob, _)| glob_match(glob, &sample.name)) {
+        Some((_, wrap)) => *wrap,
+        None => continue,
+      };
+      let rate = self.rates.entry(sample.name.clone()).or_insert_with(|| Rate::new(wrap));
+      if let Some(value) = rate.update(ts, sample.value) {
+        rates.push(Sample::new(&format!("{}.rate", sample.name), value));
+      }
+    }
+    samples.extend(rates);
+    let mut smoothed = Vec::new();
+    for sample in samples.iter() {
+      let proto = match self.smooth.iter().find(|(glob, _)| glob_match(glob, &sample.name)) {
+        Some((_, proto)) => proto,
+        None => continue,
+      };
+      let smoother = self.smoothers.entry(sample.name.clone()).or_insert_with(|| proto.clone());
+      let value = smoother.update(sample.value);
+      smoothed.push(Sample::new(&format!("{}.smooth", sample.name), value));
+    }
+    samples.extend(smoothed);
+    // Forget metrics that went away, like an unplugged interface
+    let seen: HashSet<&str> = samples.iter().map(|s| s.name.as_str()).collect();
+    self.rates.retain(|name, _| seen.contains(name.as_str()));
+    self.smoothers.retain(|name, _| seen.contains(name.as_str()));
+  }
+}
+
+fn parse_counters(section: &Section) -> Vec<(String, Option<f64>)> {
+  let mut out = Vec::new();
+  for (glob, wrap) in &section.vals {
+    let wrap = match wrap.as_str() {
+      "" | "none" | "0" => None,
+      bits => match bits.parse::<i32>() {
+        Ok(bits) if bits > 0 && bits <= 64 => Some(2f64.powi(bits)),
+        _ => { println!("Config: [counters] bad wrap for {}: {}", glob, bits); None }
+      },
+    };
+    out.push((glob.clone(), wrap));
+  }
+  out.sort_by_key(|(glob, _)| specificity(glob));
+  out
+}
+
+fn parse_smooth(section: &Section) -> Vec<(String, Smoother)> {
+  let mut out = Vec::new();
+  for (glob, spec) in &section.vals {
+    match Smoother::parse(spec) {
+      Some(smoother) => out.push((glob.clone(), smoother)),
+      None => println!("Config: [smooth] bad smoothing for {}: {}", glob, spec),
+    }
+  }
+  out.sort_by_key(|(glob, _)| specificity(glob));
+  out
+}
+
+// Config keys come out in no particular order, so when several globs match
+// a name the first one is picked by this: exact names, then longer globs.
+pub fn specificity(glob: &str) -> (bool, std::cmp::Reverse<usize>) {
+  (glob.contains('*'), std::cmp::Reverse(glob.len()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn first_sample_has_no_rate() {
+    let mut rate = Rate::new(None);
+    assert_eq!(rate.update(0.0, 100.0), None);
+    assert_eq!(rate.update(2.0, 110.0), Some(5.0));
+    // Nor does one that didn't move forward in time
+    assert_eq!(rate.update(2.0, 120.0), None);
+  }
+
+  #[test]
+  fn counters_wrap() {
+    let mut rate = Rate::new(Some(2f64.powi(32)));
+    rate.update(0.0, 2f64.powi(32) - 10.0);
+    assert_eq!(rate.update(1.0, 5.0), Some(15.0));
+    let mut rate = Rate::new(Some(2f64.powi(64)));
+    // Just under 2^64 an f64 only counts in steps of 2048
+    rate.update(0.0, 2f64.powi(64) - 4096.0);
+    assert_eq!(rate.update(2.0, 4096.0), Some(4096.0));
+  }
+
+  #[test]
+  fn a_reset_counts_from_zero() {
+    let mut rate = Rate::new(Some(2f64.powi(64)));
+    rate.update(0.0, 1000.0);
+    assert_eq!(rate.update(2.0, 10.0), Some(5.0));
+    let mut rate = Rate::new(None);
+    rate.update(0.0, 1000.0);
+    assert_eq!(rate.update(2.0, 10.0), Some(5.0));
+  }
+
+  #[test]
+  fn ema_converges() {
+    let mut ema = Smoother::ema(0.5);
+    assert_eq!(ema.update(0.0), 0.0);
+    assert_eq!(ema.update(10.0), 5.0);
+    let last = (0..20).map(|_| ema.update(10.0)).last().unwrap();
+    assert!((last - 10.0).abs() < 1e-4, "{}", last);
+    let mut avg = Smoother::window(2);
+    avg.update(1.0);
+    avg.update(3.0);
+    assert_eq!(avg.update(7.0), 5.0);
+  }
+
+  #[test]
+  fn forgets_metrics_that_went_away() {
+    let mut filters = Filters::new();
+    let mut samples = vec![Sample::new("net.eth0.rx", 0.0), Sample::new("net.wlan0.rx", 0.0), Sample::new("mem.used", 1.0)];
+    filters.apply(0.0, &mut samples);
+    let mut samples = vec![Sample::new("net.eth0.rx", 10.0), Sample::new("net.wlan0.rx", 20.0), Sample::new("mem.used", 1.0)];
+    filters.apply(1.0, &mut samples);
+    assert!(samples.iter().any(|s| s.name == "net.wlan0.rx.rate" && s.value == 20.0));
+    assert_eq!(filters.rates.len(), 2);
+    let mut samples = vec![Sample::new("net.eth0.rx", 20.0)];
+    filters.apply(2.0, &mut samples);
+    assert_eq!(filters.rates.keys().collect::<Vec<_>>(), vec!["net.eth0.rx"]);
+    assert!(filters.smoothers.is_empty());
+  }
+}
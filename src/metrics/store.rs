@@ -0,0 +1,303 @@
+
+use {
+  std::{
+    collections::{BTreeMap, VecDeque},
+  },
+  crate::{
+    config::Section,
+    metrics::{glob_match, Sample},
+  },
+};
+
+/// One bucket of a rollup tier, or a single raw sample when
+/// `min == max == avg`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+  /// Unix time in seconds of the sample, or of the start of the bucket
+  pub ts: f64,
+  pub min: f64,
+  pub max: f64,
+  pub avg: f64,
+}
+impl Point {
+  pub fn raw(ts: f64, value: f64) -> Self {
+    Self { ts, min: value, max: value, avg: value }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+  start: f64,
+  min: f64,
+  max: f64,
+  sum: f64,
+  count: u32,
+}
+impl Bucket {
+  fn new(start: f64, value: f64) -> Self {
+    Self { start, min: value, max: value, sum: value, count: 1 }
+  }
+  fn add(&mut self, value: f64) {
+    self.min = self.min.min(value);
+    self.max = self.max.max(value);
+    self.sum += value;
+    self.count += 1;
+  }
+  fn point(&self) -> Point {
+    Point { ts: self.start, min: self.min, max: self.max, avg: self.sum / self.count as f64 }
+  }
+}
+
+/// Resolution and length of one rollup tier
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TierSpec {
+  /// Seconds covered by each bucket
+  pub step: f64,
+  /// Buckets kept before the oldest is dropped
+  pub len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Tier {
+  spec: TierSpec,
+  buckets: VecDeque<Bucket>,
+}
+impl Tier {
+  fn add(&mut self, ts: f64, value: f64) {
+    let start = (ts / self.spec.step).floor() * self.spec.step;
+    match self.buckets.back_mut() {
+      Some(bucket) if bucket.start == start => bucket.add(value),
+      // Samples that arrive out of order are folded into the newest bucket
+      Some(bucket) if bucket.start > start => bucket.add(value),
+      _ => {
+        self.buckets.push_back(Bucket::new(start, value));
+        while self.buckets.len() > self.spec.len { self.buckets.pop_front(); }
+      }
+    }
+  }
+  fn oldest(&self) -> Option<f64> {
+    self.buckets.front().map(|b| b.start)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Series {
+  raw: VecDeque<(f64, f64)>,
+  raw_len: usize,
+  tiers: Vec<Tier>,
+}
+impl Series {
+  fn new(raw_len: usize, tiers: &[TierSpec]) -> Self {
+    Self {
+      raw: VecDeque::with_capacity(raw_len),
+      raw_len,
+      tiers: tiers.iter().map(|spec| Tier { spec: *spec, buckets: VecDeque::new() }).collect(),
+    }
+  }
+  pub fn push(&mut self, ts: f64, value: f64) {
+    self.raw.push_back((ts, value));
+    while self.raw.len() > self.raw_len { self.raw.pop_front(); }
+    for tier in &mut self.tiers { tier.add(ts, value); }
+  }
+  pub fn latest(&self) -> Option<(f64, f64)> {
+    self.raw.back().cloned()
+  }
+  /// Points between `from` and `to` (inclusive) from the finest resolution
+  /// that still reaches back to `from`. If nothing reaches back that far
+  /// the coarsest tier is used.
+  pub fn query(&self, from: f64, to: f64) -> Vec<Point> {
+    let raw_covers = self.raw.front().map(|(ts, _)| *ts <= from).unwrap_or(false);
+    if raw_covers || self.tiers.is_empty() {
+      return self.raw.iter()
+        .filter(|(ts, _)| *ts >= from && *ts <= to)
+        .map(|(ts, v)| Point::raw(*ts, *v))
+        .collect()
+    }
+    let tier = self.tiers.iter()
+      .find(|t| t.oldest().map(|ts| ts <= from).unwrap_or(false))
+      .unwrap_or_else(|| self.tiers.last().unwrap());
+    tier.buckets.iter()
+      // Keep the bucket `from` falls in even though it starts before it
+      .filter(|b| b.start + tier.spec.step > from && b.start <= to)
+      .map(|b| b.point())
+      .collect()
+  }
+  /// Every raw sample still held, oldest first
+  pub fn raw(&self) -> impl DoubleEndedIterator<Item = &(f64, f64)> {
+    self.raw.iter()
+  }
+}
+
+/// Recent history of every metric. A high resolution window of raw samples
+/// is kept for each one, along with min/max/avg rollups over longer spans.
+///
+/// A metric that hasn't had a sample for `stale` seconds, like the mount
+/// point of an unmounted filesystem, drops out of `glob` and `latest`. Its
+/// history stays queryable until it's older than every tier.
+#[derive(Debug, Clone)]
+pub struct Store {
+  pub raw_len: usize,
+  pub tiers: Vec<TierSpec>,
+  pub stale: f64,
+  series: BTreeMap<String, Series>,
+  /// The newest sample time seen, which staleness is measured from so it
+  /// works the same for replayed samples as for live ones
+  newest: f64,
+}
+impl Default for Store {
+  fn default() -> Self {
+    // Ten minutes of one second samples, a day of minutes and a week of
+    // quarter hours.
+    Self::with_tiers(600, &[
+      TierSpec { step: 60.0, len: 24 * 60 },
+      TierSpec { step: 900.0, len: 7 * 24 * 4 },
+    ])
+  }
+}
+
+impl Store {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn with_tiers(raw_len: usize, tiers: &[TierSpec]) -> Self {
+    let mut tiers = tiers.to_vec();
+    tiers.sort_by(|a, b| a.step.partial_cmp(&b.step).unwrap_or(std::cmp::Ordering::Equal));
+    Self { raw_len: raw_len.max(1), tiers, stale: 5.0, series: BTreeMap::new(), newest: 0.0 }
+  }
+  /// `[store]` takes `raw` (samples kept at full resolution), `tiers`, a
+  /// list of `<step seconds>x<buckets>` like `60x1440, 900x672`, and
+  /// `stale`, the seconds after which a metric with no new samples is gone.
+  pub fn from_section(section: Option<&Section>) -> Self {
+    let section = match section {
+      Some(section) => section,
+      None => return Self::new(),
+    };
+    let default = Self::new();
+    let raw_len = section.get_or("raw", default.raw_len);
+    let tiers = match section.get("tiers") {
+      Some(list) => match parse_tiers(list) {
+        Some(tiers) => tiers,
+        None => { println!("Config: [store] bad tiers: {}", list); default.tiers }
+      },
+      None => default.tiers,
+    };
+    let mut out = Self::with_tiers(raw_len, &tiers);
+    out.stale = section.get_or("stale", out.stale).max(1.0);
+    out
+  }
+  pub fn record(&mut self, ts: f64, samples: &[Sample]) {
+    for sample in samples { self.push(&sample.name, ts, sample.value); }
+    self.prune();
+  }
+  pub fn push(&mut self, name: &str, ts: f64, value: f64) {
+    if !value.is_finite() { return }
+    if !self.series.contains_key(name) {
+      self.series.insert(name.to_owned(), Series::new(self.raw_len, &self.tiers));
+    }
+    self.series.get_mut(name).unwrap().push(ts, value);
+    self.newest = self.newest.max(ts);
+  }
+  /// Forgets metrics with nothing left in any tier's span
+  fn prune(&mut self) {
+    let span = self.tiers.iter().map(|t| t.step * t.len as f64).fold(self.raw_len as f64, f64::max);
+    let oldest = self.newest - span;
+    self.series.retain(|_, series| series.latest().map(|(ts, _)| ts >= oldest).unwrap_or(false));
+  }
+  fn is_fresh(&self, series: &Series) -> bool {
+    series.latest().map(|(ts, _)| ts >= self.newest - self.stale).unwrap_or(false)
+  }
+  /// The newest sample time seen
+  pub fn newest(&self) -> f64 {
+    self.newest
+  }
+  pub fn series(&self, name: &str) -> Option<&Series> {
+    self.series.get(name)
+  }
+  /// The last value of a metric that's still being sampled
+  pub fn latest(&self, name: &str) -> Option<f64> {
+    self.series.get(name).filter(|s| self.is_fresh(s)).and_then(|s| s.latest()).map(|(_, v)| v)
+  }
+  pub fn query(&self, name: &str, from: f64, to: f64) -> Vec<Point> {
+    match self.series.get(name) {
+      Some(series) => series.query(from, to),
+      None => Vec::new(),
+    }
+  }
+  /// Every metric held, including ones no longer being sampled
+  pub fn names(&self) -> Vec<&str> {
+    self.series.keys().map(|k| k.as_str()).collect()
+  }
+  /// Names matching a pattern where `*` stands for any run of characters
+  pub fn glob(&self, pattern: &str) -> Vec<&str> {
+    self.series.iter()
+      .filter(|(k, s)| glob_match(pattern, k) && self.is_fresh(s))
+      .map(|(k, _)| k.as_str())
+      .collect()
+  }
+}
+
+pub fn parse_tiers(list: &str) -> Option<Vec<TierSpec>> {
+  let mut out = Vec::new();
+  for item in list.split(',') {
+    let mut parts = item.trim().splitn(2, 'x');
+    let step: f64 = parts.next()?.trim().parse().ok()?;
+    let len: usize = parts.next()?.trim().parse().ok()?;
+    if step <= 0.0 || len == 0 { return None }
+    out.push(TierSpec { step, len });
+  }
+  Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn store() -> Store {
+    Store::with_tiers(10, &[TierSpec { step: 10.0, len: 3 }])
+  }
+
+  #[test]
+  fn rollups_keep_min_max_avg() {
+    let mut store = store();
+    for (i, v) in [1.0, 5.0, 3.0].iter().enumerate() { store.push("x", 100.0 + i as f64, *v); }
+    for i in 0..20 { store.push("x", 110.0 + i as f64, 0.0); }
+    let points = store.query("x", 100.0, 130.0);
+    assert_eq!(points[0], Point { ts: 100.0, min: 1.0, max: 5.0, avg: 3.0 });
+  }
+
+  #[test]
+  fn stale_series_drop_out() {
+    let mut store = store();
+    store.record(100.0, &[Sample::new("disk./mnt.total", 1.0), Sample::new("disk./.total", 2.0)]);
+    assert_eq!(store.glob("disk.*.total"), vec!["disk./.total", "disk./mnt.total"]);
+    // /mnt is unmounted
+    for ts in 101..=110 { store.record(ts as f64, &[Sample::new("disk./.total", 2.0)]); }
+    assert_eq!(store.glob("disk.*.total"), vec!["disk./.total"]);
+    assert_eq!(store.names(), vec!["disk./.total", "disk./mnt.total"]);
+    assert_eq!(store.latest("disk./mnt.total"), None);
+    // Still there for graphs until it's older than every tier
+    assert_eq!(store.query("disk./mnt.total", 100.0, 110.0).len(), 1);
+    for ts in 111..=140 { store.record(ts as f64, &[Sample::new("disk./.total", 2.0)]); }
+    assert!(store.series("disk./mnt.total").is_none());
+    assert_eq!(store.names(), vec!["disk./.total"]);
+  }
+
+  #[test]
+  fn staleness_follows_sample_time() {
+    let mut store = store();
+    // A replay from long ago is as fresh as a live sample
+    store.record(1000.0, &[Sample::new("cpu.usage", 12.0)]);
+    assert_eq!(store.latest("cpu.usage"), Some(12.0));
+    assert_eq!(store.newest(), 1000.0);
+  }
+
+  #[test]
+  fn tiers_parse() {
+    assert_eq!(parse_tiers("60x1440, 900x672"), Some(vec![
+      TierSpec { step: 60.0, len: 1440 },
+      TierSpec { step: 900.0, len: 672 },
+    ]));
+    assert_eq!(parse_tiers("60"), None);
+    assert_eq!(parse_tiers("0x5"), None);
+  }
+}
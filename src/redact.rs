@@ -0,0 +1,262 @@
+
+use {
+  std::{
+    env,
+    sync::RwLock,
+  },
+  regex::{Captures, Regex},
+  crate::{
+    config::{Config, Section},
+    snapshot::hostname,
+    stats::proctree::read_passwd,
+  },
+};
+
+// Redaction is applied where text leaves the program: every GuiText as it's
+// set, everything printed (main.rs wraps println!), and the --once, --report,
+// history dump and log file output. Snapshots sent to viewers, the grid
+// directory, StatsD and Prometheus are left alone on purpose: host numbers
+// differ from one machine to the next, so masking them would leave whatever
+// reads them unable to tell hosts apart.
+
+/// The built in kinds of thing that get masked, all on unless turned off
+pub const KINDS: [&str; 6] = ["hosts", "ips", "macs", "users", "home", "commands"];
+
+static REDACTOR: RwLock<Option<Redactor>> = RwLock::new(None);
+
+enum Mask {
+  Text(String),
+  /// Only where it isn't part of a longer word, like `std::io`
+  Ipv6,
+  /// Keeps a metric suffix like `.used` on the end
+  Home,
+}
+
+/// Masks names and addresses that shouldn't end up in screenshots or logs.
+///
+///   [redact]
+///   hosts = true
+///   ips = true
+///   macs = true
+///   users = true
+///   home = true
+///   commands = true
+///   ticket = INC-[0-9]+
+///
+/// The built in kinds are all on by default. Any other key is a regex of
+/// your own, masked as `[key]`. This machine's name shows as `[host]` and
+/// other hosts as `[host 2]`, `[host 3]` and so on, in the order they're
+/// seen, so they can still be told apart.
+pub struct Redactor {
+  rules: Vec<(Regex, Mask)>,
+  /// Host names, this machine's first
+  hosts: Vec<String>,
+  host_re: Option<Regex>,
+  mask_hosts: bool,
+}
+impl Redactor {
+  pub fn from_config(config: &Config, section: &Section) -> Self {
+    let on = |kind: &str| section.get_or(kind, true);
+    let mut rules = Vec::new();
+    let mut custom: Vec<(&String, &String)> = section.vals.iter().filter(|(k, _)| !KINDS.contains(&k.as_str())).collect();
+    custom.sort();
+    for (name, pattern) in custom {
+      match Regex::new(pattern) {
+        Ok(re) => rules.push((re, Mask::Text(format!("[{}]", name)))),
+        Err(e) => println!("Config: [redact] bad pattern for {}: {}", name, e),
+      }
+    }
+    if on("commands") {
+      let mut commands: Vec<String> = config.sections("command").iter().filter_map(|s| s.get("cmd"))
+        .chain(config.sections("alert").iter().filter_map(|s| s.get("command")))
+        .filter(|c| !c.is_empty())
+        .map(|c| c.to_owned())
+        .collect();
+      if let Some(re) = literals(&mut commands, false) { rules.push((re, Mask::Text("[command]".to_owned()))) }
+    }
+    if on("home") {
+      rules.push((Regex::new(r#"/home/[^\s,;:'"()\[\]]+"#).unwrap(), Mask::Home));
+    }
+    if on("users") {
+      let mut users = login_users();
+      if let Some(re) = literals(&mut users, true) { rules.push((re, Mask::Text("[user]".to_owned()))) }
+    }
+    if on("macs") {
+      rules.push((Regex::new(r"\b[0-9a-fA-F]{2}(?:[:-][0-9a-fA-F]{2}){5}\b").unwrap(), Mask::Text("[mac]".to_owned())));
+    }
+    if on("ips") {
+      rules.push((Regex::new(r"[0-9a-fA-F:]*::[0-9a-fA-F:]*|\b(?:[0-9a-fA-F]{1,4}:){7}[0-9a-fA-F]{1,4}\b").unwrap(), Mask::Ipv6));
+      let octet = r"(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)";
+      rules.push((Regex::new(&format!(r"\b{o}(?:\.{o}){{3}}\b", o = octet)).unwrap(), Mask::Text("[ip]".to_owned())));
+    }
+    let mut out = Self { rules, hosts: Vec::new(), host_re: None, mask_hosts: on("hosts") };
+    out.add_host(&hostname());
+    for section in config.sections("remote") {
+      if let Some(host) = section.get("host") { out.add_host(host_part(host)); }
+    }
+    out
+  }
+  pub fn add_host(&mut self, host: &str) {
+    if !self.mask_hosts || host.is_empty() || self.hosts.iter().any(|h| h == host) { return }
+    self.hosts.push(host.to_owned());
+    let mut names = self.hosts.clone();
+    self.host_re = literals(&mut names, true);
+  }
+  pub fn apply(&self, text: &str) -> String {
+    let mut out = text.to_owned();
+    for (re, mask) in &self.rules {
+      out = match mask {
+        Mask::Text(mask) => re.replace_all(&out, mask.as_str()).into_owned(),
+        Mask::Ipv6 => ipv6(re, &out),
+        Mask::Home => re.replace_all(&out, |caps: &Captures| {
+          let path = &caps[0];
+          let suffix = [".total", ".avail", ".used"].iter().filter_map(|s| path.find(s)).min().map(|idx| &path[idx..]);
+          format!("/home/[path]{}", suffix.unwrap_or(""))
+        }).into_owned(),
+      };
+    }
+    // Hosts go last, so a name inside a path or command is already gone
+    if let Some(ref re) = self.host_re {
+      out = re.replace_all(&out, |caps: &Captures| {
+        match self.hosts.iter().position(|h| h == &caps[0]) {
+          Some(0) | None => "[host]".to_owned(),
+          Some(idx) => format!("[host {}]", idx + 1),
+        }
+      }).into_owned();
+    }
+    out
+  }
+}
+
+/// Turns redaction on for the rest of the run if there's a `[redact]`
+/// section
+pub fn init(config: &Config) {
+  if let Some(section) = config.section("redact") {
+    // Built before taking the lock, since config errors get printed
+    let redactor = Redactor::from_config(config, section);
+    if let Ok(mut global) = REDACTOR.write() { *global = Some(redactor); }
+  }
+}
+
+/// `text` with anything sensitive masked, or as it is if redaction is off
+pub fn redact(text: &str) -> String {
+  match REDACTOR.read() {
+    Ok(ref global) => match **global {
+      Some(ref redactor) => redactor.apply(text),
+      None => text.to_owned(),
+    },
+    Err(_) => text.to_owned(),
+  }
+}
+
+/// Masks `host` from now on, for hosts only learnt about from the network
+pub fn add_host(host: &str) {
+  if let Ok(mut global) = REDACTOR.write() {
+    if let Some(ref mut redactor) = *global { redactor.add_host(host); }
+  }
+}
+
+/// A regex matching any of `words`, longest first so a name isn't cut short
+/// by another it starts with
+fn literals(words: &mut [String], whole_words: bool) -> Option<Regex> {
+  if words.is_empty() { return None }
+  words.sort_by_key(|w| std::cmp::Reverse(w.len()));
+  let alts: Vec<String> = words.iter().map(|w| regex::escape(w)).collect();
+  let pattern = if whole_words { format!(r"\b(?:{})\b", alts.join("|")) } else { alts.join("|") };
+  Regex::new(&pattern).ok()
+}
+
+fn ipv6(re: &Regex, text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut last = 0;
+  for m in re.find_iter(text) {
+    let word = |c: Option<char>| c.map(|c| c.is_alphanumeric() || c == '_').unwrap_or(false);
+    let standalone = !word(text[..m.start()].chars().next_back()) && !word(text[m.end()..].chars().next());
+    if standalone && m.as_str().chars().any(|c| c.is_ascii_hexdigit()) {
+      out.push_str(&text[last..m.start()]);
+      out.push_str("[ip]");
+      last = m.end();
+    }
+  }
+  out.push_str(&text[last..]);
+  out
+}
+
+/// People's accounts, leaving out root and system users
+fn login_users() -> Vec<String> {
+  let mut out: Vec<String> = read_passwd("/etc/passwd").into_iter()
+    .filter(|(uid, _)| *uid >= 1000 && *uid < 65534)
+    .map(|(_, name)| name)
+    .collect();
+  for var in &["USER", "LOGNAME", "SUDO_USER"] {
+    if let Ok(user) = env::var(var) {
+      if !user.is_empty() && user != "root" && !out.contains(&user) { out.push(user) }
+    }
+  }
+  out
+}
+
+/// The host out of `host:port` or `[v6]:port`
+pub fn host_part(addr: &str) -> &str {
+  let addr = addr.trim();
+  if let Some(rest) = addr.strip_prefix('[') { return rest.split(']').next().unwrap_or(rest) }
+  match addr.rfind(':') {
+    Some(idx) if addr.matches(':').count() == 1 => &addr[..idx],
+    _ => addr,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn redactor(options: &str) -> Redactor {
+    let config = Config::parse(&format!("[redact]\n{}", options));
+    Redactor::from_config(&config, config.section("redact").unwrap())
+  }
+
+  #[test]
+  fn ipv6_only_as_a_word_of_its_own() {
+    let r = redactor("users = false\nhosts = false");
+    assert_eq!(r.apply("peer fe80::1ff:fe23:4567:890a%eth0 up"), "peer [ip]%eth0 up");
+    assert_eq!(r.apply("listening on :: and ::1"), "listening on :: and [ip]");
+    assert_eq!(r.apply("2001:0db8:85a3:0000:0000:8a2e:0370:7334"), "[ip]");
+    // Rust paths and clock times look a lot like addresses
+    assert_eq!(r.apply("error in std::io::Read at 12:30:45"), "error in std::io::Read at 12:30:45");
+    assert_eq!(r.apply("from 192.168.1.20 via 10.0.0.1, not 300.1.1.1"), "from [ip] via [ip], not 300.1.1.1");
+    assert_eq!(r.apply("eth0 de:ad:be:ef:00:01"), "eth0 [mac]");
+  }
+
+  #[test]
+  fn home_keeps_metric_suffixes() {
+    let r = redactor("users = false\nhosts = false");
+    assert_eq!(r.apply("disk./home/alice.used"), "disk./home/[path].used");
+    assert_eq!(r.apply("disk./home/alice/media.v2.avail 5 GiB"), "disk./home/[path].avail 5 GiB");
+    assert_eq!(r.apply("open /home/alice/notes.txt: denied"), "open /home/[path]: denied");
+    let off = redactor("home = false\nusers = false\nhosts = false");
+    assert_eq!(off.apply("disk./home/alice.used"), "disk./home/alice.used");
+  }
+
+  #[test]
+  fn hosts_numbered_in_order_seen() {
+    let mut r = redactor("users = false\n[remote a]\nhost = rack7:9102");
+    r.add_host("rack12");
+    r.add_host("rack7");
+    let this = hostname();
+    assert_eq!(r.apply(&format!("{} sees rack7 and rack12", this)), "[host] sees [host 2] and [host 3]");
+    // Whole names only, and the longer of two that share a start
+    assert_eq!(r.apply("rack70 rack1"), "rack70 rack1");
+    let off = redactor("hosts = false\nusers = false");
+    assert_eq!(off.apply("rack7"), "rack7");
+  }
+
+  #[test]
+  fn custom_patterns_and_commands() {
+    let config = Config::parse("[redact]\nusers = false\nticket = INC-[0-9]+\n[command up]\ncmd = ssh ops@bastion uptime\n");
+    let r = Redactor::from_config(&config, config.section("redact").unwrap());
+    assert_eq!(r.apply("INC-4411: ssh ops@bastion uptime failed"), "[ticket]: [command] failed");
+    assert_eq!(host_part("[fe80::1]:9102"), "fe80::1");
+    assert_eq!(host_part("rack1:9102"), "rack1");
+    assert_eq!(host_part("fe80::1"), "fe80::1");
+  }
+}
@@ -0,0 +1,144 @@
+
+use {
+  std::{
+    io::{self, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+  },
+  crate::{
+    remote::Message,
+    snapshot::{hostname, Snapshot},
+    util::{Arc, Mutex},
+  },
+};
+
+/// How long a viewer gets to take a message before it's dropped, so a stuck
+/// viewer can't hold up sampling for long
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Each viewer's address along with its connection
+type Viewers = Arc<Mutex<Vec<(String, Arc<TcpStream>)>>>;
+
+/// The serving end of `--agent`. Viewers can connect at any time; each one
+/// gets the hello, the latest snapshot straight away, then every snapshot
+/// after that.
+pub struct Agent {
+  /// Where it ended up listening, with the port filled in if 0 was asked for
+  pub addr: String,
+  clients: Viewers,
+  latest: Arc<Mutex<Option<String>>>,
+}
+impl Agent {
+  pub fn listen(addr: &str) -> io::Result<Self> {
+    let listener = TcpListener::bind(addr)?;
+    let addr = listener.local_addr().map(|a| a.to_string()).unwrap_or_else(|_| addr.to_owned());
+    let clients: Viewers = Arc::new(Mutex::new(Vec::new()));
+    let latest: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let (accepted, last) = (clients.clone(), latest.clone());
+    let hello = Message::hello(&hostname()).encode();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let mut stream = match stream {
+          Ok(stream) => stream,
+          Err(e) => { println!("Agent: accept failed: {}", e); continue }
+        };
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+        let _ = stream.set_nodelay(true);
+        let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+        let mut greeting = hello.clone();
+        if let Some(ref line) = *last.lock().unwrap() { greeting.push_str(line) }
+        match stream.write_all(greeting.as_bytes()) {
+          Ok(()) => {
+            println!("Agent: viewer {} connected", peer);
+            accepted.lock().unwrap().push((peer, Arc::new(stream)));
+          }
+          Err(e) => println!("Agent: viewer {} dropped: {}", peer, e),
+        }
+      }
+    });
+    Ok(Self { addr, clients, latest })
+  }
+  /// Sends a snapshot to every viewer, dropping any that have gone away.
+  /// The writes happen outside the lock, so a slow viewer doesn't keep new
+  /// ones from being accepted while it times out.
+  pub fn broadcast(&self, snapshot: &Snapshot) {
+    let line = Message::Snapshot(snapshot.clone()).encode();
+    *self.latest.lock().unwrap() = Some(line.clone());
+    let clients = self.clients.lock().unwrap().clone();
+    let mut gone = Vec::new();
+    for (peer, stream) in clients {
+      // Writing only needs a shared TcpStream
+      match (&*stream).write_all(line.as_bytes()) {
+        Ok(()) => (),
+        Err(e) => {
+          println!("Agent: viewer {} dropped: {}", peer, e);
+          gone.push(stream);
+        }
+      }
+    }
+    if gone.is_empty() { return }
+    self.clients.lock().unwrap().retain(|(_, stream)| !gone.iter().any(|g| Arc::ptr_eq(g, stream)));
+  }
+  /// How many viewers are connected
+  pub fn viewers(&self) -> usize {
+    self.clients.lock().unwrap().len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    std::{io::{BufRead, BufReader}, time::Instant},
+    crate::{remote::view_widgets, widget::Widget},
+  };
+
+  fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+      assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+      thread::sleep(Duration::from_millis(10));
+    }
+  }
+
+  fn snapshot(usage: f64) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    snapshot.host = "rack1".to_owned();
+    snapshot.metrics.insert("cpu.usage".to_owned(), usage);
+    snapshot
+  }
+
+  #[test]
+  fn viewer_gets_snapshots() {
+    let agent = Agent::listen("127.0.0.1:0").unwrap();
+    assert!(!agent.addr.ends_with(":0"));
+    agent.broadcast(&snapshot(12.5));
+    let mut viewer = view_widgets(std::slice::from_ref(&agent.addr)).pop().unwrap();
+    // The latest snapshot comes with the hello, before any broadcast
+    let mut rows = Vec::new();
+    wait_for("the first snapshot", || {
+      if let Some(update) = viewer.poll() { rows = update.rows.into_iter().map(|r| r.text).collect() }
+      rows.first().map(|r| r == "rack1").unwrap_or(false)
+    });
+    assert_eq!(rows[1], "CPU 12.5%");
+    agent.broadcast(&snapshot(40.0));
+    wait_for("the next snapshot", || {
+      if let Some(update) = viewer.poll() { rows = update.rows.into_iter().map(|r| r.text).collect() }
+      rows.get(1).map(|r| r == "CPU 40.0%").unwrap_or(false)
+    });
+  }
+
+  #[test]
+  fn drops_viewers_that_went_away() {
+    let agent = Agent::listen("127.0.0.1:0").unwrap();
+    let mut stream = BufReader::new(TcpStream::connect(&agent.addr).unwrap());
+    let mut hello = String::new();
+    stream.read_line(&mut hello).unwrap();
+    assert_eq!(Message::decode(&hello), Ok(Message::hello(&hostname())));
+    wait_for("the viewer", || agent.viewers() == 1);
+    drop(stream);
+    // The first write after a close can still go through, a later one can't
+    wait_for("the viewer to be dropped", || { agent.broadcast(&snapshot(1.0)); agent.viewers() == 0 });
+  }
+}
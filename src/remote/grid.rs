@@ -0,0 +1,320 @@
+
+use {
+  std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+  },
+  crate::{
+    alert::{fmt_secs, Severity},
+    config::Section,
+    redact,
+    gamemgr::GameMgr,
+    remote::panel_lines,
+    snapshot::Snapshot,
+    text::{TextMgr, LINE_HEIGHT},
+    widget::{row_label, COLOUR_ERROR, COLOUR_NORMAL, COLOUR_WARN},
+  },
+};
+
+// The part of the window tiles go in, under the title
+const LEFT: f32 = 0.02;
+const TOP: f32 = 0.15;
+const WIDTH: f32 = 0.96;
+const HEIGHT: f32 = 0.83;
+/// Tiles are laid out to come as close to this width over height as they can
+const TILE_SHAPE: f32 = 2.0;
+/// Longest line a tile should fit without wrapping, in characters
+const TILE_CHARS: f32 = 32.0;
+const MAX_FONT_SIZE: f32 = 2.0;
+
+/// A page of tiles, one per host, read from snapshots that every host writes
+/// into a shared directory.
+///
+///   [grid]
+///   dir = /mnt/shared/raumEnSysInfo
+///   publish = true
+///   interval = 10
+///   stale = 60
+///   lines = 6
+///   show = false
+///
+/// With `publish` this host writes its own `<host>.json` every `interval`
+/// seconds, along with its firing alerts. Tiles whose file hasn't changed
+/// for `stale` seconds are marked as such. `show` starts on the grid page,
+/// otherwise G switches to it.
+pub struct Grid {
+  pub dir: PathBuf,
+  pub publish: bool,
+  pub interval: f64,
+  pub stale: f64,
+  /// Rows per tile, the host's name and badges included
+  pub lines: usize,
+  pub show: bool,
+  tiles: Vec<Tile>,
+  /// Tiles with labels made for them so far
+  slots: usize,
+  /// Tile count and window size the labels were last placed for
+  layout: Option<(usize, (u32, u32))>,
+  next_publish: f64,
+  failing: bool,
+  hidden: bool,
+}
+
+/// A line of a tile and its colour
+type Row = (String, (f32, f32, f32));
+
+struct Tile {
+  host: String,
+  snapshot: Result<Snapshot, String>,
+  /// Seconds since its file was written
+  age: f64,
+}
+
+impl Grid {
+  pub fn from_section(section: &Section) -> Self {
+    let dir = section.get_str("dir", "");
+    if dir.is_empty() { println!("Config: [grid] no dir"); }
+    let interval = section.get_or("interval", 10.0_f64).max(1.0);
+    Self {
+      dir: PathBuf::from(dir),
+      publish: section.get_or("publish", true),
+      interval,
+      stale: section.get_or("stale", 60.0_f64).max(interval),
+      lines: section.get_or("lines", 6).max(1),
+      show: section.get_or("show", false),
+      tiles: Vec::new(),
+      slots: 0,
+      layout: None,
+      next_publish: 0.0,
+      failing: false,
+      hidden: true,
+    }
+  }
+  /// Writes this host's snapshot into the shared directory, if it's time to
+  pub fn publish(&mut self, snapshot: &Snapshot, now: f64) {
+    if !self.publish || self.dir.as_os_str().is_empty() || now < self.next_publish { return }
+    self.next_publish = now + self.interval;
+    let name = snapshot.host.replace(&['/', '\\'][..], "_");
+    // Written aside and renamed so readers never see half a file
+    let tmp = self.dir.join(format!(".{}.json.tmp", name));
+    let path = self.dir.join(format!("{}.json", name));
+    let text = serde_json::to_string_pretty(&snapshot.to_json()).unwrap_or_default();
+    match fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, &path)) {
+      Ok(()) => self.failing = false,
+      Err(e) => {
+        if !self.failing { println!("Grid: can't write {}: {}", path.display(), e); }
+        self.failing = true;
+      }
+    }
+  }
+  /// Reads every host's snapshot back in
+  pub fn refresh(&mut self, now: f64) {
+    if self.dir.as_os_str().is_empty() { return }
+    let entries = match fs::read_dir(&self.dir) {
+      Ok(entries) => entries,
+      Err(e) => {
+        self.tiles = vec![Tile { host: self.dir.display().to_string(), snapshot: Err(e.to_string()), age: 0.0 }];
+        return
+      }
+    };
+    let mut tiles = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+      let path = entry.path();
+      let stem = match path.file_stem().and_then(|s| s.to_str()) {
+        Some(stem) if !stem.starts_with('.') && path.extension().map(|x| x == "json").unwrap_or(false) => stem,
+        _ => continue,
+      };
+      let snapshot = Snapshot::load(&path.to_string_lossy());
+      let host = match snapshot {
+        Ok(ref s) if !s.host.is_empty() => s.host.clone(),
+        _ => stem.to_owned(),
+      };
+      redact::add_host(&host);
+      tiles.push(Tile { host, snapshot, age: now - modified(&path).unwrap_or(0.0) });
+    }
+    tiles.sort_by(|a, b| a.host.cmp(&b.host));
+    self.tiles = tiles;
+  }
+  /// Hides the grid while another page is up
+  pub fn set_hidden(&mut self, hidden: bool, textmgr: &mut TextMgr, mgr: GameMgr) {
+    self.hidden = hidden;
+    if hidden {
+      for slot in 0..self.slots {
+        for row in 0..self.lines { textmgr.disable_label(&row_label(&slot_label(slot), row)); }
+      }
+    } else {
+      self.show(textmgr, mgr);
+    }
+  }
+  /// Lays the tiles out for the window and fills them in
+  pub fn show(&mut self, textmgr: &mut TextMgr, mgr: GameMgr) {
+    if self.hidden { return }
+    let mut tiles: Vec<Vec<Row>> = self.tiles.iter().map(|t| self.tile_rows(t)).collect();
+    if tiles.is_empty() {
+      tiles.push(vec![(format!("No snapshots in {} yet", self.dir.display()), COLOUR_WARN)]);
+    }
+    let dims = mgr.dimensions();
+    while self.slots < tiles.len() {
+      for row in 0..self.lines {
+        textmgr.new_text(mgr.clone(), &row_label(&slot_label(self.slots), row), "-", "sans", 1.0, 0.0, 0.0, 1.0, false, false);
+      }
+      self.slots += 1;
+      self.layout = None;
+    }
+    if self.layout != Some((tiles.len(), dims)) {
+      self.place(textmgr, mgr.clone(), tiles.len(), dims);
+      self.layout = Some((tiles.len(), dims));
+    }
+    for slot in 0..self.slots {
+      for row in 0..self.lines {
+        let label = row_label(&slot_label(slot), row);
+        match tiles.get(slot).and_then(|t| t.get(row)) {
+          Some((text, (r, g, b))) => {
+            textmgr.update_text(mgr.clone(), &label, text);
+            if let Some(gtext) = textmgr.texts.get_mut(&label) { gtext.set_colour(*r, *g, *b); }
+            textmgr.enable_label(mgr.clone(), &label);
+          }
+          None => textmgr.disable_label(&label),
+        }
+      }
+    }
+  }
+  fn place(&self, textmgr: &mut TextMgr, mgr: GameMgr, count: usize, dims: (u32, u32)) {
+    let aspect = dims.0 as f32 / dims.1.max(1) as f32;
+    let (cols, rows) = grid_shape(count, aspect * WIDTH / HEIGHT);
+    let (tile_w, tile_h) = (WIDTH / cols as f32, HEIGHT / rows as f32);
+    // Text height is a share of the window height, and a character is about
+    // half as wide as it is tall, so the width limit depends on the aspect
+    let by_height = tile_h * 0.9 / (self.lines as f32 * LINE_HEIGHT);
+    let by_width = tile_w * 0.95 * aspect / (TILE_CHARS * LINE_HEIGHT * 0.5);
+    let size = by_height.min(by_width).min(MAX_FONT_SIZE);
+    for slot in 0..self.slots {
+      let (x, y) = (LEFT + (slot % cols) as f32 * tile_w, TOP + (slot / cols) as f32 * tile_h);
+      for row in 0..self.lines {
+        let y = y + row as f32 * LINE_HEIGHT * size;
+        textmgr.place_label(mgr.clone(), &row_label(&slot_label(slot), row), x, y, size, tile_w * 0.95);
+      }
+    }
+  }
+  fn tile_rows(&self, tile: &Tile) -> Vec<Row> {
+    let snapshot = match tile.snapshot {
+      Ok(ref snapshot) => snapshot,
+      Err(ref e) => return vec![(format!("{}: {}", tile.host, e), COLOUR_ERROR)],
+    };
+    let mut title = tile.host.clone();
+    let mut colour = COLOUR_NORMAL;
+    let worst = snapshot.alerts.values().filter_map(|sev| Severity::parse(sev)).max();
+    if let Some(worst) = worst {
+      title.push_str(&format!("  [{} {}]", worst.name(), snapshot.alerts.len()));
+      colour = worst.colour();
+    }
+    let stale = tile.age > self.stale;
+    if stale {
+      // Alerts from an old file may well be over, so staleness wins
+      title.push_str(&format!("  stale {}", fmt_secs(tile.age)));
+      colour = COLOUR_WARN;
+    }
+    let body = if stale { COLOUR_WARN } else { COLOUR_NORMAL };
+    let mut out = vec![(title, colour)];
+    for line in panel_lines(snapshot, self.lines - 1) { out.push((line, body)) }
+    out
+  }
+}
+
+fn slot_label(slot: usize) -> String {
+  format!("Grid {}", slot)
+}
+
+/// Columns and rows for `count` tiles in an area `aspect` times as wide as
+/// it is tall, picking whichever leaves tiles closest to `TILE_SHAPE`
+pub fn grid_shape(count: usize, aspect: f32) -> (usize, usize) {
+  let count = count.max(1);
+  let mut best = (1, count);
+  let mut best_score = f32::MAX;
+  for cols in 1..=count {
+    let rows = count.div_ceil(cols);
+    // Another column that leaves the same number of rows only shrinks tiles
+    if cols > 1 && count.div_ceil(cols - 1) == rows { continue }
+    let shape = (aspect / cols as f32) / (1.0 / rows as f32);
+    let score = (shape / TILE_SHAPE).ln().abs();
+    if score < best_score { best = (cols, rows); best_score = score; }
+  }
+  best
+}
+
+fn modified(path: &Path) -> Option<f64> {
+  let time = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+  Some(time.duration_since(UNIX_EPOCH).ok()?.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{config::Config, util::temp_dir},
+  };
+
+  fn grid(dir: &Path) -> Grid {
+    let config = Config::parse(&format!("[grid]\ndir = {}\ninterval = 10\nstale = 60\nlines = 3\n", dir.display()));
+    Grid::from_section(config.section("grid").unwrap())
+  }
+
+  fn snapshot(host: &str, alerts: &[(&str, &str)]) -> Snapshot {
+    let mut snapshot = Snapshot { host: host.to_owned(), ..Snapshot::new() };
+    snapshot.metrics.insert("cpu.usage".to_owned(), 12.5);
+    snapshot.alerts = alerts.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    snapshot
+  }
+
+  #[test]
+  fn shapes() {
+    // Nothing to show still gets one tile for the message saying so
+    assert_eq!(grid_shape(0, 2.0), (1, 1));
+    assert_eq!(grid_shape(1, 2.0), (1, 1));
+    assert_eq!(grid_shape(3, 2.0), (2, 2));
+    assert_eq!(grid_shape(4, 3.0), (2, 2));
+    assert_eq!(grid_shape(4, 8.0), (4, 1));
+    assert_eq!(grid_shape(6, 3.0), (3, 2));
+    // A tall window stacks them
+    assert_eq!(grid_shape(3, 0.5), (1, 3));
+  }
+
+  #[test]
+  fn staleness_beats_alert_colours() {
+    let grid = grid(Path::new("/nonexistent"));
+    let tile = |age, alerts: &[(&str, &str)]| Tile { host: "db1".to_owned(), snapshot: Ok(snapshot("db1", alerts)), age };
+    let rows = grid.tile_rows(&tile(5.0, &[]));
+    assert_eq!((rows[0].0.as_str(), rows[0].1), ("db1", COLOUR_NORMAL));
+    let rows = grid.tile_rows(&tile(5.0, &[("disk_full", "CRIT"), ("load_high", "WARN")]));
+    assert_eq!((rows[0].0.as_str(), rows[0].1), ("db1  [CRIT 2]", COLOUR_ERROR));
+    assert!(rows.len() > 1 && rows.len() <= 3);
+    assert!(rows[1..].iter().all(|r| r.1 == COLOUR_NORMAL));
+    let rows = grid.tile_rows(&tile(120.0, &[("disk_full", "CRIT")]));
+    assert_eq!((rows[0].0.as_str(), rows[0].1), ("db1  [CRIT 1]  stale 2m00s", COLOUR_WARN));
+    assert!(rows[1..].iter().all(|r| r.1 == COLOUR_WARN));
+    let broken = Tile { host: "db2".to_owned(), snapshot: Err("bad JSON".to_owned()), age: 0.0 };
+    assert_eq!(grid.tile_rows(&broken), vec![("db2: bad JSON".to_owned(), COLOUR_ERROR)]);
+  }
+
+  #[test]
+  fn refresh_reads_only_finished_snapshots() {
+    let dir = temp_dir("grid-refresh");
+    let mut grid = grid(&dir);
+    grid.publish(&snapshot("web1", &[]), 0.0);
+    // Too soon for another
+    grid.publish(&snapshot("web1", &[("load_high", "WARN")]), 5.0);
+    fs::write(dir.join(".web2.json.tmp"), "{\"half").unwrap();
+    fs::write(dir.join("web3.json.tmp"), "{\"half").unwrap();
+    fs::write(dir.join(".hidden.json"), "{}").unwrap();
+    fs::write(dir.join("notes.txt"), "not a snapshot").unwrap();
+    fs::write(dir.join("broken.json"), "{").unwrap();
+    grid.refresh(modified(&dir.join("web1.json")).unwrap() + 1.0);
+    let tiles: Vec<(&str, bool)> = grid.tiles.iter().map(|t| (t.host.as_str(), t.snapshot.is_ok())).collect();
+    assert_eq!(tiles, vec![("broken", false), ("web1", true)]);
+    let web1 = &grid.tiles[1];
+    assert!(web1.snapshot.as_ref().unwrap().alerts.is_empty());
+    assert!(web1.age >= 0.0 && web1.age < 5.0, "{}", web1.age);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
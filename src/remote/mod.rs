@@ -0,0 +1,160 @@
+pub mod agent;
+pub mod grid;
+pub mod viewer;
+
+pub use {
+  crate::{
+    remote::{
+      agent::Agent,
+      grid::Grid,
+      viewer::RemoteWidget,
+    },
+  },
+};
+
+use {
+  std::time::Duration,
+  serde_json::{json, Value},
+  crate::{
+    snapshot::{fmt_metric, Snapshot},
+    text::LINE_HEIGHT,
+    widget::Placement,
+  },
+};
+
+// Agents and viewers talk over plain TCP, one JSON object per line. On
+// connecting the agent says who it is:
+//
+//   {"type":"hello","proto":"raumen-sysinfo","version":1,"host":"rack1"}
+//
+// and after that sends a snapshot every sample tick:
+//
+//   {"type":"snapshot","snapshot":{"host":"rack1","time":...,"metrics":{...}}}
+//
+// A change that older viewers can't cope with bumps the version. Anything
+// else, like a new message type, is added without one and ignored by viewers
+// that don't know it.
+
+pub const PROTO: &str = "raumen-sysinfo";
+pub const VERSION: u64 = 1;
+pub const DEFAULT_PORT: u16 = 9102;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+  Hello { version: u64, host: String },
+  Snapshot(Snapshot),
+  /// A message type from a newer agent
+  Unknown(String),
+}
+impl Message {
+  pub fn hello(host: &str) -> Self {
+    Message::Hello { version: VERSION, host: host.to_owned() }
+  }
+  /// The message as one line, newline included
+  pub fn encode(&self) -> String {
+    let val = match self {
+      Message::Hello { version, host } => json!({
+        "type": "hello",
+        "proto": PROTO,
+        "version": version,
+        "host": host,
+      }),
+      Message::Snapshot(snapshot) => json!({ "type": "snapshot", "snapshot": snapshot.to_json() }),
+      Message::Unknown(kind) => json!({ "type": kind }),
+    };
+    format!("{}\n", val)
+  }
+  pub fn decode(line: &str) -> Result<Self, String> {
+    let val: Value = serde_json::from_str(line).map_err(|e| format!("bad message: {}", e))?;
+    let kind = val.get("type").and_then(|t| t.as_str()).ok_or("message without a type")?;
+    match kind {
+      "hello" => {
+        if val.get("proto").and_then(|p| p.as_str()) != Some(PROTO) { return Err("not a raumEnSysInfo agent".to_owned()) }
+        Ok(Message::Hello {
+          version: val.get("version").and_then(|v| v.as_u64()).ok_or("hello without a version")?,
+          host: val.get("host").and_then(|h| h.as_str()).unwrap_or("").to_owned(),
+        })
+      }
+      "snapshot" => Ok(Message::Snapshot(Snapshot::from_json(val.get("snapshot").unwrap_or(&Value::Null))?)),
+      other => Ok(Message::Unknown(other.to_owned())),
+    }
+  }
+}
+
+/// Adds the default port to a bare host name
+pub fn with_port(addr: &str) -> String {
+  let addr = addr.trim();
+  // A colon after the last bracket means a port was given
+  let has_port = match addr.rfind(']') {
+    Some(idx) => addr[idx..].contains(':'),
+    None => addr.contains(':'),
+  };
+  if has_port { addr.to_owned() } else { format!("{}:{}", addr, DEFAULT_PORT) }
+}
+
+/// Panels for the agents given to `--view`, three to a row under the title
+pub fn view_widgets(addrs: &[String]) -> Vec<RemoteWidget> {
+  let lines = 6;
+  let height = lines as f32 * LINE_HEIGHT + 0.02;
+  addrs.iter().enumerate().map(|(idx, addr)| {
+    let placement = Placement {
+      font: "sans".to_owned(),
+      font_size: 1.0,
+      x: 0.02 + (idx % 3) as f32 * 0.33,
+      y: 0.15 + (idx / 3) as f32 * height,
+      line_max_size: 0.31,
+    };
+    let mut widget = RemoteWidget::new(&with_port(addr), addr, placement, Duration::from_secs(5));
+    widget.max_lines = lines;
+    widget
+  }).collect()
+}
+
+/// The lines of a host's panel: CPU and load, memory, network, then a line
+/// per disk for as many as fit in `max` lines. Hosts are told apart by the
+/// caller, so the host name isn't included.
+pub fn panel_lines(snapshot: &Snapshot, max: usize) -> Vec<String> {
+  let get = |name: &str| snapshot.metrics.get(name).cloned();
+  let size = |name: &str, value: f64| fmt_metric(name, value);
+  let mut out = Vec::new();
+  let mut cpu = match get("cpu.usage") {
+    Some(usage) => format!("CPU {:.1}%", usage),
+    None => "CPU ?".to_owned(),
+  };
+  if let (Some(l1), Some(l5), Some(l15)) = (get("load.1"), get("load.5"), get("load.15")) {
+    cpu.push_str(&format!("  load {:.2} {:.2} {:.2}", l1, l5, l15));
+  }
+  out.push(cpu);
+  if let (Some(used), Some(total)) = (get("mem.used"), get("mem.total")) {
+    let mut mem = format!("RAM {} of {}", size("mem.used", used), size("mem.total", total));
+    match (get("swap.used"), get("swap.total")) {
+      (Some(used), Some(total)) if total > 0.0 => {
+        mem.push_str(&format!("  swap {} of {}", size("swap.used", used), size("swap.total", total)));
+      }
+      _ => (),
+    }
+    out.push(mem);
+  }
+  // Rates only exist if the agent has counters set up, which it does by
+  // default. Loopback traffic never leaves the host, so it's left out.
+  let sum = |suffix: &str| -> Option<f64> {
+    let vals: Vec<f64> = snapshot.metrics.iter()
+      .filter(|(k, _)| k.starts_with("net.") && !k.starts_with("net.lo.") && k.ends_with(suffix))
+      .map(|(_, v)| *v)
+      .collect();
+    if vals.is_empty() { None } else { Some(vals.iter().sum()) }
+  };
+  if let (Some(rx), Some(tx)) = (sum(".rx.rate"), sum(".tx.rate")) {
+    out.push(format!("Net in {} out {}", size("net.rx.rate", rx), size("net.tx.rate", tx)));
+  }
+  for (name, total) in &snapshot.metrics {
+    let mount = match name.strip_prefix("disk.").and_then(|n| n.strip_suffix(".total")) {
+      Some(mount) => mount,
+      None => continue,
+    };
+    let avail = get(&format!("disk.{}.avail", mount)).unwrap_or(0.0);
+    out.push(format!("{} {} free of {}", mount, size(name, avail), size(name, *total)));
+  }
+  out.truncate(max);
+  out
+}
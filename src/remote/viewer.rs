@@ -0,0 +1,187 @@
+
+use {
+  std::{
+    io::{self, BufRead, BufReader},
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+  },
+  crate::{
+    config::Section,
+    redact,
+    remote::{panel_lines, with_port, Message, VERSION},
+    snapshot::Snapshot,
+    widget::{Placement, TextRow, Widget, WidgetUpdate, COLOUR_ERROR, COLOUR_NORMAL, COLOUR_WARN},
+  },
+};
+
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Event {
+  Snapshot(Snapshot),
+  Down(String),
+}
+
+/// One agent's panel: a title row with the host, then CPU, memory, network
+/// and disks. Reconnects on its own if the agent goes away.
+///
+///   [remote rack1]
+///   host = 10.0.0.5:9102
+///   lines = 6
+///   stale = 5
+///   retry = 5
+///   x = 0.02
+///   y = 0.5
+///
+/// A panel whose last snapshot is more than `stale` seconds old goes orange,
+/// one that can't reach its agent goes red.
+pub struct RemoteWidget {
+  pub label: String,
+  pub addr: String,
+  pub max_lines: usize,
+  pub stale: Duration,
+  pub placement: Placement,
+  latest: Option<(Instant, Snapshot)>,
+  down: Option<String>,
+  /// Seconds without a snapshot as last shown, while stale
+  shown_stale: Option<u64>,
+  /// Something changed that poll hasn't shown yet
+  dirty: bool,
+  events: Receiver<Event>,
+}
+impl RemoteWidget {
+  pub fn new(label: &str, addr: &str, placement: Placement, retry: Duration) -> Self {
+    let (tx, rx) = channel();
+    let (addr, down) = if addr.trim().is_empty() {
+      (String::new(), Some("no host".to_owned()))
+    } else {
+      let addr = with_port(addr);
+      let target = addr.clone();
+      thread::spawn(move || follow(&target, retry, tx));
+      (addr, None)
+    };
+    Self {
+      label: label.to_owned(),
+      addr,
+      max_lines: 6,
+      stale: Duration::from_secs(5),
+      placement,
+      latest: None,
+      dirty: down.is_some(),
+      down,
+      shown_stale: None,
+      events: rx,
+    }
+  }
+  pub fn from_section(section: &Section) -> Self {
+    let host = section.get_str("host", "");
+    if host.is_empty() { println!("Config: [remote {}] no host", section.name); }
+    let retry = Duration::from_secs_f32(section.get_or("retry", 5.0_f32).max(0.5));
+    let mut out = Self::new(&section.name, &host, Placement::from_section(section), retry);
+    out.max_lines = section.get_or("lines", 6).max(1);
+    out.stale = Duration::from_secs_f32(section.get_or("stale", 5.0_f32).max(1.0));
+    out
+  }
+  /// Seconds since the last snapshot, once that's over `stale`
+  fn stale_secs(&self) -> Option<u64> {
+    match self.latest {
+      Some((at, _)) if at.elapsed() > self.stale => Some(at.elapsed().as_secs()),
+      _ => None,
+    }
+  }
+  fn update(&self) -> WidgetUpdate {
+    let row = |text: String, colour| TextRow { text, colour };
+    let snapshot = match self.latest {
+      Some((_, ref snapshot)) => snapshot,
+      None => {
+        let why = self.down.clone().unwrap_or_else(|| "connecting".to_owned());
+        return WidgetUpdate::single(format!("{} ({}): {}", self.label, self.addr, why), COLOUR_ERROR)
+      }
+    };
+    let (title, colour) = match (&self.down, self.stale_secs()) {
+      (Some(e), _) => (format!("{}: {}", snapshot.host, e), COLOUR_ERROR),
+      (None, Some(secs)) => (format!("{}: nothing new for {}s", snapshot.host, secs), COLOUR_WARN),
+      (None, None) => (snapshot.host.clone(), COLOUR_NORMAL),
+    };
+    // Old numbers stay up under the warning, they're still better than nothing
+    let body = if colour == COLOUR_NORMAL { COLOUR_NORMAL } else { COLOUR_WARN };
+    let mut rows = vec![row(title, colour)];
+    for line in panel_lines(snapshot, self.max_lines - 1) { rows.push(row(line, body)) }
+    WidgetUpdate { rows }
+  }
+}
+impl Widget for RemoteWidget {
+  fn label(&self) -> &str { &self.label }
+  fn placement(&self) -> &Placement { &self.placement }
+  fn rows(&self) -> usize { self.max_lines }
+  fn poll(&mut self) -> Option<WidgetUpdate> {
+    let mut changed = std::mem::take(&mut self.dirty);
+    loop {
+      match self.events.try_recv() {
+        Ok(Event::Snapshot(snapshot)) => {
+          self.latest = Some((Instant::now(), snapshot));
+          self.down = None;
+          changed = true;
+        }
+        Ok(Event::Down(e)) => { self.down = Some(e); changed = true; }
+        Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+      }
+    }
+    // Going stale isn't an event, so watch for it, and count up the seconds
+    let stale = self.stale_secs();
+    if stale != self.shown_stale { self.shown_stale = stale; changed = true; }
+    if !changed { return None }
+    Some(self.update())
+  }
+}
+
+/// Stays connected to an agent for as long as the widget is around
+fn follow(addr: &str, retry: Duration, tx: Sender<Event>) {
+  loop {
+    let e = match stream(addr, &tx) {
+      Ok(()) => return,
+      Err(e) => e,
+    };
+    if tx.send(Event::Down(e)).is_err() { return }
+    thread::sleep(retry);
+  }
+}
+
+/// Reads snapshots until the connection fails, which is the only way out
+/// other than the widget being dropped.
+fn stream(addr: &str, tx: &Sender<Event>) -> Result<(), String> {
+  let stream = connect(addr).map_err(|e| e.to_string())?;
+  // An agent sends every second, so a long silence means it's gone
+  stream.set_read_timeout(Some(Duration::from_secs(15))).map_err(|e| e.to_string())?;
+  let mut lines = BufReader::new(stream).lines();
+  let mut next = || match lines.next() {
+    Some(Ok(line)) => Message::decode(&line),
+    Some(Err(e)) => Err(e.to_string()),
+    None => Err("agent closed the connection".to_owned()),
+  };
+  match next()? {
+    Message::Hello { version, ref host } if version == VERSION => redact::add_host(host),
+    Message::Hello { version, .. } => {
+      return Err(format!("agent speaks version {}, this viewer speaks {}", version, VERSION))
+    }
+    _ => return Err("agent didn't say hello".to_owned()),
+  }
+  loop {
+    match next()? {
+      Message::Snapshot(snapshot) => if tx.send(Event::Snapshot(snapshot)).is_err() { return Ok(()) },
+      Message::Hello { .. } | Message::Unknown(_) => (),
+    }
+  }
+}
+
+fn connect(addr: &str) -> io::Result<TcpStream> {
+  let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("{} didn't resolve", addr));
+  for sock in addr.to_socket_addrs()? {
+    match TcpStream::connect_timeout(&sock, CONNECT_TIMEOUT) {
+      Ok(stream) => return Ok(stream),
+      Err(e) => last_err = e,
+    }
+  }
+  Err(last_err)
+}
@@ -0,0 +1,377 @@
+
+use {
+  std::{
+    fs,
+    path::Path,
+    thread,
+    time::Duration,
+  },
+  sysinfo::{ProcessorExt, SystemExt},
+  systemstat::{self, Platform},
+  crate::{
+    alert::fmt_secs,
+    config::Config,
+    redact,
+    metrics::collect::skip_mount,
+    snapshot::{fmt_metric, fmt_time, Snapshot},
+    stats::{proctree::fmt_kb, ProcTree},
+  },
+};
+
+/// How many of the busiest processes get listed
+pub const TOP_PROCS: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+  Markdown,
+  Html,
+}
+impl ReportFormat {
+  /// Going by the file's extension
+  pub fn from_path(path: &Path) -> Option<Self> {
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+      "md" | "markdown" => Some(ReportFormat::Markdown),
+      "html" | "htm" => Some(ReportFormat::Html),
+      _ => None,
+    }
+  }
+}
+
+pub enum Body {
+  /// `key: value` lines
+  Pairs(Vec<(String, String)>),
+  Table { head: Vec<&'static str>, rows: Vec<Vec<String>> },
+}
+
+pub struct Part {
+  pub title: &'static str,
+  pub body: Body,
+}
+
+/// Everything worth knowing about a machine for a support ticket, gathered
+/// by running every collector once.
+pub struct Report {
+  pub host: String,
+  pub time: f64,
+  pub parts: Vec<Part>,
+}
+impl Report {
+  pub fn gather(config: &Config) -> Self {
+    // Process CPU usage is worked out between two reads, and the snapshot
+    // needs a moment for the same reason
+    let mut procs = ProcTree::new();
+    procs.refresh();
+    let snapshot = Snapshot::take(config);
+    thread::sleep(Duration::from_millis(250));
+    procs.refresh();
+    let mut sys = sysinfo::System::new();
+    sys.refresh_cpu();
+    let mounts = systemstat::System::new().mounts().unwrap_or_default();
+    let networks = systemstat::System::new().networks().unwrap_or_default();
+    let parts = vec![
+      Part { title: "System", body: Body::Pairs(system(&snapshot)) },
+      Part { title: "Hardware", body: Body::Pairs(hardware()) },
+      Part { title: "CPU", body: Body::Pairs(cpu(&snapshot, &sys)) },
+      Part { title: "Memory", body: Body::Pairs(memory(&snapshot)) },
+      Part { title: "Disks", body: disks() },
+      Part { title: "Filesystems", body: filesystems(&mounts) },
+      Part { title: "Network", body: network(&snapshot, &networks) },
+      Part { title: "Top processes", body: processes(&procs) },
+    ];
+    Self { host: snapshot.host, time: snapshot.time, parts }
+  }
+  /// Runs every value through `mask`, before any escaping gets in the way
+  /// of the patterns matching
+  pub fn redact(&mut self, mask: fn(&str) -> String) {
+    self.host = mask(&self.host);
+    for part in &mut self.parts {
+      match part.body {
+        Body::Pairs(ref mut pairs) => for pair in pairs.iter_mut() { pair.1 = mask(&pair.1) },
+        Body::Table { ref mut rows, .. } => for cell in rows.iter_mut().flat_map(|r| r.iter_mut()) { *cell = mask(cell) },
+      }
+    }
+  }
+  pub fn render(&self, format: ReportFormat) -> String {
+    match format {
+      ReportFormat::Markdown => self.markdown(),
+      ReportFormat::Html => self.html(),
+    }
+  }
+  pub fn markdown(&self) -> String {
+    let mut out = format!("# System report: {}\n\nGenerated {}\n", md(&self.host), fmt_time(self.time));
+    for part in &self.parts {
+      out.push_str(&format!("\n## {}\n\n", part.title));
+      match part.body {
+        Body::Pairs(ref pairs) => {
+          for (key, val) in pairs { out.push_str(&format!("- **{}:** {}\n", key, md(val))); }
+        }
+        Body::Table { ref head, ref rows } => {
+          if rows.is_empty() { out.push_str("None found.\n"); continue }
+          out.push_str(&format!("| {} |\n", head.join(" | ")));
+          out.push_str(&format!("|{}\n", "---|".repeat(head.len())));
+          for row in rows {
+            let cells: Vec<String> = row.iter().map(|c| md(c)).collect();
+            out.push_str(&format!("| {} |\n", cells.join(" | ")));
+          }
+        }
+      }
+    }
+    out
+  }
+  pub fn html(&self) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>System report: {}</title>\n", html(&self.host)));
+    out.push_str("<style>\nbody { font-family: sans-serif; margin: 2em; }\n\
+                  table { border-collapse: collapse; }\n\
+                  th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }\n\
+                  th { background: #eee; }\n</style>\n</head>\n<body>\n");
+    out.push_str(&format!("<h1>System report: {}</h1>\n<p>Generated {}</p>\n", html(&self.host), fmt_time(self.time)));
+    for part in &self.parts {
+      out.push_str(&format!("<h2>{}</h2>\n", part.title));
+      match part.body {
+        Body::Pairs(ref pairs) => {
+          out.push_str("<table>\n");
+          for (key, val) in pairs { out.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", key, html(val))); }
+          out.push_str("</table>\n");
+        }
+        Body::Table { ref head, ref rows } => {
+          if rows.is_empty() { out.push_str("<p>None found.</p>\n"); continue }
+          out.push_str("<table>\n<tr>");
+          for h in head { out.push_str(&format!("<th>{}</th>", h)); }
+          out.push_str("</tr>\n");
+          for row in rows {
+            out.push_str("<tr>");
+            for cell in row { out.push_str(&format!("<td>{}</td>", html(cell))); }
+            out.push_str("</tr>\n");
+          }
+          out.push_str("</table>\n");
+        }
+      }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+  }
+}
+
+/// Gathers a report and writes it to `path`, in the format its extension says
+pub fn write(path: &Path, config: &Config) -> Result<(), String> {
+  let format = ReportFormat::from_path(path)
+    .ok_or_else(|| format!("can't tell what format {} should be, name it .md or .html", path.display()))?;
+  // The hardware, disks and processes would still be this machine's
+  if config.section("demo").is_some() { return Err("can't write a report of demo metrics".to_owned()) }
+  let mut report = Report::gather(config);
+  report.redact(redact::redact);
+  fs::write(path, report.render(format)).map_err(|e| format!("can't write {}: {}", path.display(), e))
+}
+
+fn system(snapshot: &Snapshot) -> Vec<(String, String)> {
+  let os = read_os_release().unwrap_or_else(|| "unknown".to_owned());
+  let mut out = vec![
+    ("Host".to_owned(), snapshot.host.clone()),
+    ("OS".to_owned(), os),
+    ("Kernel".to_owned(), snapshot.kernel.clone()),
+  ];
+  if let Some(uptime) = snapshot.metrics.get("uptime") { out.push(("Uptime".to_owned(), fmt_secs(*uptime))); }
+  out
+}
+
+/// The machine itself, from the DMI tables the firmware fills in
+fn hardware() -> Vec<(String, String)> {
+  let fields = [
+    ("Vendor", "sys_vendor"),
+    ("Product", "product_name"),
+    ("Version", "product_version"),
+    ("Board", "board_name"),
+    ("Board vendor", "board_vendor"),
+    ("BIOS", "bios_version"),
+    ("BIOS date", "bios_date"),
+  ];
+  let out: Vec<(String, String)> = fields.iter().filter_map(|(key, file)| {
+    let val = read_trimmed(&format!("/sys/class/dmi/id/{}", file))?;
+    if val.is_empty() { None } else { Some((key.to_string(), val)) }
+  }).collect();
+  if out.is_empty() { vec![("DMI".to_owned(), "not available".to_owned())] } else { out }
+}
+
+fn cpu(snapshot: &Snapshot, sys: &sysinfo::System) -> Vec<(String, String)> {
+  let processors = sys.get_processors();
+  let mut out = vec![
+    ("Model".to_owned(), snapshot.cpu.clone()),
+    ("Logical CPUs".to_owned(), processors.len().to_string()),
+  ];
+  if let Some(p) = processors.first() {
+    if !p.get_vendor_id().is_empty() { out.push(("Vendor".to_owned(), p.get_vendor_id().to_owned())); }
+    if p.get_frequency() > 0 { out.push(("Frequency".to_owned(), format!("{} MHz", p.get_frequency()))); }
+  }
+  if !snapshot.microcode.is_empty() { out.push(("Microcode".to_owned(), snapshot.microcode.clone())); }
+  if let Some(usage) = snapshot.metrics.get("cpu.usage") { out.push(("Usage".to_owned(), format!("{:.1}%", usage))); }
+  let load: Vec<String> = ["load.1", "load.5", "load.15"].iter()
+    .filter_map(|m| snapshot.metrics.get(*m).map(|v| format!("{:.2}", v)))
+    .collect();
+  if !load.is_empty() { out.push(("Load average".to_owned(), load.join(" "))); }
+  out
+}
+
+fn memory(snapshot: &Snapshot) -> Vec<(String, String)> {
+  let mut out = Vec::new();
+  for (key, total, used) in &[("RAM", "mem.total", "mem.used"), ("Swap", "swap.total", "swap.used")] {
+    if let (Some(t), Some(u)) = (snapshot.metrics.get(*total), snapshot.metrics.get(*used)) {
+      let pct = if *t > 0.0 { u / t * 100.0 } else { 0.0 };
+      out.push((key.to_string(), format!("{} used of {} ({:.0}%)", fmt_metric(used, *u), fmt_metric(total, *t), pct)));
+    }
+  }
+  out
+}
+
+/// Block devices, leaving out loop, ram and other virtual ones
+fn disks() -> Body {
+  let mut rows = Vec::new();
+  let mut names: Vec<String> = fs::read_dir("/sys/block").map(|entries| {
+    entries.flatten().filter_map(|e| e.file_name().into_string().ok()).collect()
+  }).unwrap_or_default();
+  names.sort();
+  for name in names {
+    if ["loop", "ram", "zram", "dm-", "md", "sr"].iter().any(|p| name.starts_with(p)) { continue }
+    let dir = format!("/sys/block/{}", name);
+    // The size is always in 512 byte sectors, whatever the device uses
+    let sectors: f64 = read_trimmed(&format!("{}/size", dir)).and_then(|s| s.parse().ok()).unwrap_or(0.0);
+    if sectors == 0.0 { continue }
+    let model = read_trimmed(&format!("{}/device/model", dir)).unwrap_or_default();
+    let kind = match read_trimmed(&format!("{}/queue/rotational", dir)).as_deref() {
+      Some("1") => "HDD",
+      Some("0") => "SSD",
+      _ => "",
+    };
+    rows.push(vec![name, model, kind.to_owned(), fmt_metric("disk.", sectors * 512.0)]);
+  }
+  Body::Table { head: vec!["Device", "Model", "Type", "Size"], rows }
+}
+
+fn filesystems(mounts: &[systemstat::Filesystem]) -> Body {
+  let mut rows = Vec::new();
+  for m in mounts {
+    let total = m.total.as_u64() as f64;
+    if total == 0.0 || skip_mount(&m.fs_mounted_on) { continue }
+    let used = total - m.free.as_u64() as f64;
+    rows.push(vec![
+      m.fs_mounted_on.clone(),
+      m.fs_mounted_from.clone(),
+      m.fs_type.clone(),
+      fmt_metric("disk.", total),
+      fmt_metric("disk.", used),
+      fmt_metric("disk.", m.avail.as_u64() as f64),
+      format!("{:.0}%", used / total * 100.0),
+    ]);
+  }
+  Body::Table { head: vec!["Mount", "Device", "Type", "Size", "Used", "Free", "Use"], rows }
+}
+
+fn network(snapshot: &Snapshot, networks: &std::collections::BTreeMap<String, systemstat::Network>) -> Body {
+  let mut rows = Vec::new();
+  for (name, net) in networks {
+    let addrs: Vec<String> = net.addrs.iter().filter_map(|a| match a.addr {
+      systemstat::IpAddr::V4(ip) => Some(ip.to_string()),
+      systemstat::IpAddr::V6(ip) => Some(ip.to_string()),
+      _ => None,
+    }).collect();
+    let sys = format!("/sys/class/net/{}", name);
+    let total = |dir: &str| snapshot.metrics.get(&format!("net.{}.{}", name, dir)).map(|v| fmt_metric("net.", *v)).unwrap_or_default();
+    rows.push(vec![
+      name.clone(),
+      read_trimmed(&format!("{}/operstate", sys)).unwrap_or_default(),
+      read_trimmed(&format!("{}/address", sys)).unwrap_or_default(),
+      // Virtual interfaces have no speed and fail the read
+      read_trimmed(&format!("{}/speed", sys)).filter(|s| !s.starts_with('-')).map(|s| format!("{} Mb/s", s)).unwrap_or_default(),
+      addrs.join(", "),
+      total("rx"),
+      total("tx"),
+    ]);
+  }
+  Body::Table { head: vec!["Interface", "State", "MAC", "Speed", "Addresses", "Received", "Sent"], rows }
+}
+
+fn processes(procs: &ProcTree) -> Body {
+  let mut list: Vec<_> = procs.procs.values().collect();
+  list.sort_by(|a, b| {
+    b.cpu_pct.partial_cmp(&a.cpu_pct).unwrap_or(std::cmp::Ordering::Equal).then(b.rss_kb.cmp(&a.rss_kb))
+  });
+  let rows = list.iter().take(TOP_PROCS).map(|p| vec![
+    p.pid.to_string(),
+    procs.user_name(p.uid),
+    p.name.clone(),
+    format!("{:.1}%", p.cpu_pct),
+    fmt_kb(p.rss_kb),
+  ]).collect();
+  Body::Table { head: vec!["PID", "User", "Name", "CPU", "RSS"], rows }
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+  fs::read_to_string(path).ok().map(|s| s.trim().to_owned())
+}
+
+fn read_os_release() -> Option<String> {
+  let text = fs::read_to_string("/etc/os-release").ok()?;
+  let line = text.lines().find(|l| l.starts_with("PRETTY_NAME="))?;
+  Some(line["PRETTY_NAME=".len()..].trim_matches('"').to_owned())
+}
+
+// Table cells can't hold a bare `|`, and `*`, `_` and friends would turn
+// into formatting
+fn md(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    if "\\`*_[]<>|#".contains(c) { out.push('\\') }
+    out.push(c);
+  }
+  out
+}
+
+fn html(s: &str) -> String {
+  s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn mask(s: &str) -> String {
+    s.replace("my_host", "[host]").replace("first_last", "[user]")
+  }
+
+  fn report() -> Report {
+    Report {
+      host: "my_host".to_owned(),
+      time: 0.0,
+      parts: vec![
+        Part { title: "System", body: Body::Pairs(vec![("Host".to_owned(), "my_host".to_owned())]) },
+        Part { title: "Top processes", body: Body::Table {
+          head: vec!["User", "Name"],
+          rows: vec![vec!["first_last".to_owned(), "a && b *[x]* <y>".to_owned()]],
+        }},
+      ],
+    }
+  }
+
+  #[test]
+  fn redacts_before_escaping() {
+    let mut report = report();
+    report.redact(mask);
+    let text = report.markdown();
+    assert!(!text.contains("my") && !text.contains("first"));
+    assert!(text.starts_with("# System report: \\[host\\]\n"));
+    assert!(text.contains("- **Host:** \\[host\\]\n"));
+    assert!(text.contains("| \\[user\\] | a && b \\*\\[x\\]\\* \\<y\\> |\n"));
+    let text = report.html();
+    assert!(!text.contains("my") && !text.contains("first"));
+    assert!(text.contains("<td>[host]</td>"));
+    assert!(text.contains("<td>[user]</td><td>a &amp;&amp; b *[x]* &lt;y&gt;</td>"));
+  }
+
+  #[test]
+  fn refuses_demo_metrics() {
+    let config = Config::parse("[demo]\n");
+    let err = write(Path::new("/nonexistent/report.md"), &config).err().unwrap();
+    assert!(err.contains("demo"));
+  }
+}
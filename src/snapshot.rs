@@ -0,0 +1,231 @@
+
+use {
+  std::{
+    collections::BTreeMap,
+    fs,
+    thread,
+    time::Duration,
+  },
+  serde_json::{json, Value},
+  time::OffsetDateTime,
+  crate::{
+    config::Config,
+    metrics::{now_secs, Collector, Derived, Sample, Store},
+  },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  Json,
+  Yaml,
+  Text,
+}
+impl Format {
+  pub fn parse(s: &str) -> Option<Self> {
+    match s {
+      "json" => Some(Format::Json),
+      "yaml" | "yml" => Some(Format::Yaml),
+      "text" | "txt" => Some(Format::Text),
+      _ => None,
+    }
+  }
+}
+
+/// Every metric at one moment, along with what machine it came from. This
+/// is what `--once` prints and what gets passed between hosts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+  pub host: String,
+  pub time: f64,
+  pub cpu: String,
+  /// Kernel release, like `6.1.0-18-amd64`
+  pub kernel: String,
+  /// CPU microcode revision, empty where the kernel doesn't say
+  pub microcode: String,
+  pub metrics: BTreeMap<String, f64>,
+  /// Alerts firing when it was taken, by name, with their severity
+  pub alerts: BTreeMap<String, String>,
+}
+impl Snapshot {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// Runs the collectors once, plus any derived metrics. Rates and smoothing
+  /// need more than one sample, so they're left out.
+  pub fn take(config: &Config) -> Self {
+    let mut collector = Collector::from_config(config);
+    // CPU usage is worked out between two refreshes, which need a little
+    // time between them to mean anything
+    thread::sleep(Duration::from_millis(250));
+    let samples = collector.collect();
+    let now = now_secs();
+    let mut store = Store::new();
+    store.record(now, &samples);
+    let mut derived = Derived::from_section(config.section("derived"));
+    let derived = derived.apply(&mut store, now);
+    Self::from_samples(&collector, now, samples.iter().chain(derived.iter()))
+  }
+  /// The machine the collector's samples come from, with no metrics yet.
+  /// That's the recorded or demo one rather than this one if it isn't live.
+  pub fn describe(collector: &Collector) -> Self {
+    Self {
+      host: collector.host(),
+      time: 0.0,
+      cpu: collector.cpu_brand().unwrap_or_default(),
+      kernel: collector.kernel(),
+      microcode: collector.microcode(),
+      metrics: BTreeMap::new(),
+      alerts: BTreeMap::new(),
+    }
+  }
+  /// The samples from one tick of `collector`
+  pub fn from_samples<'a>(collector: &Collector, time: f64, samples: impl Iterator<Item = &'a Sample>) -> Self {
+    Self {
+      time,
+      metrics: samples.map(|s| (s.name.clone(), s.value)).collect(),
+      ..Self::describe(collector)
+    }
+  }
+  pub fn to_json(&self) -> Value {
+    let mut out = json!({
+      "host": self.host,
+      "time": self.time,
+      "cpu": self.cpu,
+      "kernel": self.kernel,
+      "microcode": self.microcode,
+      "metrics": self.metrics,
+    });
+    // Only hosts that evaluate alerts have any to report
+    if !self.alerts.is_empty() { out["alerts"] = json!(self.alerts); }
+    out
+  }
+  pub fn from_json(val: &Value) -> Result<Self, String> {
+    let metrics = val.get("metrics").and_then(|m| m.as_object()).ok_or("no metrics")?;
+    Ok(Self {
+      host: val.get("host").and_then(|h| h.as_str()).unwrap_or("").to_owned(),
+      time: val.get("time").and_then(|t| t.as_f64()).unwrap_or(0.0),
+      cpu: val.get("cpu").and_then(|c| c.as_str()).unwrap_or("").to_owned(),
+      // Older snapshots don't have these
+      kernel: val.get("kernel").and_then(|k| k.as_str()).unwrap_or("").to_owned(),
+      microcode: val.get("microcode").and_then(|m| m.as_str()).unwrap_or("").to_owned(),
+      // Values that don't fit in JSON, like NaN, come through as null
+      metrics: metrics.iter().filter_map(|(k, v)| v.as_f64().map(|v| (k.clone(), v))).collect(),
+      alerts: val.get("alerts").and_then(|a| a.as_object()).map(|alerts| {
+        alerts.iter().map(|(k, v)| (k.clone(), v.as_str().unwrap_or("").to_owned())).collect()
+      }).unwrap_or_default(),
+    })
+  }
+  pub fn load(path: &str) -> Result<Self, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let val: Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    Self::from_json(&val).map_err(|e| format!("{}: {}", path, e))
+  }
+  pub fn render(&self, format: Format) -> String {
+    match format {
+      Format::Json => serde_json::to_string_pretty(&self.to_json()).unwrap_or_default(),
+      Format::Yaml => serde_yaml::to_string(&self.to_json()).unwrap_or_default(),
+      Format::Text => self.text(),
+    }
+  }
+  pub fn text(&self) -> String {
+    let mut out = vec![
+      format!("Host: {}", self.host),
+      format!("Time: {}", fmt_time(self.time)),
+      format!("CPU:  {}", self.cpu),
+      format!("Kernel: {}", self.kernel),
+    ];
+    if !self.microcode.is_empty() { out.push(format!("Microcode: {}", self.microcode)); }
+    if !self.alerts.is_empty() {
+      let alerts: Vec<String> = self.alerts.iter().map(|(name, sev)| format!("{} ({})", name, sev)).collect();
+      out.push(format!("Alerts: {}", alerts.join(", ")));
+    }
+    out.push(String::new());
+    let width = self.metrics.keys().map(|k| k.chars().count()).max().unwrap_or(0);
+    for (name, value) in &self.metrics {
+      out.push(format!("{:width$}  {}", name, fmt_metric(name, *value), width = width));
+    }
+    out.join("\n")
+  }
+}
+
+/// Whether a metric is a size in bytes, going by its name
+pub fn is_bytes(name: &str) -> bool {
+  ["mem.", "swap.", "disk.", "net."].iter().any(|p| name.starts_with(p)) && !name.ends_with(".util")
+}
+
+/// A metric value for people, with sizes in KiB/MiB/GiB
+pub fn fmt_metric(name: &str, value: f64) -> String {
+  if is_bytes(name) && value.is_finite() && value >= 0.0 {
+    let size = bytesize::ByteSize::b(value as u64).to_string_as(true);
+    if name.ends_with(".rate") { format!("{}/s", size) } else { size }
+  } else if value.fract() == 0.0 {
+    format!("{}", value)
+  } else {
+    format!("{:.2}", value)
+  }
+}
+
+pub fn fmt_time(secs: f64) -> String {
+  OffsetDateTime::from_unix_timestamp(secs as i64).format("%Y-%m-%d %H:%M:%S UTC")
+}
+
+pub fn hostname() -> String {
+  fs::read_to_string("/proc/sys/kernel/hostname")
+    .or_else(|_| fs::read_to_string("/etc/hostname"))
+    .map(|h| h.trim().to_owned())
+    .unwrap_or_else(|_| "localhost".to_owned())
+}
+
+pub fn kernel_release() -> String {
+  fs::read_to_string("/proc/sys/kernel/osrelease").map(|r| r.trim().to_owned()).unwrap_or_default()
+}
+
+/// The first CPU's microcode revision from /proc/cpuinfo
+pub fn microcode() -> String {
+  let info = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+  info.lines()
+    .find(|l| l.starts_with("microcode"))
+    .and_then(|l| l.split(':').nth(1))
+    .map(|m| m.trim().to_owned())
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::metrics::demo,
+  };
+
+  #[test]
+  fn demo_snapshots_are_the_demo_machine() {
+    let config = Config::parse("[demo]\nseed = 7\n");
+    let snapshot = Snapshot::take(&config);
+    assert_eq!(snapshot.host, demo::HOST_NAME);
+    assert_eq!(snapshot.cpu, demo::CPU_NAME);
+    assert_eq!(snapshot.kernel, "");
+    assert!(snapshot.metrics.contains_key("disk./home.total"));
+  }
+
+  #[test]
+  fn json_round_trip() {
+    let mut snapshot = Snapshot::new();
+    snapshot.host = "rack1".to_owned();
+    snapshot.time = 1_700_000_000.5;
+    snapshot.kernel = "6.1.0".to_owned();
+    snapshot.metrics.insert("mem.used".to_owned(), 1024.0);
+    snapshot.alerts.insert("mem_high".to_owned(), "crit".to_owned());
+    assert_eq!(Snapshot::from_json(&snapshot.to_json()), Ok(snapshot));
+    // Older snapshots without kernel and microcode still load
+    let old = Snapshot::from_json(&json!({ "host": "rack2", "metrics": { "cpu.usage": 5.0 } })).unwrap();
+    assert_eq!((old.host.as_str(), old.kernel.as_str()), ("rack2", ""));
+  }
+
+  #[test]
+  fn metric_formats() {
+    assert_eq!(fmt_metric("mem.used", 1536.0 * 1024.0), "1.5 MiB");
+    assert_eq!(fmt_metric("net.eth0.rx.rate", 2048.0), "2.0 kiB/s");
+    assert_eq!(fmt_metric("disk.sda.util", 12.345), "12.35");
+    assert_eq!(fmt_metric("load.1", 2.0), "2");
+  }
+}
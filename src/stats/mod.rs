@@ -0,0 +1,11 @@
+pub mod proctree;
+pub mod sessions;
+
+pub use {
+  crate::{
+    stats::{
+      proctree::ProcTree,
+      sessions::Sessions,
+    },
+  },
+};
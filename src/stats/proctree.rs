@@ -0,0 +1,350 @@
+
+use {
+  std::{
+    fs,
+    path::{Path, PathBuf},
+  },
+  crate::{
+    util::{HashMap, HashSet},
+  },
+};
+
+// Rows of the page, not counting the header. Anything past this is cut off
+// so the label doesn't run off the bottom of the window.
+const MAX_ROWS: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupBy {
+  User,
+  Cgroup,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcInfo {
+  pub pid: u32,
+  pub ppid: u32,
+  pub name: String,
+  pub uid: u32,
+  pub cgroup: String,
+  pub ticks: u64,
+  pub rss_kb: u64,
+  pub cpu_pct: f32,
+}
+
+#[derive(Debug)]
+pub struct ProcGroup {
+  pub name: String,
+  pub cpu_pct: f32,
+  pub rss_kb: u64,
+  pub pids: Vec<u32>,
+}
+
+pub struct ProcTree {
+  pub root: PathBuf,
+  pub group_by: GroupBy,
+  pub expanded: HashSet<String>,
+  pub selected: usize,
+  pub procs: HashMap<u32, ProcInfo>,
+  users: HashMap<u32, String>,
+  prev_ticks: HashMap<u32, u64>,
+  prev_total: u64,
+}
+impl Default for ProcTree {
+  fn default() -> Self {
+    Self::with_root("/proc")
+  }
+}
+
+impl ProcTree {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn with_root(root: &str) -> Self {
+    Self {
+      root: PathBuf::from(root),
+      group_by: GroupBy::User,
+      expanded: HashSet::new(),
+      selected: 0,
+      procs: HashMap::new(),
+      users: read_passwd("/etc/passwd"),
+      prev_ticks: HashMap::new(),
+      prev_total: 0,
+    }
+  }
+  pub fn refresh(&mut self) {
+    let (total, cpus) = read_cpu_total(&self.root.join("stat"));
+    // Jiffies that passed on a single core since the last refresh
+    let elapsed = if self.prev_total > 0 && cpus > 0 {
+      total.saturating_sub(self.prev_total) as f32 / cpus as f32
+    } else { 0.0 };
+    self.prev_total = total;
+    let mut procs = HashMap::new();
+    let mut ticks = HashMap::new();
+    let entries = match fs::read_dir(&self.root) {
+      Ok(entries) => entries,
+      Err(e) => { println!("Process tree: can't read {}: {}", self.root.display(), e); return }
+    };
+    for entry in entries.flatten() {
+      let pid: u32 = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+        Some(pid) => pid,
+        None => continue,
+      };
+      // Processes can exit between read_dir and reading their files
+      let mut info = match read_proc(&entry.path(), pid) {
+        Some(info) => info,
+        None => continue,
+      };
+      if elapsed > 0.0 {
+        if let Some(prev) = self.prev_ticks.get(&pid) {
+          info.cpu_pct = info.ticks.saturating_sub(*prev) as f32 / elapsed * 100.0;
+        }
+      }
+      ticks.insert(pid, info.ticks);
+      procs.insert(pid, info);
+    }
+    self.prev_ticks = ticks;
+    self.procs = procs;
+    let count = self.groups().len();
+    if self.selected >= count { self.selected = count.saturating_sub(1); }
+  }
+  pub fn user_name(&self, uid: u32) -> String {
+    match self.users.get(&uid) {
+      Some(name) => name.clone(),
+      None => uid.to_string(),
+    }
+  }
+  fn group_key(&self, info: &ProcInfo) -> String {
+    match self.group_by {
+      GroupBy::User => self.user_name(info.uid),
+      GroupBy::Cgroup => slice_of(&info.cgroup),
+    }
+  }
+  /// Groups sorted by CPU usage, then by RSS
+  pub fn groups(&self) -> Vec<ProcGroup> {
+    let mut groups: HashMap<String, ProcGroup> = HashMap::new();
+    for info in self.procs.values() {
+      let key = self.group_key(info);
+      let group = groups.entry(key.clone()).or_insert_with(|| ProcGroup {
+        name: key, cpu_pct: 0.0, rss_kb: 0, pids: Vec::new(),
+      });
+      group.cpu_pct += info.cpu_pct;
+      group.rss_kb += info.rss_kb;
+      group.pids.push(info.pid);
+    }
+    let mut out: Vec<ProcGroup> = groups.into_values().collect();
+    out.sort_by(|a, b| {
+      b.cpu_pct.partial_cmp(&a.cpu_pct).unwrap_or(std::cmp::Ordering::Equal)
+        .then(b.rss_kb.cmp(&a.rss_kb))
+        .then(a.name.cmp(&b.name))
+    });
+    out
+  }
+  pub fn toggle_group_by(&mut self) {
+    self.group_by = match self.group_by {
+      GroupBy::User => GroupBy::Cgroup,
+      GroupBy::Cgroup => GroupBy::User,
+    };
+    self.selected = 0;
+    self.expanded.clear();
+  }
+  pub fn select_next(&mut self) {
+    if self.selected + 1 < self.groups().len() { self.selected += 1; }
+  }
+  pub fn select_prev(&mut self) {
+    self.selected = self.selected.saturating_sub(1);
+  }
+  pub fn toggle_selected(&mut self) {
+    let name = match self.groups().get(self.selected) {
+      Some(group) => group.name.clone(),
+      None => return,
+    };
+    if !self.expanded.remove(&name) { self.expanded.insert(name); }
+  }
+  /// The page as it should be handed to `TextMgr::update_text`
+  pub fn text(&self) -> String {
+    let by = match self.group_by { GroupBy::User => "user", GroupBy::Cgroup => "cgroup" };
+    let mut rows = vec![format!("Processes by {}  (Tab: regroup, Up/Down: select, Enter: expand)", by)];
+    for (i, group) in self.groups().iter().enumerate() {
+      let open = self.expanded.contains(&group.name);
+      rows.push(format!("{} {} {}  CPU: {:.1}%  RSS: {}  Procs: {}",
+        if i == self.selected { ">" } else { " " },
+        if open { "-" } else { "+" },
+        group.name, group.cpu_pct, fmt_kb(group.rss_kb), group.pids.len()));
+      if open { self.push_tree(&mut rows, &group.pids); }
+      if rows.len() > MAX_ROWS { break }
+    }
+    rows.truncate(MAX_ROWS + 1);
+    rows.join("\n")
+  }
+  // Lays the group's processes out by their parent links. Processes whose
+  // parent is outside the group become roots.
+  fn push_tree(&self, rows: &mut Vec<String>, pids: &[u32]) {
+    let members: HashSet<u32> = pids.iter().cloned().collect();
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    let mut roots = Vec::new();
+    for pid in pids {
+      let info = &self.procs[pid];
+      if members.contains(&info.ppid) && info.ppid != info.pid {
+        children.entry(info.ppid).or_default().push(*pid);
+      } else {
+        roots.push(*pid);
+      }
+    }
+    roots.sort();
+    for list in children.values_mut() { list.sort(); }
+    let mut stack: Vec<(u32, usize)> = roots.iter().rev().map(|pid| (*pid, 1)).collect();
+    while let Some((pid, depth)) = stack.pop() {
+      if rows.len() > MAX_ROWS { return }
+      let info = &self.procs[&pid];
+      rows.push(format!("{}{} {}  CPU: {:.1}%  RSS: {}",
+        "    ".repeat(depth), info.pid, info.name, info.cpu_pct, fmt_kb(info.rss_kb)));
+      if let Some(kids) = children.get(&pid) {
+        for kid in kids.iter().rev() { stack.push((*kid, depth + 1)); }
+      }
+    }
+  }
+}
+
+fn read_proc(dir: &Path, pid: u32) -> Option<ProcInfo> {
+  let stat = fs::read_to_string(dir.join("stat")).ok()?;
+  let (name, ppid, ticks) = parse_stat(&stat)?;
+  let status = fs::read_to_string(dir.join("status")).ok()?;
+  let mut uid = 0;
+  let mut rss_kb = 0;
+  for line in status.lines() {
+    if let Some(rest) = line.strip_prefix("Uid:") {
+      uid = rest.split_whitespace().next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    } else if let Some(rest) = line.strip_prefix("VmRSS:") {
+      rss_kb = rest.split_whitespace().next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    }
+  }
+  let cgroup = fs::read_to_string(dir.join("cgroup"))
+    .map(|s| parse_cgroup(&s))
+    .unwrap_or_default();
+  Some(ProcInfo { pid, ppid, name, uid, cgroup, ticks, rss_kb, cpu_pct: 0.0 })
+}
+
+/// Returns (comm, ppid, utime + stime) from the contents of `/proc/<pid>/stat`
+pub fn parse_stat(stat: &str) -> Option<(String, u32, u64)> {
+  // comm is wrapped in parens and may itself contain spaces or parens,
+  // so split on the last ')' rather than on whitespace.
+  let open = stat.find('(')?;
+  let close = stat.rfind(')')?;
+  let name = stat[open + 1..close].to_owned();
+  let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+  // fields[0] is state, so ppid is field 4 of the man page and so on
+  let ppid = fields.get(1)?.parse().ok()?;
+  let utime: u64 = fields.get(11)?.parse().ok()?;
+  let stime: u64 = fields.get(12)?.parse().ok()?;
+  Some((name, ppid, utime + stime))
+}
+
+/// Picks the unified (v2) hierarchy if there is one, otherwise the
+/// `name=systemd` one, otherwise whatever comes first.
+pub fn parse_cgroup(cgroup: &str) -> String {
+  let mut fallback = None;
+  for line in cgroup.lines() {
+    let mut parts = line.splitn(3, ':');
+    let (id, ctrl, path) = match (parts.next(), parts.next(), parts.next()) {
+      (Some(id), Some(ctrl), Some(path)) => (id, ctrl, path),
+      _ => continue,
+    };
+    if id == "0" && ctrl.is_empty() { return path.to_owned() }
+    if ctrl == "name=systemd" || fallback.is_none() { fallback = Some(path.to_owned()) }
+  }
+  fallback.unwrap_or_default()
+}
+
+/// The innermost systemd slice of a cgroup path, or the path itself if it
+/// isn't under one. `/user.slice/user-1000.slice/session-2.scope` gives
+/// `user-1000.slice`.
+pub fn slice_of(path: &str) -> String {
+  match path.split('/').rfind(|part| part.ends_with(".slice")) {
+    Some(slice) => slice.to_owned(),
+    None if path.is_empty() => "?".to_owned(),
+    None => path.to_owned(),
+  }
+}
+
+// Sum of all jiffies on the aggregate "cpu" line and the number of cpuN lines
+fn read_cpu_total(path: &Path) -> (u64, usize) {
+  let stat = match fs::read_to_string(path) {
+    Ok(stat) => stat,
+    Err(_) => return (0, 0),
+  };
+  let mut total = 0;
+  let mut cpus = 0;
+  for line in stat.lines() {
+    if let Some(rest) = line.strip_prefix("cpu ") {
+      total = rest.split_whitespace().filter_map(|s| s.parse::<u64>().ok()).sum();
+    } else if line.starts_with("cpu") {
+      cpus += 1;
+    }
+  }
+  (total, cpus)
+}
+
+pub fn read_passwd(path: &str) -> HashMap<u32, String> {
+  let mut out = HashMap::new();
+  if let Ok(passwd) = fs::read_to_string(path) {
+    for line in passwd.lines() {
+      let parts: Vec<&str> = line.split(':').collect();
+      if parts.len() < 3 { continue }
+      if let Ok(uid) = parts[2].parse() { out.insert(uid, parts[0].to_owned()); }
+    }
+  }
+  out
+}
+
+pub fn fmt_kb(kb: u64) -> String {
+  bytesize::ByteSize::kib(kb).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::util::temp_dir,
+  };
+
+  #[test]
+  fn stat_lines() {
+    let stat = "4242 (bash) S 1 4242 4242 34816 4300 4194304 1500 900 2 0 120 35 0 0 20 0 1 0 9000 12345 678";
+    assert_eq!(parse_stat(stat), Some(("bash".to_owned(), 1, 155)));
+    // A comm can hold spaces and parens, the last ')' is the one that counts
+    let stat = "77 (tmux: server (1)) R 76 77 77 0 -1 4194560 300 0 0 0 7 3 0 0 20 0 1 0 500 0 0";
+    assert_eq!(parse_stat(stat), Some(("tmux: server (1)".to_owned(), 76, 10)));
+    assert_eq!(parse_stat("77 (cut) S 76 77"), None);
+    assert_eq!(parse_stat("no parens here"), None);
+  }
+
+  #[test]
+  fn cgroups() {
+    let v1 = "12:cpu,cpuacct:/user.slice\n1:name=systemd:/user.slice/user-1000.slice/session-2.scope\n";
+    assert_eq!(parse_cgroup(v1), "/user.slice/user-1000.slice/session-2.scope");
+    let hybrid = format!("{}0::/system.slice/sshd.service\n", v1);
+    assert_eq!(parse_cgroup(&hybrid), "/system.slice/sshd.service");
+    assert_eq!(parse_cgroup("5:memory:/docker/abc\nbroken line\n"), "/docker/abc");
+    assert_eq!(parse_cgroup(""), "");
+  }
+
+  #[test]
+  fn slices() {
+    assert_eq!(slice_of("/user.slice/user-1000.slice/session-2.scope"), "user-1000.slice");
+    assert_eq!(slice_of("/system.slice/sshd.service"), "system.slice");
+    assert_eq!(slice_of("/docker/abc"), "/docker/abc");
+    assert_eq!(slice_of(""), "?");
+  }
+
+  #[test]
+  fn passwd() {
+    let dir = temp_dir("proctree-passwd");
+    let path = dir.join("passwd");
+    fs::write(&path, "root:x:0:0:root:/root:/bin/bash\n# comment\nalice:x:1000:1000::/home/alice:/bin/sh\nbad:x:nope:0::/:/bin/false\n").unwrap();
+    let users = read_passwd(path.to_str().unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(users.len(), 2);
+    assert_eq!((users[&0].as_str(), users[&1000].as_str()), ("root", "alice"));
+    assert!(read_passwd("/nonexistent/passwd").is_empty());
+  }
+}
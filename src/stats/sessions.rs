@@ -0,0 +1,238 @@
+
+use {
+  std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+  },
+  time::{OffsetDateTime, UtcOffset},
+};
+
+pub const UTMP_PATH: &str = "/var/run/utmp";
+
+// Layout of glibc's `struct utmp` on 64 bit Linux
+const RECORD_SIZE: usize = 384;
+const USER_PROCESS: i16 = 7;
+const LINE_OFFSET: usize = 8;
+const LINE_SIZE: usize = 32;
+const USER_OFFSET: usize = 44;
+const USER_SIZE: usize = 32;
+const HOST_OFFSET: usize = 76;
+const HOST_SIZE: usize = 256;
+const TV_SEC_OFFSET: usize = 340;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UtmpRecord {
+  pub kind: i16,
+  pub pid: i32,
+  pub line: String,
+  pub user: String,
+  pub host: String,
+  pub login_time: i64,
+}
+impl UtmpRecord {
+  pub fn parse(buf: &[u8]) -> Option<Self> {
+    if buf.len() < RECORD_SIZE { return None }
+    Some(Self {
+      kind: i16::from_ne_bytes([buf[0], buf[1]]),
+      pid: i32::from_ne_bytes([buf[4], buf[5], buf[6], buf[7]]),
+      line: c_str(&buf[LINE_OFFSET..LINE_OFFSET + LINE_SIZE]),
+      user: c_str(&buf[USER_OFFSET..USER_OFFSET + USER_SIZE]),
+      host: c_str(&buf[HOST_OFFSET..HOST_OFFSET + HOST_SIZE]),
+      login_time: i32::from_ne_bytes([
+        buf[TV_SEC_OFFSET], buf[TV_SEC_OFFSET + 1], buf[TV_SEC_OFFSET + 2], buf[TV_SEC_OFFSET + 3],
+      ]) as i64,
+    })
+  }
+}
+
+/// Every complete record in a utmp file's contents
+pub fn parse_utmp(data: &[u8]) -> Vec<UtmpRecord> {
+  data.chunks(RECORD_SIZE).filter_map(UtmpRecord::parse).collect()
+}
+
+fn c_str(buf: &[u8]) -> String {
+  let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+  String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+  pub user: String,
+  pub tty: String,
+  pub host: String,
+  pub login_time: i64,
+  /// Seconds since the tty was last read from, if it could be stat'd
+  pub idle: Option<u64>,
+}
+
+pub struct Sessions {
+  pub utmp: PathBuf,
+  pub dev: PathBuf,
+  pub sessions: Vec<Session>,
+  read_failed: bool,
+}
+impl Default for Sessions {
+  fn default() -> Self {
+    Self::with_path(UTMP_PATH)
+  }
+}
+
+impl Sessions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn with_path(utmp: &str) -> Self {
+    Self {
+      utmp: PathBuf::from(utmp),
+      dev: PathBuf::from("/dev"),
+      sessions: Vec::new(),
+      read_failed: false,
+    }
+  }
+  pub fn refresh(&mut self) {
+    let data = match fs::read(&self.utmp) {
+      Ok(data) => data,
+      Err(e) => {
+        // Only complain once, this gets called every second
+        if !self.read_failed { println!("Sessions: can't read {}: {}", self.utmp.display(), e); }
+        self.read_failed = true;
+        self.sessions.clear();
+        return
+      }
+    };
+    self.read_failed = false;
+    let now = SystemTime::now();
+    self.sessions = parse_utmp(&data).into_iter()
+      .filter(|rec| rec.kind == USER_PROCESS && !rec.user.is_empty())
+      .map(|rec| Session {
+        idle: idle_secs(&self.dev.join(&rec.line), now),
+        user: rec.user,
+        tty: rec.line,
+        host: rec.host,
+        login_time: rec.login_time,
+      })
+      .collect();
+  }
+  pub fn text(&self) -> String {
+    let mut rows = vec![format!("Sessions: {}", self.sessions.len())];
+    for s in &self.sessions {
+      let host = if s.host.is_empty() { "local" } else { &s.host };
+      let idle = match s.idle { Some(secs) => fmt_idle(secs), None => "?".to_owned() };
+      rows.push(format!("{} {} from {} since {} idle {}",
+        s.user, s.tty, host, fmt_login(s.login_time), idle));
+    }
+    rows.join("\n")
+  }
+}
+
+// The tty's atime moves whenever the user types, which is what w(1) uses too
+fn idle_secs(tty: &Path, now: SystemTime) -> Option<u64> {
+  let atime = fs::metadata(tty).ok()?.accessed().ok()?;
+  Some(now.duration_since(atime).map(|d| d.as_secs()).unwrap_or(0))
+}
+
+pub fn fmt_idle(secs: u64) -> String {
+  if secs < 60 { format!("{}s", secs) }
+  else if secs < 3600 { format!("{}m", secs / 60) }
+  else if secs < 86400 { format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60) }
+  else { format!("{}d", secs / 86400) }
+}
+
+pub fn fmt_login(secs: i64) -> String {
+  let offset = UtcOffset::try_current_local_offset().unwrap_or(UtcOffset::UTC);
+  OffsetDateTime::from_unix_timestamp(secs).to_offset(offset).format("%Y-%m-%d %H:%M")
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::util::temp_dir,
+  };
+
+  const BOOT_TIME: i16 = 2;
+  const LOGIN_PROCESS: i16 = 6;
+  const DEAD_PROCESS: i16 = 8;
+
+  fn record(kind: i16, pid: i32, line: &str, user: &str, host: &str, login_time: i32) -> Vec<u8> {
+    let mut buf = vec![0u8; RECORD_SIZE];
+    buf[0..2].copy_from_slice(&kind.to_ne_bytes());
+    buf[4..8].copy_from_slice(&pid.to_ne_bytes());
+    buf[LINE_OFFSET..LINE_OFFSET + line.len()].copy_from_slice(line.as_bytes());
+    buf[USER_OFFSET..USER_OFFSET + user.len()].copy_from_slice(user.as_bytes());
+    buf[HOST_OFFSET..HOST_OFFSET + host.len()].copy_from_slice(host.as_bytes());
+    buf[TV_SEC_OFFSET..TV_SEC_OFFSET + 4].copy_from_slice(&login_time.to_ne_bytes());
+    buf
+  }
+
+  fn fixture() -> Vec<u8> {
+    [
+      record(BOOT_TIME, 0, "~", "reboot", "5.10.0", 1_700_000_000),
+      record(LOGIN_PROCESS, 812, "tty1", "LOGIN", "", 1_700_000_010),
+      record(USER_PROCESS, 1400, "pts/0", "alice", "10.0.0.7", 1_700_000_100),
+      record(DEAD_PROCESS, 1500, "pts/1", "", "", 1_700_000_200),
+      record(USER_PROCESS, 1600, "tty2", "bob", "", 1_700_000_300),
+    ].concat()
+  }
+
+  #[test]
+  fn parses_every_field() {
+    let recs = parse_utmp(&fixture());
+    assert_eq!(recs.len(), 5);
+    assert_eq!(recs[2], UtmpRecord {
+      kind: USER_PROCESS,
+      pid: 1400,
+      line: "pts/0".to_owned(),
+      user: "alice".to_owned(),
+      host: "10.0.0.7".to_owned(),
+      login_time: 1_700_000_100,
+    });
+    let kinds: Vec<i16> = recs.iter().map(|r| r.kind).collect();
+    assert_eq!(kinds, vec![BOOT_TIME, LOGIN_PROCESS, USER_PROCESS, DEAD_PROCESS, USER_PROCESS]);
+  }
+
+  #[test]
+  fn fields_that_fill_their_buffer() {
+    let user = "u".repeat(USER_SIZE);
+    let rec = UtmpRecord::parse(&record(USER_PROCESS, 1, "pts/9", &user, "", 0)).unwrap();
+    assert_eq!(rec.user, user);
+  }
+
+  #[test]
+  fn drops_truncated_records() {
+    assert_eq!(UtmpRecord::parse(&[0u8; RECORD_SIZE - 1]), None);
+    let mut data = fixture();
+    data.extend_from_slice(&record(USER_PROCESS, 1700, "pts/2", "carol", "", 0)[..100]);
+    let recs = parse_utmp(&data);
+    assert_eq!(recs.len(), 5);
+    assert!(recs.iter().all(|r| r.user != "carol"));
+    assert!(parse_utmp(&[]).is_empty());
+  }
+
+  #[test]
+  fn sessions_are_user_processes_only() {
+    let dir = temp_dir("sessions");
+    let utmp = dir.join("utmp");
+    fs::write(&utmp, fixture()).unwrap();
+    let mut sessions = Sessions::with_path(utmp.to_str().unwrap());
+    // No ttys in here, so idle can't be worked out
+    sessions.dev = dir.clone();
+    sessions.refresh();
+    let users: Vec<(&str, &str, &str)> = sessions.sessions.iter().map(|s| (&s.user[..], &s.tty[..], &s.host[..])).collect();
+    assert_eq!(users, vec![("alice", "pts/0", "10.0.0.7"), ("bob", "tty2", "")]);
+    assert!(sessions.sessions.iter().all(|s| s.idle.is_none()));
+    assert!(sessions.text().contains("bob tty2 from local"));
+    fs::remove_dir_all(&dir).unwrap();
+    sessions.refresh();
+    assert!(sessions.sessions.is_empty());
+  }
+
+  #[test]
+  fn idle_formats() {
+    assert_eq!(fmt_idle(42), "42s");
+    assert_eq!(fmt_idle(125), "2m");
+    assert_eq!(fmt_idle(3 * 3600 + 5 * 60), "3h05m");
+    assert_eq!(fmt_idle(2 * 86400 + 10), "2d");
+  }
+}
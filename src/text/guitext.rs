@@ -2,6 +2,7 @@
 
 
 use gamemgr::GameMgr;
+use redact;
 use text::{TextMgr, RTextMesh, RFontEffect, }; // RFontType, 
 use util::rvector::{Vector2f, }; // Vector3f, 
 
@@ -24,7 +25,7 @@ impl GuiText {
     Self {
       font: font.to_owned(),
       label: label.to_owned(),
-      text: text.to_owned(),
+      text: redact::redact(text),
       position: position,
       effect: RFontEffect::new(),
       font_size: font_size,
@@ -60,7 +61,7 @@ impl GuiText {
     self.loaded = true;
   }
   pub fn update_text(&mut self, textmgr: &mut TextMgr, mgr: GameMgr, text: &str) {
-    self.text = text.to_string();
+    self.text = redact::redact(text);
     self.update_size(textmgr, mgr);
   }
   pub fn update_size(&mut self, textmgr: &mut TextMgr, mgr: GameMgr) {
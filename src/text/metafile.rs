@@ -40,6 +40,15 @@ pub fn test_noms() {
   test_get_char();
 }
 
+/// A line's parse result, with nom's error turned into a message
+fn parsed<T>(result: nom::IResult<&str, T>) -> Result<T, String> {
+  match result {
+    Ok((_, result)) => Ok(result),
+    Err(nom::Err::Error((s, e))) | Err(nom::Err::Failure((s, e))) => Err(format!("{:?} at {}", e, s.trim_end_matches(crate::EOF))),
+    Err(nom::Err::Incomplete(e)) => Err(format!("{:?}", e)),
+  }
+}
+
 named!(u32_digit<&str, u32 >,
     map_res!( digit, FromStr::from_str )
 );
@@ -61,16 +70,9 @@ pub struct InfoVars {
   padding: Vec<u32>,
   spacing: Vec<u32>,
 }
-fn get_info(tstr: &str) -> InfoVars {
+fn get_info(tstr: &str) -> Result<InfoVars, String> {
   let eofs = eof(tstr);
-  match _get_info(&eofs) {
-    Ok((_, result)) => { result }
-    Err(e) => { match e {
-      nom::Err::Error((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Failure((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Incomplete(e) => { panic!("{:?}", e) }
-    }}
-  }
+  parsed(_get_info(&eofs))
 }
 named!(_get_info<&str, InfoVars >,
   do_parse!(
@@ -94,7 +96,7 @@ named!(_get_info<&str, InfoVars >,
 );
 pub fn test_get_info() {
   let tstr = "info face=\"Times New Roman\" size=59 bold=0 italic=0 charset=\"\" unicode=0 stretchH=100 smooth=1 aa=1 padding=8,8,8,8 spacing=0,0";
-  let test = get_info(tstr);
+  let test = get_info(tstr).unwrap();
   println!("{:?}", test);
 }
 // common lineHeight=84 base=54 scaleW=512 scaleH=512 pages=1 packed=0
@@ -107,16 +109,9 @@ pub struct CommonVars {
   pub pages: u32,
   pub packed: u32,
 }
-fn get_common(tstr: &str) -> CommonVars {
+fn get_common(tstr: &str) -> Result<CommonVars, String> {
   let eofs = eof(tstr);
-  match _get_common(&eofs) {
-    Ok((_, result)) => { result }
-    Err(e) => { match e {
-      nom::Err::Error((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Failure((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Incomplete(e) => { panic!("{:?}", e) }
-    }}
-  }
+  parsed(_get_common(&eofs))
 }
 named!(_get_common<&str, CommonVars >,
   do_parse!(
@@ -132,7 +127,7 @@ named!(_get_common<&str, CommonVars >,
 );
 pub fn test_get_common() {
   let tstr = "common lineHeight=84 base=54 scaleW=512 scaleH=512 pages=1 packed=0";
-  let test = get_common(tstr);
+  let test = get_common(tstr).unwrap();
   println!("{:?}", test);
 }
 // page id=0 file="TimesNewRoman.png"
@@ -141,16 +136,9 @@ pub struct PageVars {
   id: u32,
   file: String,
 }
-fn get_page(tstr: &str) -> PageVars {
+fn get_page(tstr: &str) -> Result<PageVars, String> {
   let eofs = eof(tstr);
-  match _get_page(&eofs) {
-    Ok((_, result)) => { result }
-    Err(e) => { match e {
-      nom::Err::Error((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Failure((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Incomplete(e) => { panic!("{:?}", e) }
-    }}
-  }
+  parsed(_get_page(&eofs))
 }
 named!(_get_page<&str, PageVars >,
   do_parse!(
@@ -162,20 +150,13 @@ named!(_get_page<&str, PageVars >,
 );
 pub fn test_get_page() {
   let tstr = &eof("page id=0 file=\"TimesNewRoman.png\"");
-  let test = get_page(tstr);
+  let test = get_page(tstr).unwrap();
   println!("{:?}", test);
 }
 // chars count=95
-fn get_char_count(tstr: &str) -> u32 {
+fn get_char_count(tstr: &str) -> Result<u32, String> {
   let eofs = eof(tstr);
-  match _get_char_count(&eofs) {
-    Ok((_, result)) => { result }
-    Err(e) => { match e {
-      nom::Err::Error((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Failure((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Incomplete(e) => { panic!("{:?}", e) }
-    }}
-  }
+  parsed(_get_char_count(&eofs))
 }
 named!(_get_char_count<&str, u32 >,
   do_parse!(
@@ -184,7 +165,7 @@ named!(_get_char_count<&str, u32 >,
 );
 pub fn test_get_char_count() {
   let tstr = &eof("chars count=95");
-  let test = get_char_count(tstr);
+  let test = get_char_count(tstr).unwrap();
   println!("{:?}", test);
 }
 // char id=32   x=0     y=0     width=0     height=0     xoffset=-5     yoffset=54    xadvance=31     page=0  chnl=0
@@ -201,16 +182,9 @@ pub struct CharVars {
   pub page: u32,
   pub chnl: u32,
 }
-fn get_char(tstr: &str) -> CharVars {
+fn get_char(tstr: &str) -> Result<CharVars, String> {
   let eofs = eof(tstr);
-  match _get_char(&eofs) {
-    Ok((_, result)) => { result }
-    Err(e) => { match e {
-      nom::Err::Error((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Failure((s,e)) => { panic!("{:?} {}", e,s) }
-      nom::Err::Incomplete(e) => { panic!("{:?}", e) }
-    }}
-  }
+  parsed(_get_char(&eofs))
 }
 named!(_get_char<&str, CharVars >,
   do_parse!(
@@ -231,7 +205,7 @@ named!(_get_char<&str, CharVars >,
 );
 pub fn test_get_char() {
   let tstr = "char id=32   x=0     y=0     width=0     height=0     xoffset=-5     yoffset=54    xadvance=31     page=0  chnl=0 ";
-  let test = get_char(tstr);
+  let test = get_char(tstr).unwrap();
   println!("{:?}", test);
 }
 
@@ -260,6 +234,21 @@ pub struct MetaFile {
 }
 impl MetaFile {
   pub fn new(aspect_ratio: f32, font_file: &str) -> Self {
+    match Self::load(aspect_ratio, font_file) {
+      Ok(out) => out,
+      Err(e) => panic!("{}", e),
+    }
+  }
+  /// Reads `res/fonts/<font_file>.fnt`, failing on anything it can't make
+  /// sense of rather than panicking
+  pub fn load(aspect_ratio: f32, font_file: &str) -> Result<Self, String> {
+    let filename = format!("res/fonts/{}.fnt", font_file);
+    let path = Path::new(&filename);
+    let file = File::open(path).map_err(|why| format!("couldn't open {}: {}", path.display(), why))?;
+    Self::read(aspect_ratio, &filename, BufReader::new(file))
+  }
+  /// Parses a metafile's contents, `display` being what errors call it
+  pub fn read(aspect_ratio: f32, display: &str, reader: impl BufRead) -> Result<Self, String> {
     let mut out = Self {
       aspect_ratio,
       vertical_per_pixel_size: 0.0,
@@ -276,28 +265,24 @@ impl MetaFile {
       count: 0,
       chars: Vec::new(),
     };
-    let filename = format!("res/fonts/{}.fnt", font_file);
-    let path = Path::new(&filename);
-    let display = path.display();
-    let file = match File::open(&path) {
-      Err(why) => panic!("couldn't open {}: {}", display, why),
-      Ok(file) => file,
-    };
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-      match &(line.unwrap()) {
-        l if &l[..5] == "info " => { out.info = Some(get_info(l)); }
-        l if &l[..7] == "common " => { out.common = Some(get_common(l)); }
-        l if &l[..5] == "page " => { out.page = Some(get_page(l)); }
-        l if &l[..6] == "chars " => { out.count = get_char_count(l); }
-        l if &l[..5] == "char " => { out.chars.push(get_char(l)); }
+    for (idx, line) in reader.lines().enumerate() {
+      let line = line.map_err(|why| format!("couldn't read {}: {}", display, why))?;
+      let bad = |e: String| format!("{} line {}: {}", display, idx + 1, e);
+      match line.as_str() {
+        l if l.starts_with("info ") => { out.info = Some(get_info(l).map_err(bad)?); }
+        l if l.starts_with("common ") => { out.common = Some(get_common(l).map_err(bad)?); }
+        l if l.starts_with("page ") => { out.page = Some(get_page(l).map_err(bad)?); }
+        l if l.starts_with("chars ") => { out.count = get_char_count(l).map_err(bad)?; }
+        l if l.starts_with("char ") => { out.chars.push(get_char(l).map_err(bad)?); }
         _ => ()
       }
     }
+    if out.info.is_none() { return Err(format!("{} has no info line", display)) }
+    if out.common.is_none() { return Err(format!("{} has no common line", display)) }
     out.load_padding_data();
     out.load_line_sizes();
     out.load_char_data();
-    out
+    Ok(out)
   }
   pub fn get(&self, ascii: u32) -> Option<&RChar> {
     self.metadata.get(&ascii)
@@ -353,3 +338,32 @@ impl MetaFile {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const INFO: &str = "info face=\"Sans\" size=59 bold=0 italic=0 charset=\"\" unicode=0 stretchH=100 smooth=1 aa=1 padding=8,8,8,8 spacing=0,0";
+  const COMMON: &str = "common lineHeight=84 base=54 scaleW=512 scaleH=512 pages=1 packed=0";
+  const SPACE: &str = "char id=32   x=0     y=0     width=0     height=0     xoffset=-5     yoffset=54    xadvance=31     page=0  chnl=0 ";
+
+  fn read(lines: &[&str]) -> Result<MetaFile, String> {
+    MetaFile::read(1.0, "test.fnt", lines.join("\n").as_bytes())
+  }
+
+  #[test]
+  fn shipped_fonts_load() {
+    let font = MetaFile::load(16.0 / 9.0, "sans").unwrap();
+    assert!(font.get('A' as u32).is_some());
+    assert!(MetaFile::load(1.0, "no-such-font").unwrap_err().starts_with("couldn't open res/fonts/no-such-font.fnt"));
+  }
+
+  #[test]
+  fn bad_metafiles_are_errors() {
+    let font = read(&[INFO, COMMON, "", "chars count=1", SPACE]).unwrap();
+    assert!(font.space_width > 0.0);
+    assert!(read(&[INFO, COMMON, "char id=65 x=oops"]).unwrap_err().starts_with("test.fnt line 3: "));
+    assert_eq!(read(&[COMMON, SPACE]).unwrap_err(), "test.fnt has no info line");
+    assert_eq!(read(&[INFO]).unwrap_err(), "test.fnt has no common line");
+  }
+}
@@ -42,6 +42,13 @@ impl RFontType {
       rtmc: RTextMeshCreator::new(aspect_ratio, font),
     }
   }
+  /// Like `new`, but a bad metafile is an error instead of a panic
+  pub fn load(aspect_ratio: f32, font: &str) -> Result<Self, String> {
+    Ok(Self {
+      tex_atlas: font.to_owned(),
+      rtmc: RTextMeshCreator::load(aspect_ratio, font)?,
+    })
+  }
   pub fn load_text(&mut self, text: &mut GuiTextVals) -> RTextMesh {
     self.rtmc.create_text_mesh(text)
   }
@@ -104,6 +111,22 @@ impl RFontEffect {
     if self.timer_g >= self.max_g { self.timer_g -= self.max_g; }
     if self.timer_b >= self.max_b { self.timer_b -= self.max_b; }
   }
+  /// Runs all three border channels in step over `period` seconds, so the
+  /// border flashes instead of drifting through colours.
+  pub fn pulse(&mut self, period: f32) {
+    self.max_r = period;
+    self.max_g = period;
+    self.max_b = period;
+    self.timer_g = self.timer_r % period;
+    self.timer_b = self.timer_r % period;
+    self.timer_r %= period;
+  }
+  pub fn reset_anim(&mut self) {
+    let fresh = Self::new();
+    self.max_r = fresh.max_r;
+    self.max_g = fresh.max_g;
+    self.max_b = fresh.max_b;
+  }
   pub fn anim_border_colour(&mut self) {
     let r = self.timer_r / self.max_r;
     let g = self.timer_g / self.max_g;
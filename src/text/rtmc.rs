@@ -18,11 +18,17 @@ pub struct RTextMeshCreator {
 }
 impl RTextMeshCreator {
   pub fn new(aspect_ratio: f32, file: &str) -> Self {
+    Self::with_metadata(MetaFile::new(aspect_ratio, file))
+  }
+  pub fn load(aspect_ratio: f32, file: &str) -> Result<Self, String> {
+    MetaFile::load(aspect_ratio, file).map(Self::with_metadata)
+  }
+  fn with_metadata(metadata: MetaFile) -> Self {
     Self {
       line_ht: LINE_HEIGHT,
       space_ascii: SPACE_ASCII,
       newline_ascii: NEWLINE_ASCII,
-      metadata: MetaFile::new(aspect_ratio, file),
+      metadata,
     }
   }
   pub fn update_size(&mut self, aspect_ratio: f32) {
@@ -2,6 +2,7 @@
 use {
   crate::{
     gamemgr::GameMgr,
+    loader::decode_texture,
     text::{
       GuiText,
       RFontType,
@@ -89,6 +90,39 @@ impl TextMgr {
     };
     if rm { self.active_text.remove(&font); }
   }
+  /// Reads every font's metafile and atlas back in from `res/` and rebuilds
+  /// the labels using them. Everything is read and decoded before any font
+  /// is swapped, so one bad file leaves all the fonts as they were.
+  pub fn reload_fonts(&mut self, mgr: GameMgr) -> Result<usize, String> {
+    let mut mgr = mgr;
+    let mut loaded = Vec::new();
+    for font in self.fonts.keys() {
+      let fnt = RFontType::load(mgr.aspect_ratio(), font)?;
+      let img = decode_texture(font)?;
+      loaded.push((font.clone(), fnt, img));
+    }
+    let count = loaded.len();
+    for (font, fnt, img) in loaded {
+      mgr.replace_texture(&font, img);
+      self.fonts.insert(font, fnt);
+    }
+    self.update_size(mgr);
+    Ok(count)
+  }
+  /// Moves a label and resizes its text, rebuilding the mesh if it has one
+  pub fn place_label(&mut self, mgr: GameMgr, label: &str, x: f32, y: f32, font_size: f32, line_max_size: f32) {
+    let mut text = self.texts.remove(label);
+    if let Some(ref mut text) = text {
+      let resized = text.font_size != font_size || text.line_max_size != line_max_size;
+      text.position = Vector2f::new(x, y);
+      text.font_size = font_size;
+      text.line_max_size = line_max_size;
+      if resized { text.update_size(self, mgr); }
+    }
+    if let Some(text) = text {
+      self.texts.insert(label.to_owned(), text);
+    }
+  }
   #[allow(dead_code)]
   pub fn update_text(&mut self, mgr: GameMgr, label: &str, new_text: &str) {
     let mut text = self.texts.remove(label);
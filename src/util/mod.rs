@@ -21,3 +21,13 @@ pub use {
     collections::{ HashMap, HashSet, },
   },
 };
+
+/// A fresh, empty directory for a test to work in, `name` telling it apart
+/// from those of tests running alongside
+#[cfg(test)]
+pub fn temp_dir(name: &str) -> std::path::PathBuf {
+  let dir = std::env::temp_dir().join(format!("raum-{}-{}", name, std::process::id()));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+  dir
+}
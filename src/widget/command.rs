@@ -0,0 +1,210 @@
+
+use {
+  std::{
+    io::ErrorKind,
+    sync::mpsc::{channel, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+  },
+  subprocess::{Exec, ExitStatus, Redirection},
+  crate::{
+    config::Section,
+    widget::{Placement, Widget, WidgetUpdate, COLOUR_ERROR, COLOUR_NORMAL},
+  },
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CmdOutput {
+  pub stdout: String,
+  pub stderr: String,
+  /// None if the command had to be killed for running over its timeout
+  pub exit: Option<ExitStatus>,
+}
+impl CmdOutput {
+  /// Going by the exit status alone, plenty of commands that worked still
+  /// print warnings to stderr
+  pub fn failed(&self) -> bool {
+    !self.exit.map(|status| status.success()).unwrap_or(false)
+  }
+}
+
+/// Runs `cmd` through the shell, killing it if it runs longer than `timeout`.
+/// Whatever it printed before being killed is still returned.
+pub fn call_cmd(cmd: &str, timeout: Duration) -> Result<CmdOutput, String> {
+  call_cmd_env(cmd, &[], timeout)
+}
+
+/// `call_cmd` with extra environment variables
+pub fn call_cmd_env(cmd: &str, env: &[(String, String)], timeout: Duration) -> Result<CmdOutput, String> {
+  let mut exec = Exec::shell(cmd);
+  for (key, val) in env { exec = exec.env(key, val); }
+  let mut p = exec
+    .stdin(Redirection::None)
+    .stdout(Redirection::Pipe)
+    .stderr(Redirection::Pipe)
+    .popen().map_err(|e| e.to_string())?;
+  let started = Instant::now();
+  let (out, err, timed_out) = match p.communicate_start(None).limit_time(timeout).read() {
+    Ok((out, err)) => (out, err, false),
+    Err(e) => {
+      let timed_out = e.kind() == ErrorKind::TimedOut;
+      if !timed_out { return Err(e.to_string()) }
+      (e.capture.0, e.capture.1, true)
+    }
+  };
+  let exit = if timed_out {
+    let _ = p.kill();
+    let _ = p.wait();
+    None
+  } else {
+    // Output closed, but the process may still be winding down
+    let left = timeout.checked_sub(started.elapsed()).unwrap_or_default();
+    match p.wait_timeout(left).map_err(|e| e.to_string())? {
+      Some(status) => Some(status),
+      None => { let _ = p.kill(); let _ = p.wait(); None }
+    }
+  };
+  Ok(CmdOutput {
+    stdout: String::from_utf8_lossy(&out.unwrap_or_default()).into_owned(),
+    stderr: String::from_utf8_lossy(&err.unwrap_or_default()).into_owned(),
+    exit,
+  })
+}
+
+/// A label showing the output of a shell command, rerun every `interval`.
+///
+///   [command uptime]
+///   cmd = uptime
+///   interval = 5
+///   timeout = 2
+///   lines = 3
+pub struct CommandWidget {
+  pub label: String,
+  pub cmd: String,
+  pub interval: Duration,
+  pub timeout: Duration,
+  pub max_lines: usize,
+  pub placement: Placement,
+  next_run: Instant,
+  running: Option<Receiver<Result<CmdOutput, String>>>,
+}
+impl CommandWidget {
+  pub fn new(label: &str, cmd: &str, placement: Placement) -> Self {
+    Self {
+      label: label.to_owned(),
+      cmd: cmd.to_owned(),
+      interval: Duration::from_secs(10),
+      timeout: Duration::from_secs(5),
+      max_lines: 5,
+      placement,
+      next_run: Instant::now(),
+      running: None,
+    }
+  }
+  pub fn from_section(section: &Section) -> Self {
+    let mut out = Self::new(&section.name, &section.get_str("cmd", ""), Placement::from_section(section));
+    out.interval = Duration::from_secs_f32(section.get_or("interval", 10.0_f32).max(0.1));
+    out.timeout = Duration::from_secs_f32(section.get_or("timeout", 5.0_f32).max(0.1));
+    out.max_lines = section.get_or("lines", 5);
+    out
+  }
+  fn start(&mut self) {
+    let (tx, rx) = channel();
+    let cmd = self.cmd.clone();
+    let timeout = self.timeout;
+    thread::spawn(move || { let _ = tx.send(call_cmd(&cmd, timeout)); });
+    self.running = Some(rx);
+  }
+  pub fn format(&self, result: &Result<CmdOutput, String>) -> WidgetUpdate {
+    let out = match result {
+      Ok(out) => out,
+      Err(e) => return WidgetUpdate::single(format!("{}: {}", self.label, e), COLOUR_ERROR),
+    };
+    let mut lines: Vec<String> = out.stdout.lines().map(|l| l.replace('\t', "    ")).collect();
+    if out.failed() {
+      lines.extend(out.stderr.lines().map(|l| l.replace('\t', "    ")));
+      lines.push(match out.exit {
+        Some(ExitStatus::Exited(code)) => format!("exit code {}", code),
+        Some(ExitStatus::Signaled(sig)) => format!("killed by signal {}", sig),
+        Some(status) => format!("{:?}", status),
+        None => format!("timed out after {:.1}s", self.timeout.as_secs_f32()),
+      });
+    }
+    // A failure keeps the tail, that's where the exit code and errors end up
+    if lines.len() > self.max_lines {
+      if out.failed() { lines.drain(..lines.len() - self.max_lines); } else { lines.truncate(self.max_lines); }
+    }
+    let colour = if out.failed() { COLOUR_ERROR } else { COLOUR_NORMAL };
+    WidgetUpdate::single(lines.join("\n"), colour)
+  }
+}
+impl Widget for CommandWidget {
+  fn label(&self) -> &str { &self.label }
+  fn placement(&self) -> &Placement { &self.placement }
+  fn poll(&mut self) -> Option<WidgetUpdate> {
+    if let Some(rx) = self.running.take() {
+      return match rx.try_recv() {
+        Ok(result) => Some(self.format(&result)),
+        Err(TryRecvError::Empty) => { self.running = Some(rx); None }
+        Err(TryRecvError::Disconnected) => None,
+      }
+    }
+    if self.cmd.is_empty() || Instant::now() < self.next_run { return None }
+    self.next_run = Instant::now() + self.interval;
+    self.start();
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::config::Config,
+  };
+
+  fn widget(lines: usize) -> CommandWidget {
+    let config = Config::parse(&format!("[command cmd]\ntimeout = 0.5\nlines = {}\n", lines));
+    CommandWidget::from_section(config.sections("command")[0])
+  }
+
+  fn output(stdout: &str, stderr: &str, exit: Option<ExitStatus>) -> Result<CmdOutput, String> {
+    Ok(CmdOutput { stdout: stdout.to_owned(), stderr: stderr.to_owned(), exit })
+  }
+
+  #[test]
+  fn fails_by_exit_status() {
+    let out = call_cmd("echo hi; echo careful >&2", Duration::from_secs(5)).unwrap();
+    assert_eq!((out.stdout.as_str(), out.stderr.as_str(), out.exit), ("hi\n", "careful\n", Some(ExitStatus::Exited(0))));
+    assert!(!out.failed());
+    let out = call_cmd("exit 3", Duration::from_secs(5)).unwrap();
+    assert_eq!(out.exit, Some(ExitStatus::Exited(3)));
+    assert!(out.failed());
+  }
+
+  #[test]
+  fn timeout_keeps_partial_output() {
+    let started = Instant::now();
+    let out = call_cmd("echo partial; sleep 5", Duration::from_millis(300)).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(4));
+    assert_eq!((out.stdout.as_str(), out.exit), ("partial\n", None));
+    assert!(out.failed());
+    let update = widget(5).format(&Ok(out));
+    assert_eq!(update.rows[0].text, "partial\ntimed out after 0.5s");
+    assert_eq!(update.rows[0].colour, COLOUR_ERROR);
+  }
+
+  #[test]
+  fn formats_output() {
+    let update = widget(2).format(&output("one\ttab\ntwo\nthree\n", "careful\n", Some(ExitStatus::Exited(0))));
+    assert_eq!(update.rows[0].text, "one    tab\ntwo");
+    assert_eq!(update.rows[0].colour, COLOUR_NORMAL);
+    let update = widget(3).format(&output("one\ntwo\nthree\n", "oops\n", Some(ExitStatus::Exited(2))));
+    assert_eq!(update.rows[0].text, "three\noops\nexit code 2");
+    assert_eq!(update.rows[0].colour, COLOUR_ERROR);
+    let update = widget(3).format(&output("", "", Some(ExitStatus::Signaled(9))));
+    assert_eq!(update.rows[0].text, "killed by signal 9");
+    let update = widget(3).format(&Err("no such file".to_owned()));
+    assert_eq!((update.rows[0].text.as_str(), update.rows[0].colour), ("cmd: no such file", COLOUR_ERROR));
+  }
+}
@@ -0,0 +1,265 @@
+
+use {
+  std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+  },
+  serde_json::Value,
+  subprocess::Exec,
+  crate::{
+    config::Section,
+    widget::{Placement, Widget, WidgetUpdate, COLOUR_NORMAL},
+  },
+};
+
+/// Pulls `key=value` pairs out of a line. A line starting with '{' is taken
+/// to be a JSON object, and each of its members becomes a pair. Nested
+/// objects are flattened with dots, so `{"db": {"lag": 3}}` gives `db.lag`.
+pub fn parse_line(line: &str) -> Vec<(String, String)> {
+  let line = line.trim();
+  let mut out = Vec::new();
+  if line.is_empty() || line.starts_with('#') { return out }
+  if line.starts_with('{') {
+    match serde_json::from_str::<Value>(line) {
+      Ok(value) => flatten("", &value, &mut out),
+      Err(e) => println!("Feed: bad JSON line: {}", e),
+    }
+  } else if let Some(eq) = line.find('=') {
+    let key = line[..eq].trim();
+    if !key.is_empty() { out.push((key.to_owned(), line[eq + 1..].trim().to_owned())); }
+  }
+  out
+}
+
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+  match value {
+    Value::Object(map) => {
+      for (key, val) in map {
+        let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        flatten(&key, val, out);
+      }
+    }
+    Value::String(s) if !prefix.is_empty() => out.push((prefix.to_owned(), s.clone())),
+    Value::Null => (),
+    _ if !prefix.is_empty() => out.push((prefix.to_owned(), value.to_string())),
+    _ => (),
+  }
+}
+
+/// Latest value seen for each key, shared with the reader threads
+#[derive(Default)]
+pub struct FeedValues {
+  pub values: BTreeMap<String, (String, Instant)>,
+  pub changed: bool,
+}
+impl FeedValues {
+  pub fn insert_line(&mut self, line: &str) {
+    let now = Instant::now();
+    for (key, val) in parse_line(line) {
+      self.values.insert(key, (val, now));
+      self.changed = true;
+    }
+  }
+  /// Drops everything not updated within `ttl`
+  pub fn expire(&mut self, ttl: Duration) {
+    let before = self.values.len();
+    self.values.retain(|_, (_, at)| at.elapsed() < ttl);
+    if self.values.len() != before { self.changed = true; }
+  }
+}
+
+/// Reads lines from a named pipe forever, reopening it each time the last
+/// writer closes it. The pipe is created if it doesn't exist.
+pub fn watch_fifo(path: PathBuf, values: Arc<Mutex<FeedValues>>) {
+  if !path.exists() {
+    match Exec::cmd("mkfifo").arg(&path).join() {
+      Ok(status) if status.success() => (),
+      Ok(status) => { println!("Feed: can't create fifo {}: mkfifo {:?}", path.display(), status); return }
+      Err(e) => { println!("Feed: can't create fifo {}: {}", path.display(), e); return }
+    }
+  }
+  match fs::metadata(&path) {
+    Ok(meta) if meta.file_type().is_fifo() => (),
+    _ => { println!("Feed: {} is not a fifo", path.display()); return }
+  }
+  thread::spawn(move || loop {
+    // Blocks until something opens the pipe for writing
+    let file = match File::open(&path) {
+      Ok(file) => file,
+      Err(e) => { println!("Feed: can't open {}: {}", path.display(), e); return }
+    };
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+      values.lock().unwrap().insert_line(&line);
+    }
+  });
+}
+
+/// Reads every file dropped into `dir`, then deletes it. Files starting with
+/// '.' are skipped so writers can create them under a dot name and rename
+/// them into place once they're complete.
+pub fn scan_dir(dir: &Path, values: &Mutex<FeedValues>) -> Result<(), String> {
+  let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+  for entry in entries.flatten() {
+    let path = entry.path();
+    let hidden = entry.file_name().to_str().map(|n| n.starts_with('.')).unwrap_or(true);
+    if hidden || !path.is_file() { continue }
+    if let Ok(text) = fs::read_to_string(&path) {
+      let mut values = values.lock().unwrap();
+      for line in text.lines() { values.insert_line(line); }
+    }
+    if let Err(e) = fs::remove_file(&path) { println!("Feed: can't remove {}: {}", path.display(), e); }
+  }
+  Ok(())
+}
+
+/// Values pushed in by external scripts through a fifo or a drop directory.
+///
+///   [feed scripts]
+///   fifo = /tmp/raumEnSysInfo.fifo
+///   dir = /tmp/raumEnSysInfo.d
+///   keys = build,queue
+///   ttl = 300
+pub struct FeedWidget {
+  pub label: String,
+  pub values: Arc<Mutex<FeedValues>>,
+  pub dir: Option<PathBuf>,
+  /// Only these keys are shown, in this order, if any are given
+  pub keys: Vec<String>,
+  pub ttl: Option<Duration>,
+  pub interval: Duration,
+  pub placement: Placement,
+  next_scan: Instant,
+  /// The last drop directory error, so it's only printed when it changes
+  scan_err: Option<String>,
+}
+impl FeedWidget {
+  pub fn new(label: &str, placement: Placement) -> Self {
+    Self {
+      label: label.to_owned(),
+      values: Arc::new(Mutex::new(FeedValues::default())),
+      dir: None,
+      keys: Vec::new(),
+      ttl: None,
+      interval: Duration::from_secs(1),
+      placement,
+      next_scan: Instant::now(),
+      scan_err: None,
+    }
+  }
+  pub fn from_section(section: &Section) -> Self {
+    let mut out = Self::new(&section.name, Placement::from_section(section));
+    if let Some(fifo) = section.get("fifo") { watch_fifo(PathBuf::from(fifo), out.values.clone()); }
+    out.dir = section.get("dir").map(PathBuf::from);
+    if let Some(ref dir) = out.dir {
+      if let Err(e) = fs::create_dir_all(dir) { println!("Feed: can't create {}: {}", dir.display(), e); }
+    }
+    if let Some(keys) = section.get("keys") {
+      out.keys = keys.split(',').map(|k| k.trim().to_owned()).filter(|k| !k.is_empty()).collect();
+    }
+    let ttl: f32 = section.get_or("ttl", 0.0);
+    if ttl > 0.0 { out.ttl = Some(Duration::from_secs_f32(ttl)); }
+    out.interval = Duration::from_secs_f32(section.get_or("interval", 1.0_f32).max(0.1));
+    out
+  }
+  pub fn text(&self, values: &FeedValues) -> String {
+    let rows: Vec<String> = if self.keys.is_empty() {
+      values.values.iter().map(|(key, (val, _))| format!("{}: {}", key, val)).collect()
+    } else {
+      self.keys.iter().map(|key| match values.values.get(key) {
+        Some((val, _)) => format!("{}: {}", key, val),
+        None => format!("{}: -", key),
+      }).collect()
+    };
+    rows.join("\n")
+  }
+}
+impl Widget for FeedWidget {
+  fn label(&self) -> &str { &self.label }
+  fn placement(&self) -> &Placement { &self.placement }
+  fn poll(&mut self) -> Option<WidgetUpdate> {
+    if Instant::now() >= self.next_scan {
+      self.next_scan = Instant::now() + self.interval;
+      if let Some(ref dir) = self.dir {
+        match scan_dir(dir, &self.values) {
+          Ok(()) => self.scan_err = None,
+          Err(e) => {
+            if self.scan_err.as_ref() != Some(&e) { println!("Feed: {}", e); }
+            self.scan_err = Some(e);
+          }
+        }
+      }
+      if let Some(ttl) = self.ttl { self.values.lock().unwrap().expire(ttl); }
+    }
+    let mut values = self.values.lock().unwrap();
+    if !values.changed { return None }
+    values.changed = false;
+    Some(WidgetUpdate::single(self.text(&values), COLOUR_NORMAL))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::util::temp_dir,
+  };
+
+  fn pairs(line: &str) -> Vec<(String, String)> {
+    let mut out = parse_line(line);
+    out.sort();
+    out
+  }
+
+  fn owned(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+  }
+
+  #[test]
+  fn key_value_lines() {
+    assert_eq!(pairs("  build = passing \n"), owned(&[("build", "passing")]));
+    assert_eq!(pairs("url=http://x/?a=b"), owned(&[("url", "http://x/?a=b")]));
+    assert_eq!(pairs("empty="), owned(&[("empty", "")]));
+    for skipped in &["", "# build=no", "no equals", "= no key"] { assert!(pairs(skipped).is_empty(), "{}", skipped); }
+  }
+
+  #[test]
+  fn json_lines() {
+    assert_eq!(pairs(r#"{"queue": 3, "db": {"lag": 1.5, "up": true, "primary": {"host": "db1"}}, "gone": null}"#), owned(&[
+      ("db.lag", "1.5"), ("db.primary.host", "db1"), ("db.up", "true"), ("queue", "3"),
+    ]));
+    assert_eq!(pairs(r#"{"tags": ["a", "b"]}"#), owned(&[("tags", r#"["a","b"]"#)]));
+    assert!(pairs(r#"{"unterminated": 1"#).is_empty());
+    assert!(pairs("{not json}").is_empty());
+    // Only objects have names to give their values
+    let mut out = Vec::new();
+    flatten("", &serde_json::json!(["a", 1]), &mut out);
+    flatten("", &serde_json::json!("top"), &mut out);
+    assert!(out.is_empty());
+  }
+
+  #[test]
+  fn drop_dir_is_read_and_emptied() {
+    let dir = temp_dir("feed-scan");
+    fs::write(dir.join("ci"), "build=passing\n{\"queue\": 4}\n").unwrap();
+    fs::write(dir.join(".partial"), "build=half written\n").unwrap();
+    fs::write(dir.join("core"), [0xff, 0xfe, 0x00, 0x3d]).unwrap();
+    fs::write(dir.join("notes.txt"), "just some notes\nnothing to see\n").unwrap();
+    fs::create_dir(dir.join("sub")).unwrap();
+    let values = Mutex::new(FeedValues::default());
+    scan_dir(&dir, &values).unwrap();
+    let values = values.into_inner().unwrap();
+    let got: Vec<(&str, &str)> = values.values.iter().map(|(k, (v, _))| (k.as_str(), v.as_str())).collect();
+    assert_eq!(got, vec![("build", "passing"), ("queue", "4")]);
+    let mut left: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    left.sort();
+    assert_eq!(left, vec![".partial", "sub"]);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(scan_dir(&dir, &Mutex::new(FeedValues::default())).is_err());
+  }
+}
@@ -0,0 +1,305 @@
+
+use {
+  std::{
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc::{channel, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+  },
+  regex::Regex,
+  time::{OffsetDateTime, UtcOffset},
+  crate::{
+    config::Section,
+    http::{self, Url},
+    metrics::now_secs,
+    widget::{Placement, TextRow, Widget, WidgetUpdate, COLOUR_ERROR, COLOUR_NORMAL},
+  },
+};
+
+/// Keys of a `[health]` section that aren't checks
+const SETTINGS: [&str; 7] = ["interval", "timeout", "font", "size", "x", "y", "width"];
+
+#[derive(Debug, Clone)]
+pub enum Probe {
+  /// Up if a connection can be made
+  Tcp(String),
+  /// Up if the answer has one of `status` (any 2xx or 3xx if empty) and
+  /// its body matches `body`
+  Http { url: Url, status: Vec<u16>, body: Option<Regex> },
+}
+impl Probe {
+  /// `tcp://host:port`, `host:port`, or `http://... [status=200,204] [body=regex]`.
+  /// The body regex is the rest of the line, spaces and all.
+  pub fn parse(spec: &str) -> Result<Self, String> {
+    let spec = spec.trim();
+    let (target, rest) = spec.split_at(spec.find(char::is_whitespace).unwrap_or(spec.len()));
+    if target.is_empty() { return Err("nothing to check".to_owned()) }
+    // Only a word of its own after the target starts the body, the url
+    // itself can have `body=` in its query
+    let (options, body) = match rest.match_indices("body=").find(|(idx, _)| rest[..*idx].ends_with(char::is_whitespace)) {
+      Some((idx, _)) => (&rest[..idx], Some(rest[idx + 5..].trim())),
+      None => (rest, None),
+    };
+    let mut words = options.split_whitespace();
+    if target.starts_with("http://") || target.starts_with("https://") {
+      let mut status = Vec::new();
+      for word in words {
+        match word.strip_prefix("status=") {
+          Some(codes) => for code in codes.split(',') {
+            status.push(code.parse().map_err(|_| format!("bad status {}", code))?);
+          },
+          None => return Err(format!("unknown option {}", word)),
+        }
+      }
+      let body = match body {
+        Some(re) => Some(Regex::new(re).map_err(|e| e.to_string())?),
+        None => None,
+      };
+      return Ok(Probe::Http { url: Url::parse(target)?, status, body })
+    }
+    if body.is_some() || words.next().is_some() { return Err(format!("only http checks take options: {}", spec)) }
+    let addr = target.strip_prefix("tcp://").unwrap_or(target);
+    if !addr.contains(':') { return Err(format!("no port in {}", target)) }
+    Ok(Probe::Tcp(addr.to_owned()))
+  }
+  /// Whether it's up, with how long it took and what happened
+  pub fn run(&self, timeout: Duration) -> (bool, Duration, String) {
+    let started = Instant::now();
+    let (up, detail) = match self {
+      Probe::Tcp(addr) => match connect(addr, timeout) {
+        Ok(()) => (true, String::new()),
+        Err(e) => (false, e),
+      },
+      Probe::Http { url, status, body } => match http::request("GET", url, None, timeout) {
+        Ok(resp) => {
+          let status_ok = if status.is_empty() { (200..400).contains(&resp.status) } else { status.contains(&resp.status) };
+          if !status_ok {
+            (false, format!("status {}", resp.status))
+          } else if body.as_ref().map(|re| !re.is_match(&resp.body)).unwrap_or(false) {
+            (false, "body doesn't match".to_owned())
+          } else {
+            (true, format!("{}", resp.status))
+          }
+        }
+        Err(e) => (false, e.to_string()),
+      },
+    };
+    (up, started.elapsed(), detail)
+  }
+}
+
+fn connect(addr: &str, timeout: Duration) -> Result<(), String> {
+  let mut last_err = format!("{} didn't resolve", addr);
+  for sock in addr.to_socket_addrs().map_err(|e| e.to_string())? {
+    match TcpStream::connect_timeout(&sock, timeout) {
+      Ok(_) => return Ok(()),
+      Err(e) => last_err = e.to_string(),
+    }
+  }
+  Err(last_err)
+}
+
+pub struct Check {
+  pub name: String,
+  pub probe: Probe,
+  /// None until the first result is in
+  pub up: Option<bool>,
+  pub latency: Duration,
+  pub detail: String,
+  /// When it last went up or down, in unix seconds
+  pub since: f64,
+}
+
+type Results = Vec<(bool, Duration, String)>;
+
+/// Whether local services are answering. Every key other than the settings
+/// is a check, shown one per row in name order.
+///
+///   [health services]
+///   web = http://127.0.0.1:8080/health status=200 body="status": ?"ok"
+///   db = tcp://127.0.0.1:5432
+///   interval = 10
+///   timeout = 3
+pub struct HealthWidget {
+  pub label: String,
+  pub checks: Vec<Check>,
+  pub interval: Duration,
+  pub timeout: Duration,
+  pub placement: Placement,
+  errors: Vec<String>,
+  /// Whether the names and config errors have been put up yet
+  shown: bool,
+  next_run: Instant,
+  running: Option<Receiver<Results>>,
+}
+impl HealthWidget {
+  pub fn from_section(section: &Section) -> Self {
+    let mut names: Vec<&String> = section.vals.keys().filter(|k| !SETTINGS.contains(&k.as_str())).collect();
+    names.sort();
+    let mut checks = Vec::new();
+    let mut errors = Vec::new();
+    for name in names {
+      match Probe::parse(&section.vals[name]) {
+        Ok(probe) => checks.push(Check {
+          name: name.clone(), probe, up: None, latency: Duration::default(), detail: String::new(), since: 0.0,
+        }),
+        Err(e) => errors.push(format!("{}: {}", name, e)),
+      }
+    }
+    if checks.is_empty() && errors.is_empty() { errors.push("no checks".to_owned()) }
+    for e in &errors { println!("Config: [health {}] {}", section.name, e); }
+    Self {
+      label: section.name.clone(),
+      checks,
+      interval: Duration::from_secs_f32(section.get_or("interval", 10.0_f32).max(0.5)),
+      timeout: Duration::from_secs_f32(section.get_or("timeout", 3.0_f32).max(0.1)),
+      placement: Placement::from_section(section),
+      errors,
+      shown: false,
+      next_run: Instant::now(),
+      running: None,
+    }
+  }
+  fn start(&mut self) {
+    let (tx, rx) = channel();
+    let probes: Vec<Probe> = self.checks.iter().map(|c| c.probe.clone()).collect();
+    let timeout = self.timeout;
+    thread::spawn(move || {
+      // All at once, so one slow service doesn't hold up the rest
+      let handles: Vec<_> = probes.into_iter().map(|p| thread::spawn(move || p.run(timeout))).collect();
+      let results: Results = handles.into_iter()
+        .map(|h| h.join().unwrap_or((false, Duration::default(), "check panicked".to_owned())))
+        .collect();
+      let _ = tx.send(results);
+    });
+    self.running = Some(rx);
+  }
+  fn record(&mut self, results: Results) {
+    let now = now_secs();
+    for (check, (up, latency, detail)) in self.checks.iter_mut().zip(results) {
+      if check.up != Some(up) {
+        if check.up.is_some() {
+          println!("Health {}/{}: {}{}", self.label, check.name, if up { "up" } else { "down" },
+                   if up { String::new() } else { format!(" ({})", detail) });
+        }
+        check.up = Some(up);
+        check.since = now;
+      }
+      check.latency = latency;
+      check.detail = detail;
+    }
+  }
+  fn update(&self) -> WidgetUpdate {
+    let mut rows = Vec::new();
+    for e in &self.errors {
+      rows.push(TextRow { text: format!("{}: {}", self.label, e), colour: COLOUR_ERROR });
+    }
+    for check in &self.checks {
+      let (text, colour) = match check.up {
+        None => (format!("{}: ...", check.name), COLOUR_NORMAL),
+        Some(true) => (format!("{}: UP {} ms, since {}", check.name, check.latency.as_millis(), fmt_clock(check.since)), COLOUR_NORMAL),
+        Some(false) => (format!("{}: DOWN ({}), since {}", check.name, check.detail, fmt_clock(check.since)), COLOUR_ERROR),
+      };
+      rows.push(TextRow { text, colour });
+    }
+    WidgetUpdate { rows }
+  }
+}
+impl Widget for HealthWidget {
+  fn label(&self) -> &str { &self.label }
+  fn placement(&self) -> &Placement { &self.placement }
+  fn rows(&self) -> usize { (self.errors.len() + self.checks.len()).max(1) }
+  fn poll(&mut self) -> Option<WidgetUpdate> {
+    if let Some(rx) = self.running.take() {
+      return match rx.try_recv() {
+        Ok(results) => { self.record(results); Some(self.update()) }
+        Err(TryRecvError::Empty) => { self.running = Some(rx); None }
+        Err(TryRecvError::Disconnected) => None,
+      }
+    }
+    // Show the config errors and the names being checked straight away
+    let first = if self.shown { None } else { self.shown = true; Some(self.update()) };
+    if self.checks.is_empty() || Instant::now() < self.next_run { return first }
+    self.next_run = Instant::now() + self.interval;
+    self.start();
+    first
+  }
+}
+
+/// Time of day in local time, or UTC if the offset can't be found
+fn fmt_clock(secs: f64) -> String {
+  let offset = UtcOffset::try_current_local_offset().unwrap_or(UtcOffset::UTC);
+  OffsetDateTime::from_unix_timestamp(secs as i64).to_offset(offset).format("%H:%M:%S")
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    std::net::TcpListener,
+    crate::http::tests::{answer, serve},
+  };
+
+  fn http(spec: &str) -> (Url, Vec<u16>, Option<String>) {
+    match Probe::parse(spec).unwrap() {
+      Probe::Http { url, status, body } => (url, status, body.map(|re| re.as_str().to_owned())),
+      probe => panic!("not http: {:?}", probe),
+    }
+  }
+
+  #[test]
+  fn parses_specs() {
+    let (url, status, body) = http("http://127.0.0.1:8080/health status=200,204 body=\"status\": ?\"ok\"");
+    assert_eq!((url.port, url.path.as_str()), (8080, "/health"));
+    assert_eq!(status, vec![200, 204]);
+    assert_eq!(body.as_deref(), Some("\"status\": ?\"ok\""));
+    // `body=` in the url's query is part of the url
+    let (url, status, body) = http("  http://127.0.0.1/check?body=full&x=1   body=ok  ");
+    assert_eq!((url.path.as_str(), status.len(), body.as_deref()), ("/check?body=full&x=1", 0, Some("ok")));
+    assert_eq!(http("http://127.0.0.1/?body=x").0.path, "/?body=x");
+    assert!(matches!(Probe::parse("tcp://db:5432"), Ok(Probe::Tcp(ref addr)) if addr == "db:5432"));
+    assert!(matches!(Probe::parse("db:5432"), Ok(Probe::Tcp(ref addr)) if addr == "db:5432"));
+  }
+
+  #[test]
+  fn bad_specs() {
+    assert_eq!(Probe::parse("   ").unwrap_err(), "nothing to check");
+    assert_eq!(Probe::parse("db").unwrap_err(), "no port in db");
+    assert_eq!(Probe::parse("db:5432 body=x").unwrap_err(), "only http checks take options: db:5432 body=x");
+    assert_eq!(Probe::parse("http://h/ status=2xx").unwrap_err(), "bad status 2xx");
+    assert_eq!(Probe::parse("http://h/ retries=3").unwrap_err(), "unknown option retries=3");
+    assert!(Probe::parse("http://h/ body=(").is_err());
+  }
+
+  #[test]
+  fn http_probes() {
+    let timeout = Duration::from_secs(5);
+    let (url, requests) = serve(vec![
+      answer("200 OK", "{\"status\": \"ok\"}"),
+      answer("200 OK", "{\"status\": \"degraded\"}"),
+      answer("503 Service Unavailable", ""),
+      answer("302 Found", ""),
+    ]);
+    let probe = |options: &str| Probe::parse(&format!("http://127.0.0.1:{}/health?body=1 {}", url.port, options)).unwrap();
+    let (up, _, detail) = probe("status=200 body=\"status\": ?\"ok\"").run(timeout);
+    assert_eq!((up, detail.as_str()), (true, "200"));
+    assert!(requests.recv().unwrap().starts_with("GET /health?body=1 HTTP/1.0"));
+    let (up, _, detail) = probe("body=\"ok\"").run(timeout);
+    assert_eq!((up, detail.as_str()), (false, "body doesn't match"));
+    let (up, _, detail) = probe("").run(timeout);
+    assert_eq!((up, detail.as_str()), (false, "status 503"));
+    assert!(probe("").run(timeout).0);
+  }
+
+  #[test]
+  fn tcp_probes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let probe = Probe::parse(&format!("tcp://{}", addr)).unwrap();
+    assert!(probe.run(Duration::from_secs(5)).0);
+    drop(listener);
+    let (up, _, detail) = probe.run(Duration::from_secs(5));
+    assert!(!up);
+    assert!(!detail.is_empty());
+  }
+}
@@ -0,0 +1,195 @@
+pub mod command;
+pub mod feed;
+pub mod health;
+pub mod scrape;
+pub mod tail;
+
+pub use {
+  crate::{
+    widget::{
+      command::CommandWidget,
+      feed::FeedWidget,
+      health::HealthWidget,
+      scrape::ScrapeWidget,
+      tail::TailWidget,
+    },
+  },
+};
+
+use {
+  crate::{
+    alert::Alerts,
+    config::{Config, Section},
+    gamemgr::GameMgr,
+    remote::RemoteWidget,
+    text::{TextMgr, LINE_HEIGHT},
+    util::{HashMap, HashSet},
+  },
+};
+
+pub const COLOUR_NORMAL: (f32, f32, f32) = (0.0, 0.0, 0.0);
+pub const COLOUR_ERROR: (f32, f32, f32) = (0.9, 0.1, 0.1);
+pub const COLOUR_WARN: (f32, f32, f32) = (0.9, 0.5, 0.0);
+
+/// Where a widget's label goes, in the same units `TextMgr::new_text` takes
+#[derive(Debug, Clone)]
+pub struct Placement {
+  pub font: String,
+  pub font_size: f32,
+  pub x: f32,
+  pub y: f32,
+  pub line_max_size: f32,
+}
+impl Placement {
+  pub fn from_section(section: &Section) -> Self {
+    Self {
+      font: section.get_str("font", "sans"),
+      font_size: section.get_or("size", 1.0),
+      x: section.get_or("x", 0.02),
+      y: section.get_or("y", 0.5),
+      line_max_size: section.get_or("width", 0.96),
+    }
+  }
+}
+
+pub struct TextRow {
+  pub text: String,
+  pub colour: (f32, f32, f32),
+}
+
+/// New contents for each of a widget's rows. Rows left out are hidden, and
+/// a row given empty text shows `-`.
+pub struct WidgetUpdate {
+  pub rows: Vec<TextRow>,
+}
+impl WidgetUpdate {
+  pub fn single(text: String, colour: (f32, f32, f32)) -> Self {
+    Self { rows: vec![TextRow { text, colour }] }
+  }
+}
+
+pub trait Widget {
+  fn label(&self) -> &str;
+  fn placement(&self) -> &Placement;
+  /// Widgets that need a colour per line get a label per line, stacked
+  /// one line height apart.
+  fn rows(&self) -> usize { 1 }
+  /// Text shown before the first update arrives
+  fn initial_text(&self) -> String { format!("{}: ...", self.label()) }
+  /// Called every frame. Should be cheap and never block; returns the new
+  /// contents of the label if they've changed.
+  fn poll(&mut self) -> Option<WidgetUpdate>;
+}
+
+/// All the widgets set up in the config file
+#[derive(Default)]
+pub struct Widgets {
+  pub widgets: Vec<Box<dyn Widget>>,
+  /// Row labels that currently have something to show
+  shown: HashSet<String>,
+  /// The colour each row was last given, for when an alert lets go of it
+  colours: HashMap<String, (f32, f32, f32)>,
+  hidden: bool,
+}
+impl Widgets {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn from_config(config: &Config) -> Self {
+    let mut out = Self::new();
+    for section in config.sections("command") {
+      out.widgets.push(Box::new(CommandWidget::from_section(section)));
+    }
+    for section in config.sections("feed") {
+      out.widgets.push(Box::new(FeedWidget::from_section(section)));
+    }
+    for section in config.sections("health") {
+      out.widgets.push(Box::new(HealthWidget::from_section(section)));
+    }
+    for section in config.sections("remote") {
+      out.widgets.push(Box::new(RemoteWidget::from_section(section)));
+    }
+    for section in config.sections("scrape") {
+      out.widgets.push(Box::new(ScrapeWidget::from_section(section)));
+    }
+    for section in config.sections("tail") {
+      out.widgets.push(Box::new(TailWidget::from_section(section)));
+    }
+    out
+  }
+  /// Every label owned by a widget
+  pub fn labels(&self) -> Vec<String> {
+    let mut out = Vec::new();
+    for w in &self.widgets {
+      for row in 0..w.rows() { out.push(row_label(w.label(), row)); }
+    }
+    out
+  }
+  pub fn add_labels(&mut self, textmgr: &mut TextMgr, mgr: GameMgr) {
+    for w in &self.widgets {
+      let p = w.placement();
+      for row in 0..w.rows() {
+        let (text, enable) = if row == 0 { (w.initial_text(), true) } else { ("-".to_owned(), false) };
+        let y = p.y + row as f32 * LINE_HEIGHT * p.font_size;
+        textmgr.new_text(mgr.clone(), &row_label(w.label(), row), &text, &p.font,
+                         p.font_size, p.x, y, p.line_max_size, false, enable);
+      }
+      self.shown.insert(w.label().to_owned());
+    }
+  }
+  /// Hides every widget, e.g. while another page is up. Updates keep coming
+  /// in and show up once the widgets are unhidden.
+  pub fn set_hidden(&mut self, hidden: bool, textmgr: &mut TextMgr, mgr: GameMgr) {
+    self.hidden = hidden;
+    for label in &self.shown {
+      if hidden { textmgr.disable_label(label) } else { textmgr.enable_label(mgr.clone(), label) }
+    }
+  }
+  /// Puts the widgets' own colours back on labels an alert was colouring
+  pub fn restore_colours(&self, textmgr: &mut TextMgr, labels: &[String]) {
+    for label in labels {
+      if let (Some((r, g, b)), Some(gtext)) = (self.colours.get(label), textmgr.texts.get_mut(label)) {
+        gtext.set_colour(*r, *g, *b);
+      }
+    }
+  }
+  /// Rows an alert is colouring keep the alert's colour until it clears
+  pub fn update(&mut self, mgr: GameMgr, alerts: &Alerts) {
+    let mut updates = Vec::new();
+    for w in &mut self.widgets {
+      if let Some(update) = w.poll() { updates.push((w.label().to_owned(), w.rows(), update)); }
+    }
+    if updates.is_empty() { return }
+    let _textmgr = mgr.clone().textmgr.take().unwrap();
+    let mut textmgr = _textmgr.lock().unwrap();
+    for (label, rows, update) in updates {
+      for row in 0..rows {
+        let label = row_label(&label, row);
+        match update.rows.get(row) {
+          Some(r) => {
+            // An empty string has no vertices to load, so never hand one over
+            let text = if r.text.trim().is_empty() { "-" } else { &r.text };
+            textmgr.update_text(mgr.clone(), &label, text);
+            self.colours.insert(label.clone(), r.colour);
+            if let Some(gtext) = textmgr.texts.get_mut(&label) {
+              let (red, green, blue) = alerts.colour(&label).unwrap_or(r.colour);
+              gtext.set_colour(red, green, blue);
+            }
+            if !self.hidden { textmgr.enable_label(mgr.clone(), &label); }
+            self.shown.insert(label);
+          }
+          None => {
+            textmgr.disable_label(&label);
+            self.shown.remove(&label);
+          }
+        }
+      }
+    }
+  }
+}
+
+/// The first row keeps the widget's own name so single row widgets don't
+/// get renamed.
+pub fn row_label(label: &str, row: usize) -> String {
+  if row == 0 { label.to_owned() } else { format!("{}#{}", label, row) }
+}
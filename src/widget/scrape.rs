@@ -0,0 +1,358 @@
+
+use {
+  std::{
+    sync::mpsc::{channel, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+  },
+  regex::Regex,
+  crate::{
+    config::Section,
+    export::prometheus::{parse_exposition, parse_quoted, Scraped},
+    http::{self, Url},
+    util::HashMap,
+    widget::{Placement, TextRow, Widget, WidgetUpdate, COLOUR_ERROR, COLOUR_NORMAL, COLOUR_WARN},
+  },
+};
+
+#[derive(Debug, Clone)]
+pub enum MatchOp {
+  Eq(String),
+  Ne(String),
+  Re(Regex),
+  NotRe(Regex),
+}
+
+/// Picks out series by name and labels, as in PromQL:
+/// `http_requests_total{code=~"2..",method!="OPTIONS"}`
+#[derive(Debug, Clone)]
+pub struct Selector {
+  pub name: String,
+  pub matchers: Vec<(String, MatchOp)>,
+}
+impl Selector {
+  pub fn parse(s: &str) -> Result<Self, String> {
+    let s = s.trim();
+    let (name, mut rest) = match s.find('{') {
+      Some(idx) => (&s[..idx], &s[idx + 1..]),
+      None => (s, ""),
+    };
+    let mut matchers = Vec::new();
+    if s.contains('{') {
+      loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if let Some(after) = rest.strip_prefix('}') {
+          if !after.trim().is_empty() { return Err(format!("junk after {}", s)) }
+          break
+        }
+        let op_at = rest.find(&['=', '!'][..]).ok_or_else(|| format!("bad matcher in {}", s))?;
+        let label = rest[..op_at].trim().to_owned();
+        let ops = ["=~", "!~", "!=", "="];
+        let op = ops.iter().find(|op| rest[op_at..].starts_with(*op)).ok_or_else(|| format!("bad matcher in {}", s))?;
+        let (val, after) = parse_quoted(rest[op_at + op.len()..].trim_start())
+          .ok_or_else(|| format!("unquoted label value in {}", s))?;
+        // Regexes match the whole value, like Prometheus does
+        let re = || Regex::new(&format!("^(?:{})$", val)).map_err(|e| e.to_string());
+        let op = match *op {
+          "=" => MatchOp::Eq(val),
+          "!=" => MatchOp::Ne(val),
+          "=~" => MatchOp::Re(re()?),
+          _ => MatchOp::NotRe(re()?),
+        };
+        matchers.push((label, op));
+        rest = after;
+      }
+    }
+    let name = name.trim();
+    if name.is_empty() { return Err(format!("no metric name in {}", s)) }
+    Ok(Self { name: name.to_owned(), matchers })
+  }
+  pub fn matches(&self, series: &Scraped) -> bool {
+    if series.name != self.name { return false }
+    self.matchers.iter().all(|(label, op)| {
+      let val = series.labels.iter().find(|(k, _)| k == label).map(|(_, v)| v.as_str()).unwrap_or("");
+      match op {
+        MatchOp::Eq(want) => val == want,
+        MatchOp::Ne(want) => val != want,
+        MatchOp::Re(re) => re.is_match(val),
+        MatchOp::NotRe(re) => !re.is_match(val),
+      }
+    })
+  }
+}
+
+/// One thing to show: `[sum(][rate(]selector[)][)] [as <title>]`
+#[derive(Debug, Clone)]
+pub struct Item {
+  /// Defaults to the item as written
+  pub title: String,
+  titled: bool,
+  pub selector: Selector,
+  pub rate: bool,
+  pub sum: bool,
+}
+impl Item {
+  pub fn parse(s: &str) -> Result<Self, String> {
+    let (expr, title) = match s.rfind(" as ") {
+      Some(idx) => (s[..idx].trim(), Some(s[idx + 4..].trim())),
+      None => (s.trim(), None),
+    };
+    let (sum, inner) = match unwrap_call(expr, "sum") { Some(inner) => (true, inner), None => (false, expr) };
+    let (rate, inner) = match unwrap_call(inner, "rate") { Some(inner) => (true, inner), None => (false, inner) };
+    Ok(Self {
+      title: title.unwrap_or(expr).to_owned(),
+      titled: title.is_some(),
+      selector: Selector::parse(inner)?,
+      rate,
+      sum,
+    })
+  }
+}
+
+/// The inside of `func(...)`
+fn unwrap_call<'a>(s: &'a str, func: &str) -> Option<&'a str> {
+  s.strip_prefix(func)?.trim_start().strip_prefix('(')?.strip_suffix(')').map(|s| s.trim())
+}
+
+type ScrapeResult = Result<Vec<Scraped>, String>;
+
+/// Series from another program's `/metrics` page, fetched every `interval`.
+/// `series` is a `;` separated list, each one a PromQL style selector that
+/// can be wrapped in `rate()` and `sum()`, with an optional title.
+///
+///   [scrape node]
+///   url = http://127.0.0.1:9100/metrics
+///   series = node_load1 as Load; sum(rate(http_requests_total{code=~"2.."})) as Requests/s
+///   interval = 15
+///   timeout = 5
+///   lines = 6
+pub struct ScrapeWidget {
+  pub label: String,
+  pub url: Option<Url>,
+  pub items: Vec<Item>,
+  pub interval: Duration,
+  pub timeout: Duration,
+  pub max_lines: usize,
+  pub placement: Placement,
+  /// Stops the widget, for a bad url or no usable series
+  error: Option<String>,
+  /// Items that didn't parse, each shown on its own row under the rest
+  bad_items: Vec<String>,
+  next_run: Instant,
+  running: Option<Receiver<ScrapeResult>>,
+  /// Counter values from the last scrape, for working out rates
+  last: HashMap<String, (Instant, f64)>,
+}
+impl ScrapeWidget {
+  pub fn from_section(section: &Section) -> Self {
+    let mut errors = Vec::new();
+    let url = match Url::parse(&section.get_str("url", "")) {
+      Ok(url) => Some(url),
+      Err(e) => { errors.push(e); None }
+    };
+    let mut items = Vec::new();
+    let mut bad_items = Vec::new();
+    for item in section.get_str("series", "").split(';').filter(|i| !i.trim().is_empty()) {
+      match Item::parse(item) {
+        Ok(item) => items.push(item),
+        Err(e) => bad_items.push(e),
+      }
+    }
+    if items.is_empty() {
+      errors.append(&mut bad_items);
+      errors.push("no series".to_owned());
+    }
+    for e in errors.iter().chain(bad_items.iter()) { println!("Config: [scrape {}] {}", section.name, e); }
+    Self {
+      label: section.name.clone(),
+      url,
+      interval: Duration::from_secs_f32(section.get_or("interval", 15.0_f32).max(0.1)),
+      timeout: Duration::from_secs_f32(section.get_or("timeout", 5.0_f32).max(0.1)),
+      max_lines: section.get_or("lines", items.len().max(1) + bad_items.len() + 4),
+      items,
+      placement: Placement::from_section(section),
+      error: if errors.is_empty() { None } else { Some(errors.join("; ")) },
+      bad_items,
+      next_run: Instant::now(),
+      running: None,
+      last: HashMap::new(),
+    }
+  }
+  fn start(&mut self, url: Url) {
+    let (tx, rx) = channel();
+    let timeout = self.timeout;
+    thread::spawn(move || {
+      let result = match http::request("GET", &url, None, timeout) {
+        Ok(resp) if resp.status == 200 => Ok(parse_exposition(&resp.body)),
+        Ok(resp) => Err(resp.status_line),
+        Err(e) => Err(e.to_string()),
+      };
+      let _ = tx.send(result);
+    });
+    self.running = Some(rx);
+  }
+  fn rows(&mut self, series: &[Scraped], now: Instant) -> Vec<TextRow> {
+    // Each counter's rate is worked out once, however many items select it
+    let mut rates = HashMap::new();
+    let items = &self.items;
+    for s in series.iter().filter(|s| items.iter().any(|i| i.rate && i.selector.matches(s))) {
+      let key = series_key(s);
+      let rate = match self.last.insert(key.clone(), (now, s.value)) {
+        Some((then, prev)) if now > then => {
+          let dt = now.duration_since(then).as_secs_f64();
+          // Counters that went backwards were reset
+          Some(if s.value >= prev { (s.value - prev) / dt } else { s.value / dt })
+        }
+        _ => None,
+      };
+      rates.insert(key, rate);
+    }
+    let mut rows = Vec::new();
+    for item in &self.items {
+      let values: Vec<(&Scraped, Option<f64>)> = series.iter()
+        .filter(|s| item.selector.matches(s))
+        .map(|s| (s, if item.rate { rates[&series_key(s)] } else { Some(s.value) }))
+        .collect();
+      let row = |text: String, colour| TextRow { text, colour };
+      if values.is_empty() {
+        rows.push(row(format!("{}: no data", item.title), COLOUR_WARN));
+      } else if item.sum || values.len() == 1 {
+        let total: Option<f64> = values.iter().map(|(_, v)| *v).sum();
+        rows.push(row(format!("{}: {}", item.title, fmt_scraped(&item.selector.name, item.rate, total)), COLOUR_NORMAL));
+      } else {
+        for (s, value) in values {
+          // One row per series, told apart by their labels
+          let labels: Vec<String> = s.labels.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+          let title = if item.titled { &item.title } else { &s.name };
+          let text = format!("{}{{{}}}: {}", title, labels.join(","), fmt_scraped(&s.name, item.rate, value));
+          rows.push(row(text, COLOUR_NORMAL));
+        }
+      }
+    }
+    // Series that went away would otherwise be remembered forever
+    self.last.retain(|key, _| rates.contains_key(key));
+    rows.extend(self.bad_rows());
+    rows.truncate(self.max_lines);
+    rows
+  }
+  fn bad_rows(&self) -> Vec<TextRow> {
+    self.bad_items.iter().map(|e| TextRow { text: e.clone(), colour: COLOUR_ERROR }).collect()
+  }
+}
+impl Widget for ScrapeWidget {
+  fn label(&self) -> &str { &self.label }
+  fn placement(&self) -> &Placement { &self.placement }
+  fn rows(&self) -> usize { self.max_lines }
+  fn poll(&mut self) -> Option<WidgetUpdate> {
+    if let Some(ref e) = self.error {
+      let update = WidgetUpdate::single(format!("{}: {}", self.label, e), COLOUR_ERROR);
+      self.error = None;
+      self.url = None;
+      return Some(update)
+    }
+    if let Some(rx) = self.running.take() {
+      return match rx.try_recv() {
+        Ok(Ok(series)) => Some(WidgetUpdate { rows: self.rows(&series, Instant::now()) }),
+        Ok(Err(e)) => {
+          let mut update = WidgetUpdate::single(format!("{}: {}", self.label, e), COLOUR_ERROR);
+          update.rows.extend(self.bad_rows());
+          Some(update)
+        }
+        Err(TryRecvError::Empty) => { self.running = Some(rx); None }
+        Err(TryRecvError::Disconnected) => None,
+      }
+    }
+    let url = match self.url { Some(ref url) => url.clone(), None => return None };
+    if Instant::now() < self.next_run { return None }
+    self.next_run = Instant::now() + self.interval;
+    self.start(url);
+    None
+  }
+}
+
+fn series_key(s: &Scraped) -> String {
+  let labels: Vec<String> = s.labels.iter().map(|(k, v)| format!("{}={:?}", k, v)).collect();
+  format!("{}{{{}}}", s.name, labels.join(","))
+}
+
+/// Sizes from `_bytes` series in KiB/MiB/GiB, other numbers rounded to suit
+/// their size. A rate still waiting on its second scrape shows as `...`.
+pub fn fmt_scraped(name: &str, rate: bool, value: Option<f64>) -> String {
+  let value = match value { Some(v) => v, None => return "...".to_owned() };
+  let per = if rate { "/s" } else { "" };
+  let bytes = name.ends_with("_bytes") || name.ends_with("_bytes_total");
+  if bytes && value.is_finite() && value >= 0.0 {
+    return format!("{}{}", bytesize::ByteSize::b(value as u64).to_string_as(true), per)
+  }
+  if value.fract() == 0.0 || value.abs() >= 100.0 { format!("{:.0}{}", value, per) }
+  else { format!("{:.2}{}", value, per) }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    crate::{config::Config, http::tests::{answer, serve}},
+  };
+
+  fn widget(url: &str, series: &str) -> ScrapeWidget {
+    let config = Config::parse(&format!("[scrape node]\nurl = {}\nseries = {}\n", url, series));
+    ScrapeWidget::from_section(config.sections("scrape")[0])
+  }
+
+  fn texts(rows: &[TextRow]) -> Vec<&str> {
+    rows.iter().map(|r| r.text.as_str()).collect()
+  }
+
+  fn scraped(name: &str, label: &str, value: f64) -> Scraped {
+    Scraped { name: name.to_owned(), labels: vec![("code".to_owned(), label.to_owned())], value }
+  }
+
+  #[test]
+  fn bad_items_keep_their_own_row() {
+    let (url, requests) = serve(vec![answer("200 OK", "node_load1 0.5\nreqs_total{code=\"200\"} 10\n")]);
+    let url = format!("http://127.0.0.1:{}/metrics", url.port);
+    let mut widget = widget(&url, "node_load1 as Load; reqs_total{code=200}; rate(reqs_total) as Requests");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let update = loop {
+      if let Some(update) = widget.poll() { break update }
+      assert!(Instant::now() < deadline, "no scrape");
+      thread::sleep(Duration::from_millis(10));
+    };
+    assert!(requests.recv().unwrap().starts_with("GET /metrics HTTP/1.0"));
+    assert_eq!(texts(&update.rows), vec!["Load: 0.50", "Requests: ...", "unquoted label value in reqs_total{code=200}"]);
+    assert_eq!(update.rows[2].colour, COLOUR_ERROR);
+    assert!(widget.url.is_some());
+  }
+
+  #[test]
+  fn nothing_usable_stops_the_widget() {
+    let mut widget = widget("http://127.0.0.1:1/", "{code=\"200\"}");
+    let update = widget.poll().unwrap();
+    assert_eq!(texts(&update.rows), vec!["node: no metric name in {code=\"200\"}; no series"]);
+    assert!(widget.url.is_none());
+    assert!(widget.poll().is_none());
+  }
+
+  #[test]
+  fn rates_forget_series_that_went_away() {
+    let mut widget = widget("http://127.0.0.1:1/", "rate(reqs_total) as Requests");
+    let start = Instant::now();
+    ScrapeWidget::rows(&mut widget, &[scraped("reqs_total", "200", 10.0), scraped("reqs_total", "500", 1.0)], start);
+    assert_eq!(widget.last.len(), 2);
+    let rows = ScrapeWidget::rows(&mut widget, &[scraped("reqs_total", "200", 30.0)], start + Duration::from_secs(10));
+    assert_eq!(texts(&rows), vec!["Requests: 2/s"]);
+    assert_eq!(widget.last.keys().collect::<Vec<_>>(), vec!["reqs_total{code=\"200\"}"]);
+  }
+
+  #[test]
+  fn overlapping_rates_share_a_counter() {
+    let mut widget = widget("http://127.0.0.1:1/", "rate(reqs_total) as All; rate(reqs_total{code=\"200\"}) as OK");
+    let start = Instant::now();
+    let rows = ScrapeWidget::rows(&mut widget, &[scraped("reqs_total", "200", 10.0), scraped("reqs_total", "500", 1.0)], start);
+    assert_eq!(texts(&rows), vec!["All{code=200}: ...", "All{code=500}: ...", "OK: ..."]);
+    let later = [scraped("reqs_total", "200", 30.0), scraped("reqs_total", "500", 11.0)];
+    let rows = ScrapeWidget::rows(&mut widget, &later, start + Duration::from_secs(10));
+    assert_eq!(texts(&rows), vec!["All{code=200}: 2/s", "All{code=500}: 1/s", "OK: 2/s"]);
+  }
+}
@@ -0,0 +1,272 @@
+
+use {
+  std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+    time::{Duration, Instant},
+  },
+  regex::Regex,
+  crate::{
+    config::Section,
+    widget::{Placement, TextRow, Widget, WidgetUpdate, COLOUR_ERROR, COLOUR_NORMAL, COLOUR_WARN},
+  },
+};
+
+// How far back from the end to look for the first lines when a file is opened
+const BACKLOG_BYTES: u64 = 64 * 1024;
+
+/// Follows a file like `tail -F`: picks up where it left off, starts over
+/// when the file is truncated, and reopens it when it's replaced by
+/// rotation.
+pub struct Tail {
+  pub path: PathBuf,
+  pub max_lines: usize,
+  pub filter: Option<Regex>,
+  pub lines: VecDeque<String>,
+  file: Option<File>,
+  inode: u64,
+  pos: u64,
+  partial: String,
+  /// Opened part way into the file, so the first line read is cut off
+  started_mid_file: bool,
+}
+impl Tail {
+  pub fn new(path: &str, max_lines: usize) -> Self {
+    Self {
+      path: PathBuf::from(path),
+      max_lines,
+      filter: None,
+      lines: VecDeque::new(),
+      file: None,
+      inode: 0,
+      pos: 0,
+      partial: String::new(),
+      started_mid_file: false,
+    }
+  }
+  /// Reads whatever was appended since the last call. Returns true if any
+  /// new lines were kept.
+  pub fn poll(&mut self) -> Result<bool, String> {
+    let meta = fs::metadata(&self.path).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+    let mut added = false;
+    if self.file.is_some() && meta.ino() != self.inode {
+      // Rotated out from under us. Whatever was written to the old file
+      // before it was moved hasn't been seen yet, and its last line is done.
+      let mut old = self.file.take().unwrap();
+      let mut buf = Vec::new();
+      if old.seek(SeekFrom::Start(self.pos)).and_then(|_| old.read_to_end(&mut buf)).is_ok() {
+        added |= self.keep(&buf);
+      }
+      let last = std::mem::take(&mut self.partial);
+      added |= self.keep_line(&last);
+    }
+    if self.file.is_none() {
+      // The first open starts near the end. A rotated file is read from the
+      // start, everything in it is new.
+      let first = self.inode == 0;
+      let file = File::open(&self.path).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+      self.file = Some(file);
+      self.inode = meta.ino();
+      self.partial.clear();
+      self.pos = if first { meta.len().saturating_sub(BACKLOG_BYTES) } else { 0 };
+      self.started_mid_file = self.pos > 0;
+    } else if meta.len() < self.pos {
+      // Truncated in place
+      self.pos = 0;
+      self.partial.clear();
+    }
+    if meta.len() == self.pos { return Ok(added) }
+    let file = self.file.as_mut().unwrap();
+    let mut buf = Vec::new();
+    file.seek(SeekFrom::Start(self.pos)).map_err(|e| e.to_string())?;
+    file.read_to_end(&mut buf).map_err(|e| e.to_string())?;
+    self.pos += buf.len() as u64;
+    added |= self.keep(&buf);
+    Ok(added)
+  }
+  /// Adds the complete lines in `buf`, holding on to any unfinished one
+  fn keep(&mut self, buf: &[u8]) -> bool {
+    let mut text = std::mem::take(&mut self.partial);
+    text.push_str(&String::from_utf8_lossy(buf));
+    let mut parts: Vec<&str> = text.split('\n').collect();
+    // Anything after the last newline is a line that's still being written
+    self.partial = parts.pop().unwrap_or("").to_owned();
+    // Starting part way into the file means the first line is cut off
+    if self.started_mid_file && !parts.is_empty() {
+      parts.remove(0);
+      self.started_mid_file = false;
+    }
+    let mut added = false;
+    for line in parts { added |= self.keep_line(line); }
+    added
+  }
+  fn keep_line(&mut self, line: &str) -> bool {
+    let line = line.trim_end_matches('\r');
+    if line.is_empty() { return false }
+    if let Some(ref filter) = self.filter {
+      if !filter.is_match(line) { return false }
+    }
+    self.lines.push_back(line.to_owned());
+    while self.lines.len() > self.max_lines { self.lines.pop_front(); }
+    true
+  }
+}
+
+/// The last few lines of a file, coloured by severity.
+///
+///   [tail syslog]
+///   path = /var/log/syslog
+///   lines = 8
+///   filter = sshd|kernel
+pub struct TailWidget {
+  pub label: String,
+  pub tail: Tail,
+  pub interval: Duration,
+  pub max_chars: usize,
+  pub error_words: Vec<String>,
+  pub warn_words: Vec<String>,
+  pub placement: Placement,
+  next_poll: Instant,
+  last_err: Option<String>,
+}
+impl TailWidget {
+  pub fn new(label: &str, tail: Tail, placement: Placement) -> Self {
+    Self {
+      label: label.to_owned(),
+      tail,
+      interval: Duration::from_secs(1),
+      max_chars: 120,
+      error_words: words("error,err,fail,failed,fatal,crit,critical,alert,emerg,panic"),
+      warn_words: words("warn,warning"),
+      placement,
+      next_poll: Instant::now(),
+      last_err: None,
+    }
+  }
+  pub fn from_section(section: &Section) -> Self {
+    let mut tail = Tail::new(&section.get_str("path", "/var/log/syslog"), section.get_or("lines", 8));
+    if let Some(filter) = section.get("filter") {
+      match Regex::new(filter) {
+        Ok(re) => tail.filter = Some(re),
+        Err(e) => println!("Config: [tail {}] bad filter: {}", section.name, e),
+      }
+    }
+    let mut out = Self::new(&section.name, tail, Placement::from_section(section));
+    out.interval = Duration::from_secs_f32(section.get_or("interval", 1.0_f32).max(0.1));
+    out.max_chars = section.get_or("chars", 120);
+    if let Some(list) = section.get("error_words") { out.error_words = words(list); }
+    if let Some(list) = section.get("warn_words") { out.warn_words = words(list); }
+    out
+  }
+  pub fn colour_of(&self, line: &str) -> (f32, f32, f32) {
+    let lower = line.to_lowercase();
+    let has = |list: &[String]| lower
+      .split(|c: char| !c.is_alphanumeric())
+      .any(|word| list.iter().any(|w| w == word));
+    if has(&self.error_words) { COLOUR_ERROR }
+    else if has(&self.warn_words) { COLOUR_WARN }
+    else { COLOUR_NORMAL }
+  }
+  fn rows_update(&self) -> WidgetUpdate {
+    let rows = self.tail.lines.iter().map(|line| TextRow {
+      text: line.replace('\t', "    ").chars().take(self.max_chars).collect(),
+      colour: self.colour_of(line),
+    }).collect();
+    WidgetUpdate { rows }
+  }
+}
+impl Widget for TailWidget {
+  fn label(&self) -> &str { &self.label }
+  fn placement(&self) -> &Placement { &self.placement }
+  fn rows(&self) -> usize { self.tail.max_lines.max(1) }
+  fn poll(&mut self) -> Option<WidgetUpdate> {
+    if Instant::now() < self.next_poll { return None }
+    self.next_poll = Instant::now() + self.interval;
+    match self.tail.poll() {
+      Ok(changed) => {
+        let recovered = self.last_err.take().is_some();
+        if changed || recovered { Some(self.rows_update()) } else { None }
+      }
+      Err(e) => {
+        if self.last_err.as_ref() == Some(&e) { return None }
+        self.last_err = Some(e.clone());
+        Some(WidgetUpdate::single(e, COLOUR_ERROR))
+      }
+    }
+  }
+}
+
+fn words(list: &str) -> Vec<String> {
+  list.split(',').map(|w| w.trim().to_lowercase()).filter(|w| !w.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    std::io::Write,
+    crate::util::temp_dir,
+  };
+
+  fn append(path: &PathBuf, text: &str) {
+    fs::OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(text.as_bytes()).unwrap();
+  }
+
+  #[test]
+  fn filtered_line_after_a_long_backlog_is_kept() {
+    let dir = temp_dir("tail-backlog");
+    let path = dir.join("log");
+    append(&path, &"noise noise noise\n".repeat(BACKLOG_BYTES as usize / 8));
+    let mut tail = Tail::new(path.to_str().unwrap(), 5);
+    tail.filter = Some(Regex::new("sshd").unwrap());
+    assert_eq!(tail.poll(), Ok(false));
+    append(&path, "more noise\n");
+    assert_eq!(tail.poll(), Ok(false));
+    append(&path, "sshd: accepted\n");
+    assert_eq!(tail.poll(), Ok(true));
+    assert_eq!(tail.lines, vec!["sshd: accepted"]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn cut_off_first_line_is_dropped() {
+    let dir = temp_dir("tail-cut");
+    let path = dir.join("log");
+    append(&path, &format!("{}\nwhole\n", "x".repeat(BACKLOG_BYTES as usize)));
+    let mut tail = Tail::new(path.to_str().unwrap(), 5);
+    assert_eq!(tail.poll(), Ok(true));
+    assert_eq!(tail.lines, vec!["whole"]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn rotation_keeps_the_rest_of_the_old_file() {
+    let dir = temp_dir("tail-rotate");
+    let path = dir.join("log");
+    append(&path, "one\n");
+    let mut tail = Tail::new(path.to_str().unwrap(), 10);
+    tail.poll().unwrap();
+    append(&path, "two\nthree");
+    fs::rename(&path, dir.join("log.1")).unwrap();
+    append(&path, "four\n");
+    assert_eq!(tail.poll(), Ok(true));
+    assert_eq!(tail.lines, vec!["one", "two", "three", "four"]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn truncation_starts_over() {
+    let dir = temp_dir("tail-truncate");
+    let path = dir.join("log");
+    append(&path, "one\ntwo\n");
+    let mut tail = Tail::new(path.to_str().unwrap(), 10);
+    tail.poll().unwrap();
+    fs::write(&path, "new\n").unwrap();
+    assert_eq!(tail.poll(), Ok(true));
+    assert_eq!(tail.lines, vec!["one", "two", "new"]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}